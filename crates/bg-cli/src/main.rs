@@ -1,17 +1,23 @@
 mod utils;
 
 use log::{error, info, trace};
+#[allow(clippy::single_component_path_imports)]
 use env_logger;
 use clap::{Parser, Subcommand};
 use std::env;
 use std::path::PathBuf;
+use std::time::Duration;
 use std::process::exit;
-use bg_core::{backend, media, orchestrator, wl};
-use bg_core::backend::WallpaperMode;
-use bg_core::media::{MediaKind, ScanConfig};
-use utils::constants::{ListTarget, ANIMATED_MEDIA, BACKEND, HELP, OUTPUT, SEAT, STATIC_MEDIA};
+use bg_core::{backend, ipc, media, wl};
+use bg_core::ipc::Command;
+use bg_core::backend::{BackendDescriptor, CommandTemplateConfig, DEFAULT_STOP_GRACE};
+use bg_core::layout::{Layout, SpanConfig};
+use bg_core::media::{MediaEntry, MediaKind, ScanConfig};
+use bg_core::Error;
+use bg_core::orchestrator::{LastSession, RotationConfig, RotationScope, Session};
+use utils::constants::{AspectArg, AssignArg, ListFormat, ListTarget, ModeArg, TransitionArg, ANIMATED_MEDIA, BACKEND, HELP, OUTPUT, SEAT, STATIC_MEDIA};
 use crate::utils::constants::ALL_MEDIA;
-use crate::utils::{expand_media_path, wait_for_shutdown_signal};
+use crate::utils::{custom_backends, fail, kill_strays, take_lock, load_config, parse_color, parse_minutes, parse_seconds, list_backends, list_media, list_outputs, list_plans, list_seats, run_foreground, send_command, shutdown_signal, wallpaper_mode};
 
 #[derive(Parser, Debug, Clone)]
 #[command(name="bg-settings", version = "0.1", about = "A wallpaper orchestrator for wayland")]
@@ -26,10 +32,30 @@ pub(crate) struct Cli {
     #[clap(default_value_t = false)]
     recursive: bool,
 
+    #[clap(long, conflicts_with = "recursive")]
+    #[clap(help = "Only scan <MEDIA_PATH> itself, even if the config file sets `recursive = true`")]
+    no_recursive: bool,
+
     #[clap(short, long)]
     #[clap(help = "Max recursion depth, where -1 means no limit (default=-1)")]
-    #[clap(default_value_t = -1)]
-    max_recurse_depth: i8,
+    max_recurse_depth: Option<i8>,
+
+    #[clap(long, value_name = "PATH")]
+    #[clap(help = "Config file to use instead of $XDG_CONFIG_HOME/bg-settings/config.toml")]
+    config: Option<PathBuf>,
+
+    #[clap(long = "command", value_name = "TEMPLATE")]
+    #[clap(help = "Custom backend command such as 'wbg {media}', named after its executable. \
+    Placeholders: {media} {output} {mode} {color} {width} {height} {scale}")]
+    custom_command: Option<String>,
+
+    #[clap(long)]
+    #[clap(help = "Stop the bg-settings instance running on this Wayland display and take over")]
+    replace: bool,
+
+    #[clap(long)]
+    #[clap(help = "Stop swaybg, hyprpaper, mpvpaper and awww processes on this display not started by bg-settings")]
+    kill_strays: bool,
 
     #[command(subcommand)]
     #[clap(help = "One of the supported subcommands.")]
@@ -39,12 +65,103 @@ pub(crate) struct Cli {
 #[derive(Subcommand, Debug, Clone)]
 pub(crate) enum Commands {
     List {
-        target: Option<ListTarget>
+        target: Option<ListTarget>,
+
+        #[clap(long, value_enum, default_value_t = ListFormat::Plain)]
+        #[clap(help = "Output format, json is meant for scripts")]
+        format: ListFormat,
     },
     Setup {
         #[clap(help="The desired outputs. Check the `name` field from `list output` subcommand.")]
-        outputs: Option<Vec<String>>
+        outputs: Option<Vec<String>>,
+
+        #[clap(long, value_enum)]
+        #[clap(help = "How the media is scaled on each output (default=fit)")]
+        mode: Option<ModeArg>,
+
+        #[clap(short, long, value_parser = parse_color)]
+        #[clap(help = "Background colour as #rrggbb, implies `--mode solid-color`")]
+        color: Option<String>,
+
+        #[clap(long, value_enum)]
+        #[clap(help = "Transition of the awww backend between two wallpapers (default=awww's own)")]
+        transition: Option<TransitionArg>,
+
+        #[clap(long, value_name = "SECONDS", value_parser = parse_seconds)]
+        #[clap(help = "How long the awww transition takes")]
+        transition_duration: Option<Duration>,
+
+        #[clap(long, value_name = "FPS")]
+        #[clap(help = "Frame rate of the awww transition")]
+        transition_fps: Option<u32>,
+
+        #[clap(long, value_name = "STEP")]
+        #[clap(help = "How much the awww transition advances per frame, 255 switches at once")]
+        transition_step: Option<u8>,
+
+        #[clap(short, long, value_name = "MINUTES", value_parser = parse_minutes)]
+        #[clap(help = "Show new media every MINUTES. Without the daemon, send SIGUSR1 to switch right away")]
+        interval: Option<Duration>,
+
+        #[clap(long, requires = "interval")]
+        #[clap(help = "Give every output its own rotation timer")]
+        per_output: bool,
+
+        #[clap(long)]
+        #[clap(help = "Cut one image across all outputs following their arrangement")]
+        span: bool,
+
+        #[clap(long, value_name = "PIXELS", requires = "span")]
+        #[clap(help = "Logical pixels hidden behind the bezels between two outputs (default=0)")]
+        bezel: Option<u32>,
+
+        #[clap(long, value_enum)]
+        #[clap(help = "How media is spread over the outputs (default=unique)")]
+        assign: Option<AssignArg>,
+
+        #[clap(long, value_enum)]
+        #[clap(help = "Pick media shaped like each output, `prefer` falls back to any media (default=off)")]
+        aspect: Option<AspectArg>,
+
+        #[clap(long, value_name = "RATIO")]
+        #[clap(help = "Largest relative difference between media and output aspect ratios (default=0.1)")]
+        aspect_tolerance: Option<f64>,
+
+        #[clap(long)]
+        #[clap(help = "Seed the random picks, the same seed and media give the same wallpapers. The seed in use is logged and shown by `status`")]
+        seed: Option<u64>,
+
+        #[clap(long, value_name = "COUNT")]
+        #[clap(help = "Media remembered per output, random picks show all media before repeating any, 0 disables it (default=100)")]
+        history: Option<usize>,
+
+        #[clap(long, value_name = "SECONDS")]
+        #[clap(help = "How long backends may take to exit on SIGTERM before they are killed (default=3)")]
+        stop_timeout: Option<u64>,
+
+        #[clap(long, value_name = "COUNT")]
+        #[clap(help = "Restart a crashed backend up to COUNT times a minute before giving up, 0 never restarts (default=5)")]
+        max_restarts: Option<u32>,
+
+        #[clap(long, value_enum, value_name = "FORMAT", num_args = 0..=1, require_equals = true, default_missing_value = "plain")]
+        #[clap(help = "Print the commands every backend would run and exit, backends need not be installed")]
+        dry_run: Option<ListFormat>,
+    },
+    #[clap(about = "Run in the background and accept commands on $XDG_RUNTIME_DIR/bg-settings-$WAYLAND_DISPLAY.sock")]
+    Daemon,
+    #[clap(about = "Ask the daemon to show new media")]
+    Next {
+        #[clap(help = "Only change these outputs.")]
+        outputs: Option<Vec<String>>,
     },
+    #[clap(about = "Show what the daemon is displaying")]
+    Status,
+    #[clap(about = "Stop the daemon and its backends")]
+    Stop,
+    #[clap(about = "Ask the daemon to rescan media and set up every output again")]
+    Reload,
+    #[clap(about = "Show the wallpapers of the last session again, new media only where they are gone")]
+    Restore,
 }
 
 #[tokio::main]
@@ -63,11 +180,20 @@ async fn main() {
     let args = Cli::parse();
     trace!("Got args: {:?}", args);
 
-    let mut thread_pool: Vec<tokio::process::Child> = Vec::new();
-
     match args.command {
         // Command: List
-        Some(Commands::List{target: opt_target }) => {
+        Some(Commands::List{target: opt_target, format }) => {
+            let scan_config = ScanConfig {
+                recurse: args.recursive,
+                max_recurses: args.max_recurse_depth.unwrap_or(-1).into(),
+            };
+            let list_media = |kind: MediaKind, context: &str| {
+                let paths = media::scan_media(args.media_path.clone(), kind, false, None, &mut scan_config.clone())
+                    .unwrap_or_else(|e| fail(context, e));
+                let entries: Vec<MediaEntry> = paths.iter().map(MediaEntry::of).collect();
+                print!("{}", list_media(&entries, format));
+            };
+
             match opt_target {
                 Some(target) => {
                     // check media path for those commands that requires it.
@@ -78,58 +204,42 @@ async fn main() {
 
                     match target {
                         t if t.is_in(&SEAT) => {
-                            let (_, seat_info) = wl::get_info();
-                            for info in seat_info {
-                                println!("{}", info)
-                            }
+                            let (_, seat_info) = wl::get_info()
+                                .unwrap_or_else(|e| fail("Failed to list seats", e));
+                            print!("{}", list_seats(&seat_info, format));
                         }
 
                         t if t.is_in(&OUTPUT) => {
-                            let (output_info, _) = wl::get_info();
-                            for info in output_info {
-                                println!("{}", info)
-                            }
+                            let (output_info, _) = wl::get_info()
+                                .unwrap_or_else(|e| fail("Failed to list outputs", e));
+                            print!("{}", list_outputs(&output_info, format));
                         }
 
                         t if t.is_in(&ALL_MEDIA) => {
-                            if let Err(e) = media::list_media(
-                                args.media_path.clone(),
-                                MediaKind::Any,
-                                args.recursive,
-                                args.max_recurse_depth,
-                            ) {
-                                error!("Failed to list all media: {}", e);
-                            }
+                            list_media(MediaKind::Any, "Failed to list all media");
                         }
 
                         t if t.is_in(&STATIC_MEDIA) => {
-                            if let Err(e) = media::list_media(
-                                args.media_path.clone(),
-                                MediaKind::StaticImage,
-                                args.recursive,
-                                args.max_recurse_depth,
-                            ) {
-                                error!("Failed to list static media: {}", e);
-                            }
+                            list_media(MediaKind::StaticImage, "Failed to list static media");
                         }
 
                         t if t.is_in(&ANIMATED_MEDIA) => {
-                            if let Err(e) = media::list_media(
-                                args.media_path.clone(),
-                                MediaKind::AnimatedImage,
-                                args.recursive,
-                                args.max_recurse_depth,
-                            ) {
-                                error!("Failed to list animated media: {}", e);
-                            }
+                            list_media(MediaKind::AnimatedImage, "Failed to list animated media");
                         }
 
                         t if t.is_in(&BACKEND) => {
-                            let backends = backend::available_backends();
-                            print!("Detected backends: ");
-                            for backend in backends {
-                                print!("{} ", backend.name())
+                            let mut custom = custom_backends(args.custom_command.clone());
+                            for entry in load_config(args.config.clone()).custom_backends {
+                                match entry.into_backend() {
+                                    Ok(b) => custom.push(Box::new(b)),
+                                    Err(e) => error!("Invalid custom backend: {}", e),
+                                }
                             }
+                            let backends: Vec<BackendDescriptor> = backend::available_backends_with(custom)
+                                .iter()
+                                .map(|b| BackendDescriptor::of(b.as_ref()))
+                                .collect();
+                            print!("{}", list_backends(&backends, format));
                         }
 
                         t if t.is_in(&HELP) => {
@@ -148,53 +258,180 @@ async fn main() {
         // default: Fill all outputs with random pictures if provided media_path, else noop.
         Some(Commands::Setup{
                  outputs: target_output,
+                 mode,
+                 color,
+                 transition,
+                 transition_duration,
+                 transition_fps,
+                 transition_step,
+                 interval,
+                 per_output,
+                 span,
+                 bezel,
+                 assign,
+                 aspect,
+                 aspect_tolerance,
+                 seed,
+                 history,
+                 stop_timeout,
+                 max_restarts,
+                 dry_run,
              }) => {
-            
-            let media_path = expand_media_path(
-                args.media_path.clone(),
-                ScanConfig {
-                    recurse: args.recursive,
-                    max_recurses: args.max_recurse_depth.into(),
+            // Flags given on the command line win over the config file.
+            let mut config = load_config(args.config.clone());
+            if let Some(media_path) = args.media_path.clone() {
+                config.media_paths = vec![media_path];
+            }
+            if args.backend.is_some() {
+                config.backend = args.backend.clone();
+            }
+            if args.recursive {
+                config.scan.recurse = true;
+            }
+            if args.no_recursive {
+                config.scan.recurse = false;
+            }
+            if let Some(depth) = args.max_recurse_depth {
+                config.scan.max_recurses = depth.into();
+            }
+            if target_output.is_some() {
+                config.outputs = target_output;
+            }
+            if let Some(mode) = wallpaper_mode(mode, color) {
+                config.mode = mode;
+            }
+            if let Some(command) = args.custom_command.clone() {
+                config.custom_backends.insert(0, CommandTemplateConfig {
+                    name: None,
+                    command,
+                    executable: None,
+                    capabilities: None,
+                });
+            }
+            if let Some(transition) = transition {
+                config.awww.kind = Some(transition.into());
+            }
+            if transition_duration.is_some() {
+                config.awww.duration = transition_duration;
+            }
+            if transition_fps.is_some() {
+                config.awww.fps = transition_fps;
+            }
+            if transition_step.is_some() {
+                config.awww.step = transition_step;
+            }
+            if span || bezel.is_some() {
+                let mut span_config = match config.layout {
+                    Layout::Span(span_config) => span_config,
+                    Layout::Independent => SpanConfig::default(),
+                };
+                if let Some(bezel) = bezel {
+                    span_config.bezel = bezel;
                 }
-            );
-
-            match orchestrator::setup_wallpaper(
-                media_path,
-                args.backend,
-                target_output,
-                WallpaperMode::Fit,
-            ).await { // if setup, put into thread_pool and wait for shutdown signal.
-                Ok(children) => {
-                    thread_pool.extend(children);
-                    info!("Main function reaching end");
+                config.layout = Layout::Span(span_config);
+            }
+            if let Some(strategy) = assign {
+                config.assign.strategy = strategy.into();
+            }
+            if let Some(aspect) = aspect {
+                config.assign.aspect = aspect.into();
+            }
+            if let Some(tolerance) = aspect_tolerance {
+                config.assign.tolerance = tolerance;
+            }
+            if let Some(history) = history {
+                config.assign.history = history;
+            }
+            if let Some(seconds) = stop_timeout {
+                config.stop_grace = Duration::from_secs(seconds);
+            }
+            if let Some(max_restarts) = max_restarts {
+                config.supervise.max_restarts = max_restarts;
+            }
+            if seed.is_some() {
+                config.seed = seed;
+            }
+            if let Some(interval) = interval {
+                let rotation = config.rotation.get_or_insert_with(RotationConfig::default);
+                rotation.interval = Some(interval);
+                if per_output {
+                    rotation.scope = RotationScope::PerOutput;
+                }
+            }
 
-                    wait_for_shutdown_signal(
-                        || async move {
-                            let mut ok = true;
-                            info!("Received kill signal, exiting.");
+            if let Some(format) = dry_run {
+                match Session::dry_run(config) {
+                    Ok(plans) => print!("{}", list_plans(&plans, format)),
+                    Err(e) => fail("Dry run failed", e),
+                }
+                return;
+            }
 
-                            for mut child in thread_pool {
-                                if let Some(pid) = child.id() {
-                                    info!("Killing child thread: {}", pid);
-                                }
+            // Hand the session to a running daemon, otherwise keep it in the foreground.
+            if !args.replace && ipc::is_running().await {
+                if args.kill_strays {
+                    kill_strays(config.stop_grace).await;
+                }
+                info!("Handing setup to the running daemon.");
+                // The daemon runs in its own working directory.
+                if let Err(e) = config.canonicalize_media() {
+                    fail("Invalid media path", e);
+                }
+                exit(send_command(Command::Set(Box::new(config))).await);
+            }
 
-                                if child.kill().await.is_err() {
-                                    ok = false;
-                                }
-                            }
+            // One orchestrator per display, held until exit.
+            let _lock = take_lock(args.replace).await;
+            if args.kill_strays {
+                kill_strays(config.stop_grace).await;
+            }
 
-                            if ok { 0 } else {
-                                error!("Failed terminating all child processes.");
-                                1
-                            }
-                        }
-                    ).await
+            match Session::start(config).await { // if setup, keep the session going until shutdown signal.
+                Ok(session) => {
+                    info!("Main function reaching end");
+                    exit(run_foreground(session).await);
                 }
                 
-                Err(e) => {
-                    error!("Setup failed: {}", e);
-                    exit(1);
+                Err(e) => fail("Setup failed", e),
+            }
+        }
+        Some(Commands::Daemon) => {
+            let _lock = take_lock(args.replace).await;
+            if args.kill_strays {
+                kill_strays(DEFAULT_STOP_GRACE).await;
+            }
+            let shutdown = shutdown_signal().unwrap_or_else(|e| fail("Daemon failed", e));
+            if let Err(e) = ipc::serve(&ipc::socket_path(), shutdown).await {
+                fail("Daemon failed", e);
+            }
+        }
+        Some(Commands::Next { outputs }) => exit(send_command(Command::Next { outputs }).await),
+        Some(Commands::Status) => exit(send_command(Command::Status).await),
+        Some(Commands::Stop) => exit(send_command(Command::Stop).await),
+        Some(Commands::Reload) => exit(send_command(Command::Reload).await),
+        Some(Commands::Restore) => {
+            let last = match LastSession::load_default() {
+                Ok(Some(last)) => last,
+                Ok(None) => fail("Restore failed", Error::NoLastSession),
+                Err(e) => fail("Restore failed", e),
+            };
+
+            if !args.replace && ipc::is_running().await {
+                if args.kill_strays {
+                    kill_strays(last.config.stop_grace).await;
                 }
+                info!("Handing restore to the running daemon.");
+                exit(send_command(Command::Restore(Box::new(last))).await);
+            }
+
+            let _lock = take_lock(args.replace).await;
+            if args.kill_strays {
+                kill_strays(last.config.stop_grace).await;
+            }
+
+            match Session::restore(last).await {
+                Ok(session) => exit(run_foreground(session).await),
+                Err(e) => fail("Restore failed", e),
             }
         }
         None => {
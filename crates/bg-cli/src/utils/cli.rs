@@ -1,45 +1,267 @@
+use std::future::Future;
 use std::path::PathBuf;
 use std::process::exit;
-use log::error;
-use bg_core::media::{scan_media, MediaKind, ScanConfig};
+use std::time::Duration;
+use log::{debug, error, info};
+use tokio::signal::unix::{signal, SignalKind};
+use bg_core::config::{self, Config};
+use bg_core::{Error, ErrorKind};
+use bg_core::backend::{CommandTemplateBackend, WallpaperBackend, WallpaperMode};
+use bg_core::instance::{find_strays, lock_holder, terminate_strays, InstanceLock};
+use bg_core::ipc::{self, Command, Reply};
+use bg_core::orchestrator::{ProcessState, Session, SessionConfig};
+use crate::utils::constants::ModeArg;
 
-pub async fn wait_for_shutdown_signal<F, Fut>(on_exit: F)
-where F: FnOnce() -> Fut,
-    Fut: std::future::Future<Output = i32>, {
-    #[cfg(unix)]
-    {
-        use tokio::signal::unix::{signal, SignalKind};
-
-        let mut sigterm = signal(SignalKind::terminate()).unwrap();
-        let mut sigint  = signal(SignalKind::interrupt()).unwrap();
-        let mut sighup  = signal(SignalKind::hangup()).unwrap();
+/// Listen for SIGTERM, SIGINT and SIGHUP, the returned future resolves on the first of them.
+pub fn shutdown_signal() -> Result<impl Future<Output = ()>, Error> {
+    let mut sigterm = signal(SignalKind::terminate()).map_err(Error::Signal)?;
+    let mut sigint  = signal(SignalKind::interrupt()).map_err(Error::Signal)?;
+    let mut sighup  = signal(SignalKind::hangup()).map_err(Error::Signal)?;
 
+    Ok(async move {
         tokio::select! {
             _ = sigterm.recv() => {}
             _ = sigint.recv() => {}
             _ = sighup.recv() => {}
         }
+    })
+}
+
+/// Keep the session going without the daemon: follow hotplug and rotation,
+/// show new media on SIGUSR1 and stop on shutdown. Returns the exit code.
+pub async fn run_foreground(mut session: Session) -> i32 {
+    let signals = signal(SignalKind::user_defined1())
+        .map_err(Error::Signal)
+        .and_then(|sigusr1| Ok((sigusr1, shutdown_signal()?)));
+    let (mut sigusr1, shutdown) = match signals {
+        Ok(signals) => signals,
+        Err(e) => {
+            error!("{}", e);
+            if let Err(e) = session.stop().await {
+                error!("{}", e);
+            }
+            return exit_code(&e);
+        }
+    };
+    tokio::pin!(shutdown);
+
+    loop {
+        tokio::select! {
+            _ = &mut shutdown => break,
+            _ = sigusr1.recv() => {
+                info!("Received SIGUSR1, showing new media.");
+                if let Err(e) = session.next(None).await {
+                    error!("{}", e);
+                }
+            }
+            event = session.event() => {
+                if let Err(e) = session.handle(event).await {
+                    error!("{}", e);
+                }
+            }
+        }
+    }
+
+    info!("Received kill signal, exiting.");
+    match session.stop().await {
+        Ok(_) => 0,
+        Err(e) => {
+            error!("{}", e);
+            exit_code(&e)
+        }
+    }
+}
+
+/// Exit code for each kind of failure, documented in README.md.
+/// 1 is left for generic failures, 2 is used by clap.
+pub fn exit_code(e: &Error) -> i32 {
+    kind_exit_code(e.kind())
+}
 
-        exit(on_exit().await)
+/// Exit code for a failure of this kind, also used for errors reported by the daemon.
+pub fn kind_exit_code(kind: ErrorKind) -> i32 {
+    match kind {
+        ErrorKind::Wayland => 3,
+        ErrorKind::NoBackend => 4,
+        ErrorKind::BackendNotFound => 5,
+        ErrorKind::Outputs => 6,
+        ErrorKind::Media => 7,
+        ErrorKind::Backend => 8,
+        ErrorKind::Scan => 9,
+        ErrorKind::Config => 10,
+        ErrorKind::Session => 11,
+        ErrorKind::Storage => 12,
+        ErrorKind::Instance => 13,
+        ErrorKind::Signal => 14,
     }
 }
 
-pub fn expand_media_path (media_path: Option<PathBuf>, mut scan_config: ScanConfig) -> Vec<PathBuf> {
-    if let Some(path) = media_path {
-        scan_media(
-            Some(path),
-            MediaKind::StaticImage,
-            false,
-            None,
-            &mut scan_config
-        ).unwrap_or_else(
-            |e| {
-                error!("Error scanning media: {}", e);
-                exit(1);
+/// Log `e` and exit with its code.
+pub fn fail(context: &str, e: Error) -> ! {
+    error!("{}: {}", context, e);
+    exit(exit_code(&e));
+}
+
+/// Send a command to the daemon and print its reply. Returns the exit code.
+pub async fn send_command(command: Command) -> i32 {
+    match ipc::send(command).await {
+        Ok(Reply::Ok) => 0,
+        Ok(Reply::Status { session: Some(status) }) => {
+            for output in status.outputs {
+                let media = match &output.mode {
+                    WallpaperMode::SolidColor(color) => color.clone(),
+                    _ => output.media.display().to_string(),
+                };
+                let state = match output.state {
+                    ProcessState::Running => "",
+                    ProcessState::Restarting => " restarting",
+                    ProcessState::GaveUp => " crashed, gave up",
+                };
+                match output.pid {
+                    Some(pid) => println!("{}: {} [{}, {}] (pid {}){}", output.output, media, output.backend, output.mode, pid, state),
+                    None => println!("{}: {} [{}, {}]{}", output.output, media, output.backend, output.mode, state),
+                }
             }
-        )
-    } else {
-        error!("Missing media_path, stop executing.");
-        exit(1);
+            println!("seed: {}", status.seed);
+            0
+        }
+        Ok(Reply::Status { session: None }) => {
+            println!("No wallpaper session running");
+            0
+        }
+        Ok(Reply::Error { kind, message }) => {
+            error!("Daemon error: {}", message);
+            kind_exit_code(kind)
+        }
+        Err(e) => {
+            error!("Cannot reach daemon at {}: {}. Is `bg-cli daemon` running?", ipc::socket_path().display(), e);
+            exit_code(&e)
+        }
+    }
+}
+
+/// Check `--color` is `#rrggbb` for clap.
+pub fn parse_color(value: &str) -> Result<String, String> {
+    WallpaperMode::solid_color(value).map(|_| value.to_string()).map_err(|e| e.to_string())
+}
+
+/// `--color` alone implies `solid-color`, other modes take no colour.
+/// `None` keeps the mode from the config file.
+pub fn wallpaper_mode(mode: Option<ModeArg>, color: Option<String>) -> Option<WallpaperMode> {
+    match (mode, color) {
+        (Some(mode), Some(_)) if mode != ModeArg::SolidColor => {
+            error!("--color only works with --mode solid-color, stop executing.");
+            exit(1);
+        }
+        (Some(ModeArg::Stretch), _) => Some(WallpaperMode::Stretch),
+        (Some(ModeArg::Fit), _) => Some(WallpaperMode::Fit),
+        (Some(ModeArg::Fill), _) => Some(WallpaperMode::Fill),
+        (Some(ModeArg::Center), _) => Some(WallpaperMode::Center),
+        (Some(ModeArg::Tile), _) => Some(WallpaperMode::Tile),
+        (Some(ModeArg::SolidColor) | None, Some(color)) => Some(WallpaperMode::SolidColor(color)),
+        (Some(ModeArg::SolidColor), None) => {
+            error!("Mode solid-color requires --color, stop executing.");
+            exit(1);
+        }
+        (None, None) => None,
+    }
+}
+
+/// Parse `--interval` minutes for clap.
+pub fn parse_minutes(value: &str) -> Result<Duration, String> {
+    let minutes: u64 = value.parse().map_err(|e| format!("{}", e))?;
+    config::minutes(minutes).map_err(|e| e.to_string())
+}
+
+/// Read `path`, or the default config file if it exists.
+pub fn load_config(path: Option<PathBuf>) -> SessionConfig {
+    let config = match path {
+        Some(path) => Config::load(&path).map(Some),
+        None => Config::load_default(),
+    };
+    match config.and_then(|c| c.unwrap_or_default().into_session_config()) {
+        Ok(config) => config,
+        Err(e) => fail("Invalid config", e),
+    }
+}
+
+/// Parse fractional seconds such as `1.5` for clap.
+pub fn parse_seconds(value: &str) -> Result<Duration, String> {
+    let seconds: f32 = value.parse().map_err(|e| format!("{}", e))?;
+    Duration::try_from_secs_f32(seconds).map_err(|e| format!("{}", e))
+}
+
+/// Take the lock of this Wayland display, asking the instance holding it to exit with `replace`.
+pub async fn take_lock(replace: bool) -> InstanceLock {
+    let lock = match replace {
+        true => InstanceLock::replace().await,
+        false => InstanceLock::acquire(),
+    };
+    match lock {
+        Ok(lock) => lock,
+        Err(e @ Error::AlreadyRunning { .. }) => fail("Pass --replace to take over", e),
+        Err(e) => fail("Cannot lock the display", e),
+    }
+}
+
+/// Stop wallpaper processes on this display that bg-settings did not start.
+pub async fn kill_strays(grace: Duration) {
+    let mut parents = vec![std::process::id()];
+    parents.extend(lock_holder());
+    let strays = find_strays(&parents);
+    if strays.is_empty() {
+        debug!("No stray wallpaper processes");
+        return;
+    }
+    let stopped = terminate_strays(strays, grace).await;
+    info!("Stopped {} stray wallpaper processes", stopped.len());
+}
+
+pub fn custom_backends(template: Option<String>) -> Vec<Box<dyn WallpaperBackend>> {
+    let Some(command) = template else {
+        return Vec::new();
+    };
+    match CommandTemplateBackend::from_template(&command) {
+        Ok(backend) => vec![Box::new(backend)],
+        Err(source) => fail("Invalid custom command", Error::InvalidCommand { command, source }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bg_core::ipc::Response;
+
+    #[test]
+    fn interval_minutes_must_fit() {
+        assert_eq!(parse_minutes("30"), Ok(Duration::from_secs(1800)));
+        assert!(parse_minutes(&u64::MAX.to_string()).unwrap_err().contains("too long"));
+    }
+
+    #[test]
+    fn errors_map_to_exit_codes() {
+        let io = || std::io::Error::other("boom");
+        assert_eq!(exit_code(&Error::NoBackendAvailable), 4);
+        assert_eq!(exit_code(&Error::BackendNotFound("wbg".to_string())), 5);
+        assert_eq!(exit_code(&Error::NoOutputsMatched), 6);
+        assert_eq!(exit_code(&Error::NoMediaPath), 7);
+        assert_eq!(exit_code(&Error::SpawnFailed { backend: "swaybg".to_string(), source: io() }), 8);
+        assert_eq!(exit_code(&Error::ScanIo { path: PathBuf::new(), source: io() }), 9);
+        assert_eq!(exit_code(&Error::Config("bad".to_string())), 10);
+        assert_eq!(exit_code(&Error::NoSession), 11);
+        assert_eq!(exit_code(&Error::AlreadyRunning { pid: None }), 13);
+        assert_eq!(exit_code(&Error::Signal(io())), 14);
+        assert_eq!(exit_code(&Error::Watcher(io())), 3);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn daemon_errors_keep_their_exit_code() {
+        let e = Error::BackendNotFound("wbg".to_string());
+        let json = serde_json::to_string(&Response::new(Reply::Error { kind: e.kind(), message: e.to_string() })).unwrap();
+        let response: Response = serde_json::from_str(&json).unwrap();
+        match response.reply {
+            Reply::Error { kind, .. } => assert_eq!(kind_exit_code(kind), exit_code(&e)),
+            reply => panic!("unexpected reply {:?}", reply),
+        }
+    }
+}
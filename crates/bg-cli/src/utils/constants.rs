@@ -1,3 +1,5 @@
+use bg_core::backend::AwwwTransition;
+use bg_core::orchestrator::{AspectMatch, AssignStrategy};
 use clap::ValueEnum;
 
 #[derive(ValueEnum, Debug, Clone)]
@@ -27,6 +29,17 @@ pub enum ListTarget {
     Videos
 }
 
+#[derive(ValueEnum, Debug, Clone, PartialEq)]
+pub enum ModeArg {
+    Stretch,
+    Fit,
+    Fill,
+    Center,
+    Tile,
+    #[value(alias = "solid_color")]
+    SolidColor,
+}
+
 pub const SEAT: [ListTarget; 2] = [
     ListTarget::Seat, ListTarget::Seats];
 
@@ -64,3 +77,94 @@ impl ListTarget {
         group.contains(self)
     }
 }
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum TransitionArg {
+    None,
+    Simple,
+    Fade,
+    Left,
+    Right,
+    Top,
+    Bottom,
+    Wipe,
+    Wave,
+    Grow,
+    Center,
+    Any,
+    Outer,
+    Random,
+}
+
+impl From<TransitionArg> for AwwwTransition {
+    fn from(arg: TransitionArg) -> Self {
+        match arg {
+            TransitionArg::None => AwwwTransition::None,
+            TransitionArg::Simple => AwwwTransition::Simple,
+            TransitionArg::Fade => AwwwTransition::Fade,
+            TransitionArg::Left => AwwwTransition::Left,
+            TransitionArg::Right => AwwwTransition::Right,
+            TransitionArg::Top => AwwwTransition::Top,
+            TransitionArg::Bottom => AwwwTransition::Bottom,
+            TransitionArg::Wipe => AwwwTransition::Wipe,
+            TransitionArg::Wave => AwwwTransition::Wave,
+            TransitionArg::Grow => AwwwTransition::Grow,
+            TransitionArg::Center => AwwwTransition::Center,
+            TransitionArg::Any => AwwwTransition::Any,
+            TransitionArg::Outer => AwwwTransition::Outer,
+            TransitionArg::Random => AwwwTransition::Random,
+        }
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Default)]
+pub enum ListFormat {
+    /// Stable, machine-readable output.
+    Json,
+    /// Aligned columns with a header.
+    Table,
+    /// Human-readable, one entry per block or line.
+    #[default]
+    Plain,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum AspectArg {
+    Off,
+    Prefer,
+    Require,
+}
+
+impl From<AspectArg> for AspectMatch {
+    fn from(arg: AspectArg) -> Self {
+        match arg {
+            AspectArg::Off => AspectMatch::Off,
+            AspectArg::Prefer => AspectMatch::Prefer,
+            AspectArg::Require => AspectMatch::Require,
+        }
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum AssignArg {
+    /// Different media on every output, fails if there is not enough
+    Unique,
+    /// The same media on all outputs
+    Same,
+    /// Random media on every output, repeats allowed
+    #[value(alias = "with_replacement")]
+    WithReplacement,
+    /// Walk through the media in path order
+    Cycle,
+}
+
+impl From<AssignArg> for AssignStrategy {
+    fn from(arg: AssignArg) -> Self {
+        match arg {
+            AssignArg::Unique => AssignStrategy::Unique,
+            AssignArg::Same => AssignStrategy::Same,
+            AssignArg::WithReplacement => AssignStrategy::WithReplacement,
+            AssignArg::Cycle => AssignStrategy::Cycle,
+        }
+    }
+}
@@ -0,0 +1,188 @@
+use serde::Serialize;
+use bg_core::backend::BackendDescriptor;
+use bg_core::media::MediaEntry;
+use bg_core::orchestrator::BackendPlan;
+use bg_core::wl::{self, OutputInfo, SeatInfo};
+use crate::utils::constants::ListFormat;
+
+pub fn list_outputs(outputs: &[OutputInfo], format: ListFormat) -> String {
+    match format {
+        ListFormat::Json => json(outputs),
+        ListFormat::Table => table(
+            &["NAME", "MAKE", "MODEL", "MODE", "POSITION", "SIZE", "SCALE", "TRANSFORM"],
+            outputs.iter().map(|o| {
+                let mode = o.current_mode()
+                    .map(|m| format!("{}x{}@{:.2}", m.width(), m.height(), m.refresh() as f64 / 1000.0))
+                    .unwrap_or_default();
+                let (x, y) = o.position();
+                let size = o.logical_size()
+                    .map(|(width, height)| format!("{}x{}", width, height))
+                    .unwrap_or_default();
+                vec![
+                    o.name.clone(),
+                    o.make().to_string(),
+                    o.model().to_string(),
+                    mode,
+                    format!("{},{}", x, y),
+                    size,
+                    o.fractional_scale().to_string(),
+                    wl::transform_name(o.transform()).to_string(),
+                ]
+            }).collect(),
+        ),
+        ListFormat::Plain => outputs.iter().map(|output| format!("{}\n", output)).collect(),
+    }
+}
+
+pub fn list_seats(seats: &[SeatInfo], format: ListFormat) -> String {
+    match format {
+        ListFormat::Json => json(seats),
+        ListFormat::Table => table(
+            &["ID", "NAME", "CAPABILITIES"],
+            seats.iter().map(|s| vec![
+                s.id().to_string(),
+                s.name().to_string(),
+                s.capabilities().join(","),
+            ]).collect(),
+        ),
+        ListFormat::Plain => seats.iter().map(|seat| format!("{}\n", seat)).collect(),
+    }
+}
+
+pub fn list_backends(backends: &[BackendDescriptor], format: ListFormat) -> String {
+    match format {
+        ListFormat::Json => json(backends),
+        ListFormat::Table => table(
+            &["NAME", "CAPABILITIES", "PATH", "VERSION"],
+            backends.iter().map(|b| vec![
+                b.name.clone(),
+                b.capabilities.iter().map(|c| format!("{:?}", c)).collect::<Vec<_>>().join(","),
+                b.path.as_ref().map(|p| p.display().to_string()).unwrap_or_default(),
+                b.version.clone().unwrap_or_default(),
+            ]).collect(),
+        ),
+        ListFormat::Plain => backends.iter().map(|backend| format!("{}\n", backend.name)).collect(),
+    }
+}
+
+pub fn list_media(media: &[MediaEntry], format: ListFormat) -> String {
+    match format {
+        ListFormat::Json => json(media),
+        ListFormat::Table => table(
+            &["PATH", "KIND", "SIZE", "DIMENSIONS"],
+            media.iter().map(|m| vec![
+                m.path.display().to_string(),
+                format!("{:?}", m.kind),
+                m.size.to_string(),
+                m.dimensions.map(|d| format!("{}x{}", d.width, d.height)).unwrap_or_default(),
+            ]).collect(),
+        ),
+        ListFormat::Plain => media.iter().map(|entry| format!("{}\n", entry.path.to_string_lossy())).collect(),
+    }
+}
+
+pub fn list_plans(plans: &[BackendPlan], format: ListFormat) -> String {
+    match format {
+        ListFormat::Json => json(plans),
+        ListFormat::Table => table(
+            &["BACKEND", "OUTPUTS", "COMMAND"],
+            plans.iter().flat_map(|p| p.commands.iter().map(|argv| vec![
+                p.backend.clone(),
+                p.outputs.join(","),
+                shell_words(argv),
+            ])).collect(),
+        ),
+        ListFormat::Plain => plans.iter().map(|plan| {
+            let commands: String = plan.commands.iter().map(|argv| format!("  {}\n", shell_words(argv))).collect();
+            format!("{} ({})\n{}", plan.backend, plan.outputs.join(", "), commands)
+        }).collect(),
+    }
+}
+
+/// Join `argv` so it can be pasted into a shell.
+fn shell_words(argv: &[String]) -> String {
+    argv.iter().map(|arg| {
+        let plain = !arg.is_empty() && arg.chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:,=+@%".contains(c));
+        match plain {
+            true => arg.clone(),
+            false => format!("'{}'", arg.replace('\'', "'\\''")),
+        }
+    }).collect::<Vec<_>>().join(" ")
+}
+
+fn json<T: Serialize + ?Sized>(value: &T) -> String {
+    match serde_json::to_string_pretty(value) {
+        Ok(json) => format!("{}\n", json),
+        Err(e) => {
+            log::error!("Failed to serialize: {}", e);
+            String::new()
+        }
+    }
+}
+
+/// Left aligned columns separated by two spaces.
+fn table(headers: &[&str], rows: Vec<Vec<String>>) -> String {
+    let mut widths: Vec<usize> = headers.iter().map(|h| h.chars().count()).collect();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let line = |cells: Vec<&str>| {
+        let line = cells.iter().zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join("  ");
+        format!("{}\n", line.trim_end())
+    };
+
+    let mut table = line(headers.to_vec());
+    for row in &rows {
+        table.push_str(&line(row.iter().map(String::as_str).collect()));
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn outputs() -> Vec<OutputInfo> {
+        vec![
+            OutputInfo::new("DP-1").with_make_model("Acme", "Panel").with_scale(2).with_mode(3840, 2160, 60000),
+            OutputInfo::new("HDMI-A-1").with_position(1920, 0).with_mode(1920, 1080, 59940),
+        ]
+    }
+
+    #[test]
+    fn outputs_as_json() {
+        let listed = list_outputs(&outputs(), ListFormat::Json);
+
+        let json: serde_json::Value = serde_json::from_str(&listed).unwrap();
+        let names: Vec<&str> = json.as_array().unwrap().iter().map(|o| o["name"].as_str().unwrap()).collect();
+        assert_eq!(names, ["DP-1", "HDMI-A-1"]);
+        assert_eq!(json[1]["x"], 1920);
+        assert_eq!(json[0]["output_transform"], "normal");
+    }
+
+    #[test]
+    fn outputs_as_table() {
+        assert_eq!(list_outputs(&outputs(), ListFormat::Table), [
+            "NAME      MAKE  MODEL  MODE             POSITION  SIZE       SCALE  TRANSFORM\n",
+            "DP-1      Acme  Panel  3840x2160@60.00  0,0       1920x1080  2      normal\n",
+            "HDMI-A-1               1920x1080@59.94  1920,0    1920x1080  1      normal\n",
+        ].concat());
+    }
+
+    #[test]
+    fn outputs_as_plain() {
+        let listed = list_outputs(&outputs(), ListFormat::Plain);
+
+        let blocks: Vec<&str> = listed.split("\n\n").filter(|block| !block.is_empty()).collect();
+        assert_eq!(blocks.len(), 2);
+        assert!(blocks[0].contains("name: DP-1\n"), "{}", blocks[0]);
+        assert!(blocks[1].contains("x: 1920, y: 0, scale: 1\n"), "{}", blocks[1]);
+    }
+}
@@ -1,4 +1,6 @@
 pub(crate) mod constants;
 mod cli;
+mod list;
 
 pub use cli::*;
+pub use list::*;
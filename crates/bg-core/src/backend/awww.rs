@@ -1 +1,296 @@
-pub struct AwwwBackend;
+use std::ffi::OsString;
+use std::fmt::Display;
+use std::io::{Error, ErrorKind};
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+
+use super::{BackendCapability, BackendSpawnSpec, HotReloadBackend, MultiOutputBackend, WallpaperBackend, WallpaperMode, command_version};
+
+use tokio::process::{Child, Command};
+use log::{info, error, debug};
+use serde::{Deserialize, Serialize};
+use which::which;
+
+const DAEMON_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Transition effects understood by `awww img --transition-type`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AwwwTransition {
+    None,
+    Simple,
+    Fade,
+    Left,
+    Right,
+    Top,
+    Bottom,
+    Wipe,
+    Wave,
+    Grow,
+    Center,
+    Any,
+    Outer,
+    Random,
+}
+
+impl Display for AwwwTransition {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            AwwwTransition::None => "none",
+            AwwwTransition::Simple => "simple",
+            AwwwTransition::Fade => "fade",
+            AwwwTransition::Left => "left",
+            AwwwTransition::Right => "right",
+            AwwwTransition::Top => "top",
+            AwwwTransition::Bottom => "bottom",
+            AwwwTransition::Wipe => "wipe",
+            AwwwTransition::Wave => "wave",
+            AwwwTransition::Grow => "grow",
+            AwwwTransition::Center => "center",
+            AwwwTransition::Any => "any",
+            AwwwTransition::Outer => "outer",
+            AwwwTransition::Random => "random",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Transition settings passed to every `awww img`. `None` keeps awww's default,
+/// which also honours the `AWWW_TRANSITION*` environment variables.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AwwwTransitionOptions {
+    pub kind: Option<AwwwTransition>,
+    pub duration: Option<Duration>,
+    pub fps: Option<u32>,
+    pub step: Option<u8>,
+}
+
+impl AwwwTransitionOptions {
+    pub fn to_args(&self) -> Vec<OsString> {
+        let mut args: Vec<OsString> = Vec::new();
+        if let Some(kind) = self.kind {
+            args.push("--transition-type".into());
+            args.push(kind.to_string().into());
+        }
+        if let Some(duration) = self.duration {
+            args.push("--transition-duration".into());
+            args.push(duration.as_secs_f32().to_string().into());
+        }
+        if let Some(fps) = self.fps {
+            args.push("--transition-fps".into());
+            args.push(fps.to_string().into());
+        }
+        if let Some(step) = self.step {
+            args.push("--transition-step".into());
+            args.push(step.to_string().into());
+        }
+        args
+    }
+}
+
+/// Runs `awww-daemon` once and pushes images to it with `awww img`.
+pub struct AwwwBackend {
+    pub transition: AwwwTransitionOptions,
+    /// How long to wait for a freshly spawned daemon to answer.
+    pub startup_timeout: Duration,
+}
+
+impl Default for AwwwBackend {
+    fn default() -> Self {
+        Self {
+            transition: AwwwTransitionOptions::default(),
+            startup_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+impl AwwwBackend {
+    pub fn with_transition(transition: AwwwTransitionOptions) -> Self {
+        Self {
+            transition,
+            ..Self::default()
+        }
+    }
+
+    /// `awww query` only succeeds when the daemon is up.
+    pub fn is_running(&self) -> bool {
+        super::block_on(self.query())
+    }
+
+    async fn query(&self) -> bool {
+        Command::new("awww")
+            .arg("query")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .await
+            .is_ok_and(|status| status.success())
+    }
+
+    /// Spawn the daemon unless one is already answering.
+    /// Returns the child only when we spawned it.
+    async fn ensure_daemon(&self) -> Result<Option<Child>, Error> {
+        if self.query().await {
+            info!("Reusing running awww-daemon.");
+            return Ok(None);
+        }
+
+        info!("Starting awww-daemon.");
+        let mut child = Command::new("awww-daemon").spawn()?;
+        match self.wait_for_daemon(&mut child).await {
+            Ok(()) => Ok(Some(child)),
+            Err(e) => {
+                super::kill_and_reap(&mut child).await;
+                Err(e)
+            }
+        }
+    }
+
+    async fn wait_for_daemon(&self, child: &mut Child) -> Result<(), Error> {
+        let deadline = Instant::now() + self.startup_timeout;
+        while !self.query().await {
+            if let Some(status) = child.try_wait()? {
+                return Err(Error::other(format!("awww-daemon exited early with {}", status)));
+            }
+            if Instant::now() >= deadline {
+                return Err(Error::new(ErrorKind::TimedOut, "Timed out waiting for awww-daemon"));
+            }
+            tokio::time::sleep(DAEMON_POLL_INTERVAL).await;
+        }
+        Ok(())
+    }
+
+    pub fn img_args(&self, spec: &BackendSpawnSpec) -> Vec<OsString> {
+        let mut args: Vec<OsString> = vec![
+            "img".into(),
+            "-o".into(),
+            spec.output.name.clone().into(),
+        ];
+
+        let resize = match spec.mode {
+            WallpaperMode::Fill => "crop",
+            WallpaperMode::Fit => "fit",
+            WallpaperMode::Stretch => "stretch",
+            WallpaperMode::Center => "no",
+            _ => {
+                debug!("awww has no equivalent for this mode, using crop.");
+                "crop"
+            }
+        };
+        args.push("--resize".into());
+        args.push(resize.into());
+
+        args.extend(self.transition.to_args());
+        args.extend(spec.extra_args.iter().cloned());
+        args.push(spec.media.clone().into_os_string());
+        args
+    }
+
+    async fn apply(&self, spec: &BackendSpawnSpec) -> Result<(), Error> {
+        let mut command = Command::new("awww");
+        command.args(self.img_args(spec));
+        debug!("Constructed command: {:?}", command);
+
+        let status = command.status().await?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(Error::other(format!("awww img exited with {}", status)))
+        }
+    }
+}
+
+impl WallpaperBackend for AwwwBackend {
+    fn name(&self) -> &str {
+        "awww"
+    }
+
+    fn start(&self, spec: &BackendSpawnSpec) -> Result<Option<Child>, Error> {
+        let mut children = self.start_multi_output(std::slice::from_ref(spec))?;
+        Ok(children.pop())
+    }
+
+    fn update(&self, spec: &BackendSpawnSpec) -> Result<(), Error> {
+        self.reload(spec)
+    }
+
+    fn version(&self) -> Option<String> {
+        command_version("awww", &["--version"])
+    }
+
+    fn exists(&self) -> bool {
+        debug!("Looking for executables `awww` and `awww-daemon`");
+        which("awww").is_ok() && which("awww-daemon").is_ok()
+    }
+
+    fn capabilities(&self) -> Vec<BackendCapability> {
+        vec![
+            BackendCapability::Static,
+            BackendCapability::Animated,
+            BackendCapability::MultiOutput,
+            BackendCapability::HotReload,
+        ]
+    }
+
+    fn start_multi(&self, specs: Vec<BackendSpawnSpec>) -> Result<Vec<Child>, Error> {
+        self.start_multi_output(&specs)
+    }
+
+    /// The daemon, unless it is already running, then one `awww img` per output.
+    fn planned_commands(&self, specs: &[BackendSpawnSpec]) -> Vec<Vec<OsString>> {
+        let daemon = (!self.is_running()).then(|| vec!["awww-daemon".into()]);
+        daemon.into_iter()
+            .chain(specs.iter().map(|spec| self.argv(spec)))
+            .collect()
+    }
+
+    fn argv(&self, spec: &BackendSpawnSpec) -> Vec<OsString> {
+        std::iter::once("awww".into()).chain(self.img_args(spec)).collect()
+    }
+}
+
+impl MultiOutputBackend for AwwwBackend {
+    fn start_multi_output(&self, specs: &[BackendSpawnSpec]) -> Result<Vec<Child>, Error> {
+        super::block_on(async {
+            let mut spawned = self.ensure_daemon().await?;
+
+            for spec in specs {
+                if let Err(e) = self.apply(spec).await {
+                    error!("Error setting awww wallpaper on {}: {}", spec.output.name, e);
+                    // Rollback, but leave a reused daemon alone.
+                    if let Some(c) = spawned.take() {
+                        let _ = super::stop_and_wait(self, c, super::DEFAULT_STOP_GRACE).await;
+                    }
+                    return Err(e);
+                }
+            }
+
+            Ok(spawned.into_iter().collect())
+        })
+    }
+}
+
+impl HotReloadBackend for AwwwBackend {
+    fn reload(&self, spec: &BackendSpawnSpec) -> Result<(), Error> {
+        info!("Reloading awww wallpaper on {}", spec.output.name);
+        super::block_on(self.apply(spec))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unset_transition_keeps_awww_defaults() {
+        assert!(AwwwTransitionOptions::default().to_args().is_empty());
+
+        let options = AwwwTransitionOptions {
+            kind: Some(AwwwTransition::Wipe),
+            duration: Some(Duration::from_millis(1500)),
+            ..AwwwTransitionOptions::default()
+        };
+        assert_eq!(options.to_args(), ["--transition-type", "wipe", "--transition-duration", "1.5"]);
+    }
+}
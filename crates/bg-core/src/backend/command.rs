@@ -0,0 +1,283 @@
+use std::ffi::OsString;
+use std::io::{Error, ErrorKind};
+
+use super::{BackendCapability, BackendSpawnSpec, MultiOutputBackend, WallpaperBackend, WallpaperMode};
+
+use serde::{Deserialize, Serialize};
+use tokio::process::Child;
+use log::{info, error, debug};
+use which::which;
+
+/// How a `CommandTemplateBackend` is declared in the config file or sent to the daemon.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CommandTemplateConfig {
+    pub name: Option<String>,
+    pub command: String,
+    pub executable: Option<String>,
+    pub capabilities: Option<Vec<BackendCapability>>,
+}
+
+impl CommandTemplateConfig {
+    pub fn into_backend(self) -> Result<CommandTemplateBackend, Error> {
+        let mut backend = match self.name {
+            Some(name) => CommandTemplateBackend::new(name, &self.command)?,
+            None => CommandTemplateBackend::from_template(&self.command)?,
+        };
+        if let Some(executable) = self.executable {
+            backend = backend.with_executable(executable);
+        }
+        if let Some(capabilities) = self.capabilities {
+            backend = backend.with_capabilities(capabilities);
+        }
+        Ok(backend)
+    }
+}
+
+/// A backend declared by the user as a command template, e.g. `wbg {media}`
+/// or `xwallpaper --output {output} --zoom {media}`.
+///
+/// Placeholders: `{media}`, `{output}`, `{mode}`, `{color}`, `{width}`, `{height}`, `{scale}`.
+/// A template without `{output}` is run once for all outputs, which then must show the same media.
+#[derive(Debug, Clone)]
+pub struct CommandTemplateBackend {
+    pub name: String,
+    pub template: Vec<String>,
+    /// Looked up on PATH by `exists()`, defaults to the first word of the template.
+    pub executable: String,
+    pub capabilities: Vec<BackendCapability>,
+}
+
+impl CommandTemplateBackend {
+    pub fn new(name: impl Into<String>, template: &str) -> Result<Self, Error> {
+        let template = split_template(template)?;
+        let executable = template.first().cloned().ok_or_else(|| Error::new(
+            ErrorKind::InvalidInput,
+            "Command template is empty",
+        ))?;
+
+        Ok(Self {
+            name: name.into(),
+            template,
+            executable,
+            capabilities: vec![BackendCapability::Static],
+        })
+    }
+
+    /// Use the executable name of the template as backend name.
+    pub fn from_template(template: &str) -> Result<Self, Error> {
+        let backend = Self::new("", template)?;
+        let name = backend.executable.rsplit('/').next().unwrap_or_default().to_string();
+        Ok(Self { name, ..backend })
+    }
+
+    pub fn with_executable(mut self, executable: impl Into<String>) -> Self {
+        self.executable = executable.into();
+        self
+    }
+
+    pub fn with_capabilities(mut self, capabilities: Vec<BackendCapability>) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    /// Whether the template addresses a single output.
+    pub fn is_per_output(&self) -> bool {
+        self.template.iter().any(|t| t.contains("{output}"))
+    }
+}
+
+impl WallpaperBackend for CommandTemplateBackend {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn start(&self, spec: &BackendSpawnSpec) -> Result<Option<Child>, Error> {
+        info!("Starting custom backend {}.", self.name);
+
+        let argv = self.argv(spec);
+        debug!("Constructed command: {:?}", argv);
+
+        let child_proc = tokio::process::Command::new(&argv[0])
+            .args(&argv[1..])
+            .spawn()?;
+        Ok(Some(child_proc))
+    }
+
+    fn executable(&self) -> &str {
+        &self.executable
+    }
+
+    /// Expand the template for one spec, `extra_args` are appended.
+    fn argv(&self, spec: &BackendSpawnSpec) -> Vec<OsString> {
+        let (width, height) = spec.output.current_mode()
+            .map(|m| (m.width(), m.height()))
+            .unwrap_or_default();
+        let color = match &spec.mode {
+            WallpaperMode::SolidColor(color) => color.as_str(),
+            _ => "",
+        };
+
+        let mut argv: Vec<OsString> = self.template.iter().map(|token| {
+            // Keep non UTF-8 paths intact when the token is only the placeholder.
+            if token == "{media}" {
+                return spec.media.clone().into_os_string();
+            }
+            token
+                .replace("{media}", &spec.media.to_string_lossy())
+                .replace("{output}", &spec.output.name)
+                .replace("{mode}", &spec.mode.to_string())
+                .replace("{color}", color)
+                .replace("{width}", &width.to_string())
+                .replace("{height}", &height.to_string())
+                .replace("{scale}", &spec.output.fractional_scale().to_string())
+                .into()
+        }).collect();
+
+        argv.extend(spec.extra_args.iter().cloned());
+        argv
+    }
+
+    fn exists(&self) -> bool {
+        debug!("Looking for executable `{}`", self.executable);
+        which(&self.executable).is_ok()
+    }
+
+    /// A template without `{output}` serves every output with one process.
+    fn capabilities(&self) -> Vec<BackendCapability> {
+        let mut capabilities = self.capabilities.clone();
+        if !self.is_per_output() && !capabilities.contains(&BackendCapability::MultiOutput) {
+            capabilities.push(BackendCapability::MultiOutput);
+        }
+        capabilities
+    }
+
+    fn start_multi(&self, specs: Vec<BackendSpawnSpec>) -> Result<Vec<Child>, Error> {
+        self.start_multi_output(&specs)
+    }
+
+    fn planned_commands(&self, specs: &[BackendSpawnSpec]) -> Vec<Vec<OsString>> {
+        match self.is_per_output() {
+            true => specs.iter().map(|spec| self.argv(spec)).collect(),
+            false => specs.first().map(|spec| self.argv(spec)).into_iter().collect(),
+        }
+    }
+}
+
+impl MultiOutputBackend for CommandTemplateBackend {
+    fn start_multi_output(&self, specs: &[BackendSpawnSpec]) -> Result<Vec<Child>, Error> {
+        // One process covers every output, so they must all show the same.
+        if !self.is_per_output() {
+            let Some(first) = specs.first() else {
+                return Ok(Vec::new());
+            };
+            if specs.iter().any(|spec| self.argv(spec) != self.argv(first)) {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("{} has no {{output}} placeholder and cannot show different media per output", self.name),
+                ));
+            }
+            return Ok(self.start(first)?.into_iter().collect());
+        }
+
+        let mut children = Vec::new();
+        for spec in specs {
+            match self.start(spec) {
+                Ok(child) => children.extend(child),
+                Err(e) => {
+                    error!("Error starting {}: {}", self.name, e);
+                    // Rollback
+                    super::roll_back(self, children);
+                    return Err(e);
+                }
+            }
+        }
+
+        Ok(children)
+    }
+}
+
+/// Split a template into words, honouring single and double quotes.
+fn split_template(template: &str) -> Result<Vec<String>, Error> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut quote: Option<char> = None;
+
+    for c in template.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => word.push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                in_word = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            (None, c) => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+
+    if quote.is_some() {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("Unclosed quote in `{}`", template)));
+    }
+    if in_word {
+        words.push(word);
+    }
+    Ok(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::orchestrator::start_backend;
+    use crate::wl::OutputInfo;
+
+    fn spec(output: &str) -> BackendSpawnSpec {
+        BackendSpawnSpec {
+            media: "/a.png".into(),
+            mode: WallpaperMode::Fill,
+            output: OutputInfo::new(output),
+            extra_args: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn template_without_output_starts_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("log");
+        let backend = CommandTemplateBackend::from_template(
+            &format!("sh -c 'echo $0 >> {}' {{media}}", log.display()),
+        ).unwrap();
+        assert!(backend.capabilities().contains(&BackendCapability::MultiOutput));
+
+        let mut processes = start_backend(Arc::new(backend), vec![spec("DP-1"), spec("HDMI-A-1")]).unwrap();
+        assert_eq!(processes.len(), 1);
+        assert_eq!(processes[0].outputs(), ["DP-1", "HDMI-A-1"]);
+
+        processes[0].child.as_mut().unwrap().wait().await.unwrap();
+        assert_eq!(std::fs::read_to_string(&log).unwrap(), "/a.png\n");
+    }
+
+    #[test]
+    fn template_without_output_rejects_different_media() {
+        let backend = CommandTemplateBackend::from_template("true {media}").unwrap();
+        let other = BackendSpawnSpec { media: "/b.png".into(), ..spec("HDMI-A-1") };
+
+        let err = start_backend(Arc::new(backend), vec![spec("DP-1"), other]).err().unwrap();
+        assert!(err.to_string().contains("cannot show different media per output"), "{}", err);
+    }
+
+    #[test]
+    fn template_with_output_is_per_output() {
+        let backend = CommandTemplateBackend::from_template("xwallpaper --output {output} --zoom {media}").unwrap();
+        assert_eq!(backend.capabilities(), [BackendCapability::Static]);
+    }
+}
@@ -0,0 +1,370 @@
+use std::env;
+use std::ffi::OsString;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use super::{BackendCapability, BackendSpawnSpec, HotReloadBackend, MultiOutputBackend, WallpaperBackend, WallpaperMode};
+
+use tokio::process::Child;
+use log::{info, error, debug};
+use which::which;
+
+const SOCKET_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Drives hyprpaper through its IPC socket.
+/// A single hyprpaper process serves every output, so a running one is reused.
+pub struct HyprpaperBackend {
+    /// Overrides the socket path derived from the hyprland environment.
+    pub socket: Option<PathBuf>,
+    /// How long to wait for the socket of a freshly spawned hyprpaper.
+    pub startup_timeout: Duration,
+    /// Program spawned when no hyprpaper is listening.
+    pub executable: String,
+}
+
+impl Default for HyprpaperBackend {
+    fn default() -> Self {
+        Self {
+            socket: None,
+            startup_timeout: Duration::from_secs(5),
+            executable: "hyprpaper".to_string(),
+        }
+    }
+}
+
+impl HyprpaperBackend {
+    pub fn with_socket(socket: impl Into<PathBuf>) -> Self {
+        Self {
+            socket: Some(socket.into()),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_executable(mut self, executable: impl Into<String>) -> Self {
+        self.executable = executable.into();
+        self
+    }
+
+    /// `$XDG_RUNTIME_DIR/hypr/$HYPRLAND_INSTANCE_SIGNATURE/.hyprpaper.sock`
+    pub fn socket_path(&self) -> Option<PathBuf> {
+        if let Some(socket) = &self.socket {
+            return Some(socket.clone());
+        }
+
+        let signature = env::var_os("HYPRLAND_INSTANCE_SIGNATURE")?;
+        let runtime_dir = env::var_os("XDG_RUNTIME_DIR")?;
+        Some(PathBuf::from(runtime_dir).join("hypr").join(signature).join(".hyprpaper.sock"))
+    }
+
+    fn connect(&self) -> Result<UnixStream, Error> {
+        let path = self.socket_path().ok_or_else(|| Error::new(
+            ErrorKind::NotFound,
+            "Cannot locate hyprpaper socket, is HYPRLAND_INSTANCE_SIGNATURE set?",
+        ))?;
+        UnixStream::connect(path)
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.connect().is_ok()
+    }
+
+    /// Send one IPC request. hyprpaper answers `ok` or an error message, then closes.
+    pub fn request(&self, request: &str) -> Result<(), Error> {
+        debug!("hyprpaper request: {}", request);
+        let mut stream = self.connect()?;
+        stream.set_read_timeout(Some(self.startup_timeout))?;
+        stream.write_all(request.as_bytes())?;
+
+        let mut reply = String::new();
+        stream.read_to_string(&mut reply)?;
+        let reply = reply.trim();
+
+        if reply == "ok" {
+            Ok(())
+        } else {
+            Err(Error::other(format!("hyprpaper rejected `{}`: {}", request, reply)))
+        }
+    }
+
+    /// Spawn hyprpaper unless one is already listening.
+    /// Returns the child only when we spawned it.
+    fn ensure_running(&self) -> Result<Option<Child>, Error> {
+        if self.is_running() {
+            info!("Reusing running hyprpaper.");
+            return Ok(None);
+        }
+
+        info!("Starting hyprpaper backend.");
+        let mut child = tokio::process::Command::new(&self.executable).spawn()?;
+
+        super::block_on(async {
+            match self.wait_for_socket(&mut child).await {
+                Ok(()) => Ok(Some(child)),
+                Err(e) => {
+                    super::kill_and_reap(&mut child).await;
+                    Err(e)
+                }
+            }
+        })
+    }
+
+    async fn wait_for_socket(&self, child: &mut Child) -> Result<(), Error> {
+        let deadline = Instant::now() + self.startup_timeout;
+        while !self.is_running() {
+            if let Some(status) = child.try_wait()? {
+                return Err(Error::other(format!("hyprpaper exited early with {}", status)));
+            }
+            if Instant::now() >= deadline {
+                return Err(Error::new(ErrorKind::TimedOut, "Timed out waiting for hyprpaper socket"));
+            }
+            tokio::time::sleep(SOCKET_POLL_INTERVAL).await;
+        }
+        Ok(())
+    }
+
+    /// The `wallpaper` prefix for `mode`, hyprpaper covers by default.
+    fn mode_prefix(mode: &WallpaperMode) -> Option<&'static str> {
+        match mode {
+            WallpaperMode::Fill => Some(""),
+            WallpaperMode::Fit => Some("contain:"),
+            WallpaperMode::Tile => Some("tile:"),
+            _ => None,
+        }
+    }
+
+    /// The IPC requests showing `spec`.
+    fn requests(spec: &BackendSpawnSpec) -> Result<[String; 2], Error> {
+        let prefix = Self::mode_prefix(&spec.mode).ok_or_else(|| Error::new(
+            ErrorKind::Unsupported,
+            format!("hyprpaper cannot show mode {}", spec.mode),
+        ))?;
+        let media = spec.media.to_string_lossy();
+        Ok([
+            format!("preload {}", media),
+            format!("wallpaper {},{}{}", spec.output.name, prefix, media),
+        ])
+    }
+
+    fn apply(&self, spec: &BackendSpawnSpec) -> Result<(), Error> {
+        for request in Self::requests(spec)? {
+            self.request(&request)?;
+        }
+        Ok(())
+    }
+}
+
+impl WallpaperBackend for HyprpaperBackend {
+    fn name(&self) -> &str {
+        "hyprpaper"
+    }
+
+    fn start(&self, spec: &BackendSpawnSpec) -> Result<Option<Child>, Error> {
+        let mut children = self.start_multi_output(std::slice::from_ref(spec))?;
+        Ok(children.pop())
+    }
+
+    fn update(&self, spec: &BackendSpawnSpec) -> Result<(), Error> {
+        self.reload(spec)
+    }
+
+    fn exists(&self) -> bool {
+        debug!("Looking for executable `{}`", self.executable);
+        which(&self.executable).is_ok()
+    }
+
+    fn capabilities(&self) -> Vec<BackendCapability> {
+        vec![
+            BackendCapability::Static,
+            BackendCapability::MultiOutput,
+            BackendCapability::HotReload,
+        ]
+    }
+
+    fn supports_mode(&self, mode: &WallpaperMode) -> bool {
+        Self::mode_prefix(mode).is_some()
+    }
+
+    fn start_multi(&self, specs: Vec<BackendSpawnSpec>) -> Result<Vec<Child>, Error> {
+        self.start_multi_output(&specs)
+    }
+
+    /// hyprpaper unless it is already running, then the IPC requests
+    /// written as the equivalent `hyprctl hyprpaper` calls.
+    fn planned_commands(&self, specs: &[BackendSpawnSpec]) -> Vec<Vec<OsString>> {
+        let daemon = (!self.is_running()).then(|| vec![self.executable.clone().into()]);
+        let requests = specs.iter().filter_map(|spec| Self::requests(spec).ok()).flatten().map(|request| {
+            ["hyprctl", "hyprpaper"].into_iter().map(OsString::from)
+                .chain(request.splitn(2, ' ').map(OsString::from))
+                .collect()
+        });
+        daemon.into_iter().chain(requests).collect()
+    }
+}
+
+impl MultiOutputBackend for HyprpaperBackend {
+    fn start_multi_output(&self, specs: &[BackendSpawnSpec]) -> Result<Vec<Child>, Error> {
+        let mut spawned = self.ensure_running()?;
+
+        for spec in specs {
+            if let Err(e) = self.apply(spec) {
+                error!("Error setting hyprpaper wallpaper on {}: {}", spec.output.name, e);
+                // Rollback, but leave a reused hyprpaper alone.
+                super::roll_back(self, spawned.take().into_iter().collect());
+                return Err(e);
+            }
+        }
+
+        // Free images that are no longer shown on any output.
+        if let Err(e) = self.request("unload unused") {
+            debug!("Failed unloading unused wallpapers: {}", e);
+        }
+
+        Ok(spawned.into_iter().collect())
+    }
+}
+
+impl HotReloadBackend for HyprpaperBackend {
+    fn reload(&self, spec: &BackendSpawnSpec) -> Result<(), Error> {
+        info!("Reloading hyprpaper wallpaper on {}", spec.output.name);
+        self.apply(spec)?;
+        if let Err(e) = self.request("unload unused") {
+            debug!("Failed unloading unused wallpapers: {}", e);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixListener;
+    use std::path::Path;
+    use std::sync::{Arc, Mutex};
+    use bg_test_support::{process_exists, write_script};
+    use crate::wl::OutputInfo;
+
+    /// Answers every request on `socket` like hyprpaper, rejecting those starting with `reject`.
+    fn fake_hyprpaper(socket: &Path, reject: Option<&'static str>) -> Arc<Mutex<Vec<String>>> {
+        let listener = UnixListener::bind(socket).unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let seen = requests.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut buf = [0; 1024];
+                let n = stream.read(&mut buf).unwrap();
+                if n == 0 {
+                    // `is_running` probing the socket.
+                    continue;
+                }
+                let request = String::from_utf8_lossy(&buf[..n]).to_string();
+                let reply = match reject {
+                    Some(prefix) if request.starts_with(prefix) => "invalid request",
+                    _ => "ok",
+                };
+                seen.lock().unwrap().push(request);
+                let _ = stream.write_all(reply.as_bytes());
+            }
+        });
+        requests
+    }
+
+    fn spec(output: &str, media: &str, mode: WallpaperMode) -> BackendSpawnSpec {
+        BackendSpawnSpec {
+            media: media.into(),
+            mode,
+            output: OutputInfo::new(output),
+            extra_args: Vec::new(),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn reuses_running_hyprpaper() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join(".hyprpaper.sock");
+        let requests = fake_hyprpaper(&socket, None);
+        let backend = HyprpaperBackend::with_socket(&socket).with_executable("/nonexistent/hyprpaper");
+
+        let children = backend.start_multi(vec![
+            spec("DP-1", "/a.png", WallpaperMode::Fill),
+            spec("HDMI-A-1", "/b.png", WallpaperMode::Fit),
+        ]).unwrap();
+
+        assert!(children.is_empty());
+        assert_eq!(*requests.lock().unwrap(), [
+            "preload /a.png",
+            "wallpaper DP-1,/a.png",
+            "preload /b.png",
+            "wallpaper HDMI-A-1,contain:/b.png",
+            "unload unused",
+        ]);
+    }
+
+    #[test]
+    fn modes_map_to_wallpaper_prefixes() {
+        let wallpaper = |mode| HyprpaperBackend::requests(&spec("DP-1", "/a.png", mode)).map(|[_, wallpaper]| wallpaper);
+        assert_eq!(wallpaper(WallpaperMode::Fill).unwrap(), "wallpaper DP-1,/a.png");
+        assert_eq!(wallpaper(WallpaperMode::Fit).unwrap(), "wallpaper DP-1,contain:/a.png");
+        assert_eq!(wallpaper(WallpaperMode::Tile).unwrap(), "wallpaper DP-1,tile:/a.png");
+        for mode in [WallpaperMode::Stretch, WallpaperMode::Center, WallpaperMode::SolidColor("#000000".to_string())] {
+            assert!(!HyprpaperBackend::default().supports_mode(&mode));
+            assert_eq!(wallpaper(mode).unwrap_err().kind(), ErrorKind::Unsupported);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn unsupported_mode_is_refused_before_any_request() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join(".hyprpaper.sock");
+        let requests = fake_hyprpaper(&socket, None);
+        let backend = HyprpaperBackend::with_socket(&socket);
+
+        let err = crate::orchestrator::start_backend(std::sync::Arc::new(backend), vec![
+            spec("DP-1", "/a.png", WallpaperMode::Fill),
+            spec("HDMI-A-1", "/b.png", WallpaperMode::Stretch),
+        ]).err().unwrap();
+        assert!(err.to_string().contains("cannot show mode stretch"), "{}", err);
+        assert!(requests.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn rejected_request_fails_start() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join(".hyprpaper.sock");
+        let _requests = fake_hyprpaper(&socket, Some("wallpaper"));
+        let backend = HyprpaperBackend::with_socket(&socket);
+
+        let err = backend.start_multi(vec![spec("DP-1", "/a.png", WallpaperMode::Tile)]).unwrap_err();
+        assert!(err.to_string().contains("invalid request"), "{}", err);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn startup_timeout_reaps_hyprpaper() {
+        let dir = tempfile::tempdir().unwrap();
+        let pid_file = dir.path().join("pid");
+        let script = write_script(dir.path(), "hyprpaper", &format!("echo $$ > {}; exec sleep 60", pid_file.display())).unwrap();
+        let backend = HyprpaperBackend {
+            startup_timeout: Duration::from_millis(300),
+            ..HyprpaperBackend::with_socket(dir.path().join(".hyprpaper.sock")).with_executable(script.to_string_lossy())
+        };
+
+        let err = backend.start_multi(vec![spec("DP-1", "/a.png", WallpaperMode::Fill)]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+
+        let pid: u32 = std::fs::read_to_string(&pid_file).unwrap().trim().parse().unwrap();
+        assert!(!process_exists(pid), "hyprpaper {} was left behind", pid);
+    }
+
+    #[tokio::test]
+    async fn early_exit_is_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = write_script(dir.path(), "hyprpaper", "exit 3").unwrap();
+        let backend = HyprpaperBackend::with_socket(dir.path().join(".hyprpaper.sock"))
+            .with_executable(script.to_string_lossy());
+
+        let err = backend.start(&spec("DP-1", "/a.png", WallpaperMode::Fill)).unwrap_err();
+        assert!(err.to_string().contains("exited early"), "{}", err);
+    }
+}
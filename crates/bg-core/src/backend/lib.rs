@@ -11,7 +11,8 @@ pub enum ImageType {
     WEBP,
 }
 
-#[derive(PartialEq, Clone)]
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum BackendCapability {
     Static,
     Animated,
@@ -20,18 +21,48 @@ pub enum BackendCapability {
     HotReload
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum WallpaperMode {
     Stretch, 
     Fit, 
     Fill, 
     Center, 
     Tile, 
-    SolidColor
+    /// A plain colour such as `#1e1e2e`, no media needed.
+    SolidColor(String)
+}
+
+impl WallpaperMode {
+    /// `SolidColor` if `color` is `#rrggbb`, the one format every backend understands.
+    pub fn solid_color(color: impl Into<String>) -> Result<Self> {
+        let color = color.into();
+        match color.strip_prefix('#') {
+            Some(hex) if hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit()) => {
+                Ok(WallpaperMode::SolidColor(color))
+            }
+            _ => Err(Error::Config(format!("`{}` is not a colour, expected #rrggbb", color))),
+        }
+    }
+}
+
+impl Display for WallpaperMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            WallpaperMode::Stretch => "stretch",
+            WallpaperMode::Fit => "fit",
+            WallpaperMode::Fill => "fill",
+            WallpaperMode::Center => "center",
+            WallpaperMode::Tile => "tile",
+            WallpaperMode::SolidColor(_) => "solid_color",
+        };
+        write!(f, "{}", name)
+    }
 }
 
 pub enum Backend {
     Swaybg(SwaybgBackend),
+    Hyprpaper(HyprpaperBackend),
     MpvPaper(MpvPaperBackend),
     Awww(AwwwBackend)
 }
@@ -40,16 +71,24 @@ impl Backend {
     pub fn exists(&self) -> bool {
         match self {
             Backend::Swaybg(backend) => backend.exists(),
-            // Backend::MpvPaper(backend) => backend.exists(),
-            // Backend::Awww(backend) => backend.exists(),
-            _ => false
+            Backend::Hyprpaper(backend) => backend.exists(),
+            Backend::MpvPaper(backend) => backend.exists(),
+            Backend::Awww(backend) => backend.exists(),
         }
     }
 
     pub fn supported_backends() -> Vec<Box<dyn WallpaperBackend>> {
-        let mut backends: Vec<Box<dyn WallpaperBackend>>  = Vec::new();
-        backends.push(Box::new(SwaybgBackend));
-        backends
+        Self::supported_backends_with(AwwwTransitionOptions::default())
+    }
+
+    /// The built-in backends, awww showing media with `transition`.
+    pub fn supported_backends_with(transition: AwwwTransitionOptions) -> Vec<Box<dyn WallpaperBackend>> {
+        vec![
+            Box::new(SwaybgBackend),
+            Box::new(HyprpaperBackend::default()),
+            Box::new(MpvPaperBackend::default()),
+            Box::new(AwwwBackend::with_transition(transition)),
+        ]
     }
 }
 
@@ -64,29 +103,117 @@ impl From<MediaKind> for Option<BackendCapability> {
     }
 }
 
-#[allow(unused_variables)]
-#[allow(dead_code)]
+/// A running backend together with the outputs it serves.
+/// Daemon style backends serve several outputs from one process,
+/// and own no child when an already running process was reused.
 pub struct WallpaperProcess {
-    backend: Box<dyn WallpaperBackend>,
-    child: Option<tokio::process::Child>
+    pub(crate) backend: Arc<dyn WallpaperBackend>,
+    pub(crate) specs: Vec<BackendSpawnSpec>,
+    pub(crate) child: Option<tokio::process::Child>
 }
 
-pub trait WallpaperBackend {
+impl WallpaperProcess {
+    pub fn new(
+        backend: Arc<dyn WallpaperBackend>,
+        specs: Vec<BackendSpawnSpec>,
+        child: Option<Child>,
+    ) -> Self {
+        Self { backend, specs, child }
+    }
+
+    pub fn backend(&self) -> &dyn WallpaperBackend {
+        self.backend.as_ref()
+    }
+
+    pub fn specs(&self) -> &[BackendSpawnSpec] {
+        &self.specs
+    }
+
+    /// Names of the outputs served.
+    pub fn outputs(&self) -> Vec<String> {
+        self.specs.iter().map(|s| s.output.name.clone()).collect()
+    }
+
+    pub fn id(&self) -> Option<u32> {
+        self.child.as_ref().and_then(|c| c.id())
+    }
+
+    /// Stop the owned child, if any, within `DEFAULT_STOP_GRACE`.
+    pub async fn stop(self) -> std::io::Result<Option<StopReport>> {
+        self.stop_within(DEFAULT_STOP_GRACE).await
+    }
+
+    /// Stop the owned child, if any, killing it if it is still running after `grace`.
+    /// `None` if the process owns no child.
+    pub async fn stop_within(mut self, grace: Duration) -> std::io::Result<Option<StopReport>> {
+        match self.child.take() {
+            Some(child) => stop_and_wait(self.backend.as_ref(), child, grace).await.map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+pub trait WallpaperBackend: Send + Sync {
     fn name(&self) -> &str;
-    fn start(&self, media_path: &BackendSpawnSpec) -> Result<Child, std::io::Error>;
-    // Removed update, move this to HotReload trait. 
-    // If no such trait, we stop and restart the backend manually.
-    // fn update(&self, media_path: &Path) -> Result<Option<Child>, std::io::Error>;
+    /// Start the backend for one output.
+    /// Returns `None` if an already running process was reused, so there is no child to own.
+    fn start(&self, spec: &BackendSpawnSpec) -> Result<Option<Child>, std::io::Error>;
+    /// Change the wallpaper of a running backend in place.
+    /// Only backends with `HotReload` override this,
+    /// for the others we stop and restart the backend manually.
+    #[allow(unused_variables)]
+    fn update(&self, spec: &BackendSpawnSpec) -> Result<(), std::io::Error> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            format!("{} does not support hot reload", self.name()),
+        ))
+    }
     fn exists(&self) -> bool;
+    /// The program looked up on PATH, shown by `list backend`.
+    fn executable(&self) -> &str {
+        self.name()
+    }
+    /// Version reported by the installed program, if it can tell without side effects.
+    fn version(&self) -> Option<String> {
+        None
+    }
+    /// The command `start` runs for `spec`, shown by `--dry-run`.
+    #[allow(unused_variables)]
+    fn argv(&self, spec: &BackendSpawnSpec) -> Vec<OsString> {
+        vec![self.executable().into()]
+    }
+    /// Every command run to show `specs`, in order, without running any.
+    fn planned_commands(&self, specs: &[BackendSpawnSpec]) -> Vec<Vec<OsString>> {
+        specs.iter().map(|spec| self.argv(spec)).collect()
+    }
+    /// Ask the child to exit, `stop_and_wait` kills it if it does not.
     fn stop(&self, c: &mut Child) -> Result<(), std::io::Error> {
-        c.start_kill()
+        terminate(c)
     }
     fn capabilities(&self) -> Vec<BackendCapability>;
 
+    /// Whether media of this kind can be shown by the backend.
+    fn supports(&self, kind: MediaKind) -> bool {
+        match Option::<BackendCapability>::from(kind) {
+            Some(capability) => self.capabilities().contains(&capability),
+            None => false,
+        }
+    }
+
+    /// Whether the backend can show media in this mode.
+    #[allow(unused_variables)]
+    fn supports_mode(&self, mode: &WallpaperMode) -> bool {
+        true
+    }
+
+    /// Start the backend for several outputs at once.
+    /// Only backends with `MultiOutput` override this.
     #[allow(unused_variables)]
     fn start_multi(&self, specs: Vec<BackendSpawnSpec>) -> Result<Vec<Child>, std::io::Error> {
-        error!("Not implemented.");
-        exit(1)
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            format!("{} cannot serve several outputs at once", self.name()),
+        ))
     }
 }
 
@@ -94,15 +221,29 @@ pub trait MultiOutputBackend {
     fn start_multi_output(&self, specs: &[BackendSpawnSpec]) -> Result<Vec<Child>, std::io::Error>;
 }
 
+pub trait HotReloadBackend {
+    fn reload(&self, spec: &BackendSpawnSpec) -> Result<(), std::io::Error>;
+}
+
 use std::ffi::OsString;
+use std::future::Future;
+use std::fmt::Display;
+use std::os::unix::process::ExitStatusExt;
 use std::path::PathBuf;
-use std::process::exit;
-use log::{error, info};
-use crate::backend::awww::AwwwBackend;
+use std::process::ExitStatus;
+use std::sync::Arc;
+use std::time::Duration;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::runtime::{Handle, RuntimeFlavor};
+use crate::backend::awww::{AwwwBackend, AwwwTransitionOptions};
+use crate::backend::hyprpaper::HyprpaperBackend;
 use crate::backend::mpvpaper::MpvPaperBackend;
 use crate::backend::swaybg::SwaybgBackend;
 use crate::wl::OutputInfo;
+use crate::{Error, Result};
 
+#[derive(Debug, Clone)]
 pub struct BackendSpawnSpec {
     pub media: PathBuf, // backends would assume this must exist. 
     pub mode: WallpaperMode,
@@ -117,19 +258,198 @@ pub struct MultiMonitorBackendSpawnSpec {
     pub extra_args: Vec<OsString>
 }
 
+/// What `list backend` shows about a backend.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackendDescriptor {
+    pub name: String,
+    pub capabilities: Vec<BackendCapability>,
+    pub path: Option<PathBuf>,
+    pub version: Option<String>,
+}
+
+impl BackendDescriptor {
+    pub fn of(backend: &dyn WallpaperBackend) -> Self {
+        Self {
+            name: backend.name().to_string(),
+            capabilities: backend.capabilities(),
+            path: which::which(backend.executable()).ok(),
+            version: backend.version(),
+        }
+    }
+}
+
+/// First line printed by `executable args`, e.g. `swaybg -v`.
+pub(crate) fn command_version(executable: &str, args: &[&str]) -> Option<String> {
+    let output = std::process::Command::new(executable).args(args).output().ok()?;
+    let text = String::from_utf8_lossy(&output.stdout).into_owned() + &String::from_utf8_lossy(&output.stderr);
+    text.lines().map(str::trim).find(|line| !line.is_empty()).map(str::to_string)
+}
+
+/// How long a backend may take to exit after SIGTERM before it is killed.
+pub const DEFAULT_STOP_GRACE: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StopOutcome {
+    /// Had exited on its own before it was asked to.
+    AlreadyExited,
+    /// Exited within the grace period.
+    Terminated,
+    /// Was still running after the grace period and got SIGKILL.
+    Killed,
+}
+
+/// How one backend child ended.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StopReport {
+    pub backend: String,
+    pub pid: Option<u32>,
+    pub outcome: StopOutcome,
+    pub code: Option<i32>,
+    pub signal: Option<i32>,
+}
+
+impl Display for StopReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let outcome = match self.outcome {
+            StopOutcome::AlreadyExited => "had already exited",
+            StopOutcome::Terminated => "terminated",
+            StopOutcome::Killed => "killed",
+        };
+        write!(f, "{}", self.backend)?;
+        if let Some(pid) = self.pid {
+            write!(f, " (pid {})", pid)?;
+        }
+        write!(f, " {}", outcome)?;
+        match (self.code, self.signal) {
+            (Some(code), _) => write!(f, ", exit code {}", code),
+            (None, Some(signal)) => write!(f, ", signal {}", signal),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+/// Send SIGTERM to `child`, nothing to do if it was already reaped.
+pub fn terminate(child: &Child) -> std::io::Result<()> {
+    match child.id() {
+        Some(pid) => send_signal(pid, libc::SIGTERM),
+        None => Ok(()),
+    }
+}
+
+/// Send `signal` to `pid`, a process that is already gone is not an error.
+pub fn send_signal(pid: u32, signal: libc::c_int) -> std::io::Result<()> {
+    // SAFETY: kill(2) has no memory effects.
+    if unsafe { libc::kill(pid as libc::pid_t, signal) } == 0 {
+        return Ok(());
+    }
+    match std::io::Error::last_os_error() {
+        e if e.raw_os_error() == Some(libc::ESRCH) => Ok(()),
+        e => Err(e),
+    }
+}
+
+/// Ask `child` to stop, SIGKILL it if it is still running after `grace`,
+/// and reap it so no zombie is left behind.
 pub async fn stop_and_wait(
     backend: &dyn WallpaperBackend,
     mut child: Child,
-) -> std::io::Result<()> {
+    grace: Duration,
+) -> std::io::Result<StopReport> {
+    let pid = child.id();
+    let report = |outcome, status: ExitStatus| StopReport {
+        backend: backend.name().to_string(),
+        pid,
+        outcome,
+        code: status.code(),
+        signal: status.signal(),
+    };
+
+    if let Some(status) = child.try_wait()? {
+        return Ok(report(StopOutcome::AlreadyExited, status));
+    }
+
     backend.stop(&mut child)?;
-    child.wait().await?;
-    Ok(())
+    match tokio::time::timeout(grace, child.wait()).await {
+        Ok(status) => Ok(report(StopOutcome::Terminated, status?)),
+        Err(_) => {
+            warn!("{} still running after {:?}, killing it", backend.name(), grace);
+            child.kill().await?;
+            Ok(report(StopOutcome::Killed, child.wait().await?))
+        }
+    }
+}
+
+/// Drive `future` from a synchronous backend method, e.g. to wait for a daemon
+/// with `tokio::time::sleep`. On a multi-threaded runtime the worker is handed
+/// off with `block_in_place`, so other tasks keep running meanwhile.
+pub(crate) fn block_on<F>(future: F) -> F::Output
+where
+    F: Future + Send,
+    F::Output: Send,
+{
+    match Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+            tokio::task::block_in_place(|| handle.block_on(future))
+        }
+        // A current-thread runtime cannot be blocked from inside, wait on a thread of our own.
+        _ => std::thread::scope(|scope| {
+            scope.spawn(|| {
+                tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .expect("Failed to build a runtime")
+                    .block_on(future)
+            }).join().expect("Backend wait panicked")
+        }),
+    }
+}
+
+/// Kill a daemon that never came up and wait for it, so no zombie is left behind.
+/// Polls instead of awaiting `Child::wait`, which needs the runtime `child` was spawned on.
+pub(crate) async fn kill_and_reap(child: &mut Child) {
+    if let Err(e) = child.start_kill() {
+        warn!("Failed killing pid {:?}: {}", child.id(), e);
+    }
+    while let Ok(None) = child.try_wait() {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+/// Stop the children started before a failure and reap them, killing the ones
+/// still running after `DEFAULT_STOP_GRACE`. Polls like `kill_and_reap`.
+pub(crate) fn roll_back(backend: &dyn WallpaperBackend, children: Vec<Child>) {
+    block_on(async {
+        for mut child in children {
+            if let Err(e) = backend.stop(&mut child) {
+                warn!("Failed stopping {} pid {:?}: {}", backend.name(), child.id(), e);
+            }
+            let deadline = tokio::time::Instant::now() + DEFAULT_STOP_GRACE;
+            while let Ok(None) = child.try_wait() {
+                if tokio::time::Instant::now() >= deadline {
+                    kill_and_reap(&mut child).await;
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }
+    })
 }
 
 pub fn available_backends() -> Vec<Box<dyn WallpaperBackend>> {
+    available_backends_with(Vec::new())
+}
+
+/// Like `available_backends`, with user declared backends (e.g. command templates) checked first.
+pub fn available_backends_with(custom: Vec<Box<dyn WallpaperBackend>>) -> Vec<Box<dyn WallpaperBackend>> {
+    installed_backends(custom.into_iter().chain(Backend::supported_backends()).collect())
+}
+
+/// The `candidates` that are installed, in order.
+pub fn installed_backends(candidates: Vec<Box<dyn WallpaperBackend>>) -> Vec<Box<dyn WallpaperBackend>> {
     info!("Detecting backends");
     let mut backends: Vec<Box<dyn WallpaperBackend>> = Vec::new();
-    for backend in Backend::supported_backends() {
+    for backend in candidates {
         if backend.exists() {
             info!("Found backend {:?}", backend.name());
             backends.push(backend);
@@ -140,6 +460,26 @@ pub fn available_backends() -> Vec<Box<dyn WallpaperBackend>> {
     backends
 }
 
+/// All `candidates`, the installed ones first, for planning without spawning anything.
+pub fn installed_first(candidates: Vec<Box<dyn WallpaperBackend>>) -> Vec<Box<dyn WallpaperBackend>> {
+    let (mut installed, missing): (Vec<_>, Vec<_>) = candidates.into_iter()
+        .partition(|backend| backend.exists());
+    installed.extend(missing);
+    installed
+}
+
+/// Like `select_backend`, for backends shared between processes.
+pub fn select_shared_backend(
+    requested: Option<&str>,
+    available: &[Arc<dyn WallpaperBackend>],
+) -> Result<Arc<dyn WallpaperBackend>> {
+    match requested {
+        Some(name) => available.iter().find(|b| b.name() == name).cloned()
+            .ok_or_else(|| Error::BackendNotFound(name.to_string())),
+        None => available.first().cloned().ok_or(Error::NoBackendAvailable),
+    }
+}
+
 pub fn get_backend_by_name(name: &String, available: Option<Vec<Box<dyn WallpaperBackend>>>) -> Option<Box<dyn WallpaperBackend>> {
     available.unwrap_or(available_backends())
         .into_iter().find(
@@ -147,24 +487,119 @@ pub fn get_backend_by_name(name: &String, available: Option<Vec<Box<dyn Wallpape
         )
 }
 
-pub fn get_first_backend() -> Box<dyn WallpaperBackend> {
+pub fn get_first_backend() -> Result<Box<dyn WallpaperBackend>> {
     available_backends().into_iter().find(
         |backend| backend.exists()
-    ).unwrap()
+    ).ok_or(Error::NoBackendAvailable)
 }
 
+/// The requested backend, or the first available one if none was requested.
 pub fn select_backend(
     requested: Option<String>,
     available: Vec<Box<dyn WallpaperBackend>>,
-) -> Box<dyn WallpaperBackend>  {
+) -> Result<Box<dyn WallpaperBackend>>  {
+    let mut available = available;
     if let Some(name) = requested {
-        if let Some(b) = get_backend_by_name(
-            &name, Some(available)
-        ) {
-            return b;
+        return match available.iter().position(|b| b.name() == name) {
+            Some(i) => Ok(available.swap_remove(i)),
+            None => Err(Error::BackendNotFound(name)),
+        };
+    }
+
+    if available.is_empty() {
+        return get_first_backend();
+    }
+    Ok(available.remove(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use bg_test_support::{process_exists, write_script};
+    use crate::backend::MockBackend;
+
+    /// Start `body` as a backend child and wait until it wrote `ready`, so its trap is set.
+    async fn start_script(dir: &Path, body: &str) -> (MockBackend, Child) {
+        let ready = dir.join("ready");
+        let script = write_script(dir, "backend", &format!("{}\ntouch {}\nwhile :; do sleep 0.05; done", body, ready.display()))
+            .unwrap();
+        let backend = MockBackend::new("trapper").with_command(&[script.to_str().unwrap()]);
+        let child = backend.start(&spec()).unwrap().unwrap();
+        for _ in 0..500 {
+            if ready.exists() {
+                return (backend, child);
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("{} did not start", script.display());
+    }
+
+    fn spec() -> BackendSpawnSpec {
+        BackendSpawnSpec {
+            output: OutputInfo::new("DP-1"),
+            media: PathBuf::new(),
+            mode: WallpaperMode::Fit,
+            extra_args: Vec::new(),
         }
-        error!("Backend {} not found, falling back.", name);
     }
 
-    get_first_backend()
+    #[tokio::test]
+    async fn trapped_sigterm_ends_within_the_grace_period() {
+        let dir = tempfile::tempdir().unwrap();
+        let (backend, child) = start_script(dir.path(), "trap 'exit 3' TERM").await;
+        let pid = child.id().unwrap();
+
+        let report = stop_and_wait(&backend, child, Duration::from_secs(5)).await.unwrap();
+
+        assert_eq!(report.outcome, StopOutcome::Terminated);
+        assert_eq!((report.pid, report.code, report.signal), (Some(pid), Some(3), None));
+        assert_eq!(backend.stopped(), [Some(pid)]);
+        assert!(!process_exists(pid));
+    }
+
+    #[tokio::test]
+    async fn ignored_sigterm_is_killed_after_the_grace_period() {
+        let dir = tempfile::tempdir().unwrap();
+        let (backend, child) = start_script(dir.path(), "trap '' TERM").await;
+        let pid = child.id().unwrap();
+
+        let report = stop_and_wait(&backend, child, Duration::from_millis(200)).await.unwrap();
+
+        assert_eq!(report.outcome, StopOutcome::Killed);
+        assert_eq!((report.code, report.signal), (None, Some(libc::SIGKILL)));
+        assert!(!process_exists(pid));
+    }
+
+    #[tokio::test]
+    async fn exited_child_is_only_reaped() {
+        let backend = MockBackend::new("quitter").with_command(&["true"]);
+        let mut child = backend.start(&spec()).unwrap().unwrap();
+        child.wait().await.unwrap();
+
+        let report = stop_and_wait(&backend, child, Duration::from_secs(5)).await.unwrap();
+
+        assert_eq!(report.outcome, StopOutcome::AlreadyExited);
+        assert_eq!(report.code, Some(0));
+        assert!(backend.stopped().is_empty());
+    }
+
+    #[tokio::test]
+    async fn rolled_back_children_are_reaped() {
+        let dir = tempfile::tempdir().unwrap();
+        let (backend, child) = start_script(dir.path(), "").await;
+        let pid = child.id().unwrap();
+
+        roll_back(&backend, vec![child]);
+
+        assert!(!process_exists(pid), "rolled back child not reaped");
+    }
+
+    #[test]
+    fn solid_color_needs_rrggbb() {
+        assert_eq!(WallpaperMode::solid_color("#1E1e2e").unwrap(), WallpaperMode::SolidColor("#1E1e2e".to_string()));
+        assert!(WallpaperMode::solid_color("red").is_err());
+        assert!(WallpaperMode::solid_color("#12345").is_err());
+        assert!(WallpaperMode::solid_color("#12345g").is_err());
+    }
 }
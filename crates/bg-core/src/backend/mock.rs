@@ -0,0 +1,150 @@
+use std::io::{Error, ErrorKind};
+use std::sync::{Arc, Mutex};
+
+use super::{BackendCapability, BackendSpawnSpec, MultiOutputBackend, WallpaperBackend};
+
+use tokio::process::Child;
+use log::info;
+
+/// A backend that records what it is asked to show instead of showing it,
+/// so the orchestrator can be driven without a compositor or any wallpaper program.
+/// Clones share their records, keep one to inspect a backend handed to the orchestrator.
+#[derive(Debug, Clone)]
+pub struct MockBackend {
+    pub name: String,
+    pub capabilities: Vec<BackendCapability>,
+    /// What `exists()` answers, a missing backend is skipped by `available_backends_with`.
+    pub installed: bool,
+    /// `start` and `update` fail for these outputs.
+    pub failing: Vec<String>,
+    /// Spawned by every `start`, so there is a child to stop. No process when empty.
+    pub command: Vec<String>,
+    records: Arc<Mutex<MockRecords>>,
+}
+
+#[derive(Debug, Default)]
+struct MockRecords {
+    started: Vec<BackendSpawnSpec>,
+    updated: Vec<BackendSpawnSpec>,
+    stopped: Vec<Option<u32>>,
+}
+
+impl MockBackend {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            capabilities: vec![BackendCapability::Static],
+            installed: true,
+            failing: Vec::new(),
+            command: Vec::new(),
+            records: Arc::default(),
+        }
+    }
+
+    pub fn with_capabilities(mut self, capabilities: Vec<BackendCapability>) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    /// Report the backend as not installed.
+    pub fn missing(mut self) -> Self {
+        self.installed = false;
+        self
+    }
+
+    /// Fail to start on `output`.
+    pub fn failing_on(mut self, output: impl Into<String>) -> Self {
+        self.failing.push(output.into());
+        self
+    }
+
+    /// Spawn `command`, e.g. `["sleep", "60"]`, for every output started.
+    pub fn with_command(mut self, command: &[&str]) -> Self {
+        self.command = command.iter().map(|arg| arg.to_string()).collect();
+        self
+    }
+
+    /// Specs passed to `start`, in order, including the failed ones.
+    pub fn started(&self) -> Vec<BackendSpawnSpec> {
+        self.records.lock().unwrap().started.clone()
+    }
+
+    /// Specs passed to `update`, in order.
+    pub fn updated(&self) -> Vec<BackendSpawnSpec> {
+        self.records.lock().unwrap().updated.clone()
+    }
+
+    /// Pids of the children `stop` was called for.
+    pub fn stopped(&self) -> Vec<Option<u32>> {
+        self.records.lock().unwrap().stopped.clone()
+    }
+
+    fn check(&self, spec: &BackendSpawnSpec) -> Result<(), Error> {
+        match self.failing.contains(&spec.output.name) {
+            true => Err(Error::other(format!("{} told to fail on {}", self.name, spec.output.name))),
+            false => Ok(()),
+        }
+    }
+}
+
+impl WallpaperBackend for MockBackend {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn start(&self, spec: &BackendSpawnSpec) -> Result<Option<Child>, Error> {
+        info!("Starting mock backend {} on {}.", self.name, spec.output.name);
+        self.records.lock().unwrap().started.push(spec.clone());
+        self.check(spec)?;
+
+        match self.command.split_first() {
+            Some((program, args)) => tokio::process::Command::new(program).args(args).spawn().map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn update(&self, spec: &BackendSpawnSpec) -> Result<(), Error> {
+        if !self.capabilities.contains(&BackendCapability::HotReload) {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("{} does not support hot reload", self.name),
+            ));
+        }
+        self.records.lock().unwrap().updated.push(spec.clone());
+        self.check(spec)
+    }
+
+    fn exists(&self) -> bool {
+        self.installed
+    }
+
+    fn stop(&self, c: &mut Child) -> Result<(), Error> {
+        self.records.lock().unwrap().stopped.push(c.id());
+        super::terminate(c)
+    }
+
+    fn capabilities(&self) -> Vec<BackendCapability> {
+        self.capabilities.clone()
+    }
+
+    fn start_multi(&self, specs: Vec<BackendSpawnSpec>) -> Result<Vec<Child>, Error> {
+        self.start_multi_output(&specs)
+    }
+}
+
+impl MultiOutputBackend for MockBackend {
+    fn start_multi_output(&self, specs: &[BackendSpawnSpec]) -> Result<Vec<Child>, Error> {
+        let mut children = Vec::new();
+        for spec in specs {
+            match self.start(spec) {
+                Ok(child) => children.extend(child),
+                Err(e) => {
+                    // Rollback
+                    super::roll_back(self, children);
+                    return Err(e);
+                }
+            }
+        }
+        Ok(children)
+    }
+}
@@ -1,10 +1,17 @@
 mod lib;
 mod swaybg;
+mod hyprpaper;
 mod mpvpaper;
 mod awww;
+mod command;
+#[cfg(any(test, feature = "mock"))]
+mod mock;
 
 pub use lib::*;
 pub use swaybg::*;
+pub use hyprpaper::*;
 pub use mpvpaper::*;
 pub use awww::*;
-
+pub use command::*;
+#[cfg(any(test, feature = "mock"))]
+pub use mock::*;
@@ -1,2 +1,145 @@
-pub struct MpvPaperBackend;
+use std::ffi::OsString;
+use std::io::Error;
 
+use super::{BackendCapability, BackendSpawnSpec, WallpaperBackend, WallpaperMode};
+
+use tokio::process::Child;
+use log::{info, debug};
+use which::which;
+
+/// Spawns one `mpvpaper` per output, looping the media muted.
+pub struct MpvPaperBackend {
+    /// Program spawned for every output.
+    pub executable: String,
+}
+
+impl Default for MpvPaperBackend {
+    fn default() -> Self {
+        Self {
+            executable: "mpvpaper".to_string(),
+        }
+    }
+}
+
+impl MpvPaperBackend {
+    pub fn with_executable(mut self, executable: impl Into<String>) -> Self {
+        self.executable = executable.into();
+        self
+    }
+
+    /// mpv options for the given mode, followed by `extra_args` as additional mpv options.
+    /// mpvpaper takes them as one space separated string, so values with spaces are quoted.
+    pub fn mpv_options(spec: &BackendSpawnSpec) -> OsString {
+        let mut options: Vec<OsString> = vec!["loop".into(), "no-audio".into()];
+
+        match spec.mode {
+            WallpaperMode::Stretch => options.push("keepaspect=no".into()),
+            WallpaperMode::Fill => options.push("panscan=1.0".into()),
+            WallpaperMode::Center => options.push("video-unscaled=yes".into()),
+            // mpv letterboxes by default, which is what fit means.
+            WallpaperMode::Fit => {}
+            // mpv can neither tile nor show a plain colour.
+            _ => debug!("mpvpaper has no equivalent for this mode, using fit."),
+        }
+
+        for arg in &spec.extra_args {
+            let arg = arg.to_string_lossy();
+            options.push(quote_option(arg.trim_start_matches("--")).into());
+        }
+
+        options.join(" ".as_ref())
+    }
+}
+
+/// Quote the value of `key=value` the way mpv config files do,
+/// with `%len%` when it contains a double quote itself.
+fn quote_option(option: &str) -> String {
+    let Some((key, value)) = option.split_once('=') else {
+        return option.to_string();
+    };
+    if value.contains('"') {
+        format!("{}=%{}%{}", key, value.len(), value)
+    } else if value.contains(char::is_whitespace) {
+        format!("{}=\"{}\"", key, value)
+    } else {
+        option.to_string()
+    }
+}
+
+impl WallpaperBackend for MpvPaperBackend {
+    fn name(&self) -> &str {
+        "mpvpaper"
+    }
+
+    fn start(&self, spec: &BackendSpawnSpec) -> Result<Option<Child>, Error> {
+        info!("Starting mpvpaper backend.");
+
+        let argv = self.argv(spec);
+        debug!("Constructed command: {:?}", argv);
+
+        let child_proc = tokio::process::Command::new(&argv[0])
+            .args(&argv[1..])
+            .spawn()?;
+        Ok(Some(child_proc))
+    }
+
+    fn argv(&self, spec: &BackendSpawnSpec) -> Vec<OsString> {
+        vec![
+            self.executable.clone().into(),
+            "-o".into(),
+            Self::mpv_options(spec),
+            spec.output.name.clone().into(),
+            spec.media.clone().into_os_string(),
+        ]
+    }
+
+    fn exists(&self) -> bool {
+        debug!("Looking for executable `{}`", self.executable);
+        which(&self.executable).is_ok()
+    }
+
+    fn capabilities(&self) -> Vec<BackendCapability> {
+        vec![BackendCapability::Video, BackendCapability::Animated]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bg_test_support::write_script;
+    use crate::wl::OutputInfo;
+
+    #[tokio::test]
+    async fn extra_args_reach_mpvpaper_intact() {
+        let dir = tempfile::tempdir().unwrap();
+        let args_file = dir.path().join("args");
+        let script = write_script(
+            dir.path(),
+            "mpvpaper",
+            &format!("printf '%s\\n' \"$@\" > {}", args_file.display()),
+        ).unwrap();
+        let backend = MpvPaperBackend::default().with_executable(script.to_string_lossy());
+        let spec = BackendSpawnSpec {
+            media: "/videos/a b.mp4".into(),
+            mode: WallpaperMode::Fill,
+            output: OutputInfo::new("DP-1"),
+            extra_args: vec![
+                "--title=my wallpaper".into(),
+                "--speed=0.5".into(),
+                "--osd-msg1=say \"hi\"".into(),
+                "hwdec".into(),
+            ],
+        };
+
+        let mut child = backend.start(&spec).unwrap().unwrap();
+        assert!(child.wait().await.unwrap().success());
+
+        let args = std::fs::read_to_string(&args_file).unwrap();
+        assert_eq!(args.lines().collect::<Vec<_>>(), [
+            "-o",
+            "loop no-audio panscan=1.0 title=\"my wallpaper\" speed=0.5 osd-msg1=%8%say \"hi\" hwdec",
+            "DP-1",
+            "/videos/a b.mp4",
+        ]);
+    }
+}
@@ -1,7 +1,8 @@
+use std::ffi::OsString;
 use std::io::Error;
 
 use crate::backend::MultiOutputBackend;
-use super::{WallpaperBackend, BackendCapability, BackendSpawnSpec};
+use super::{WallpaperBackend, BackendCapability, BackendSpawnSpec, WallpaperMode, command_version};
 
 use tokio::process::Child;
 use log::{info, error, debug};
@@ -15,29 +16,35 @@ impl WallpaperBackend for SwaybgBackend {
         "swaybg"
     }
 
-    fn start(&self, spec: &BackendSpawnSpec) -> Result<Child, std::io::Error> {
+    fn start(&self, spec: &BackendSpawnSpec) -> Result<Option<Child>, std::io::Error> {
         info!("Starting swaybg backend.");
-        
-        let image = &spec.media;
 
-        let mut binding = tokio::process::Command::new("swaybg");
-        let command = binding
-            .arg("-o")
-            .arg(&spec.output.name)
-            .arg("-i")
-            .arg(image)
-            ;
-        let cmd_std = &command.as_std();
-        let cmd = format!("{}", vec![
-            cmd_std.get_program().to_str().unwrap(),
-              cmd_std.get_args()
-                  .collect::<Vec<_>>()
-              .join(" ".as_ref()).to_str().unwrap()].join(" "));
+        let argv = self.argv(spec);
+        debug!("Constructed command: {:?}", argv);
 
-        debug!("Constructed command: {}", cmd);
+        let child_proc = tokio::process::Command::new(&argv[0])
+            .args(&argv[1..])
+            .spawn()?;
+        Ok(Some(child_proc))
+    }
+
+    fn argv(&self, spec: &BackendSpawnSpec) -> Vec<OsString> {
+        let mut argv: Vec<OsString> = vec![
+            "swaybg".into(),
+            "-o".into(),
+            spec.output.name.clone().into(),
+            "-m".into(),
+            spec.mode.to_string().into(),
+        ];
+        match &spec.mode {
+            WallpaperMode::SolidColor(color) => argv.extend(["-c".into(), color.into()]),
+            _ => argv.extend(["-i".into(), spec.media.clone().into_os_string()]),
+        }
+        argv
+    }
 
-        let child_proc = command.spawn()?;
-        Ok(child_proc)
+    fn version(&self) -> Option<String> {
+        command_version("swaybg", &["-v"])
     }
 
     fn exists(&self) -> bool {
@@ -50,7 +57,7 @@ impl WallpaperBackend for SwaybgBackend {
     }
 
     fn start_multi(&self, specs: Vec<BackendSpawnSpec>) -> Result<Vec<Child>, Error> {
-        self.start_multi_output(&*specs)
+        self.start_multi_output(&specs)
     }
 }
 
@@ -59,13 +66,12 @@ impl MultiOutputBackend for SwaybgBackend {
         let mut children = Vec::new();
         for spec in specs {
             match self.start(spec) {
-                Ok(child) => children.push(child),
+                Ok(Some(child)) => children.push(child),
+                Ok(None) => {}
                 Err(e) => {
                     error!("Error starting swaybg: {}", e);
                     // Rollback
-                    for mut c in children {
-                        let _ = self.stop(&mut c);
-                    }
+                    super::roll_back(self, children);
                     return Err(e);
                 }
             }
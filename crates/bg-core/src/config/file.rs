@@ -0,0 +1,435 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use indexmap::IndexMap;
+use log::info;
+use serde::Deserialize;
+use crate::backend::{AwwwTransition, AwwwTransitionOptions, BackendCapability, CommandTemplateConfig, WallpaperMode, DEFAULT_STOP_GRACE};
+use crate::layout::{Layout, SpanConfig};
+use crate::media::ScanConfig;
+use crate::orchestrator::{AspectMatch, DEFAULT_SWAP_DELAY, AssignConfig, AssignStrategy, OutputRule, RotationConfig, RotationScope, SessionConfig, SupervisorConfig};
+use crate::{Error, Result};
+
+/// `$XDG_CONFIG_HOME/bg-settings/config.toml`, e.g.
+///
+/// ```toml
+/// backend = "swaybg"
+/// mode = "fill"
+/// media = ["~/Pictures/wallpapers"]
+/// recursive = true
+/// interval = 30
+/// layout = "span"
+/// bezel = 40
+/// assign = "cycle"
+/// aspect = "prefer"
+/// aspect_tolerance = 0.1
+/// seed = 42
+/// history = 100
+/// stop_timeout = 3
+/// max_restarts = 5
+/// swap_delay = 500
+///
+/// [backends.wbg]
+/// command = "wbg {media}"
+///
+/// [backends.awww]
+/// transition = "wipe"
+/// transition_duration = 1.5
+///
+/// [outputs.DP-2]
+/// media = "~/Pictures/portrait"
+/// mode = "fit"
+/// interval = 10
+///
+/// [outputs."Dell Inc. DELL U2720Q"]
+/// backend = "hyprpaper"
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub backend: Option<String>,
+    pub mode: Option<ModeName>,
+    /// `#rrggbb`, implies `mode = "solid_color"` if no mode is given.
+    pub color: Option<String>,
+    pub media: MediaPaths,
+    pub recursive: bool,
+    pub max_recurse_depth: Option<isize>,
+    /// Minutes between rotations.
+    pub interval: Option<u64>,
+    pub per_output: bool,
+    pub layout: LayoutName,
+    /// Logical pixels hidden between neighbouring outputs with `layout = "span"`.
+    pub bezel: u32,
+    pub assign: AssignStrategy,
+    /// Pick media shaped like the output.
+    pub aspect: AspectMatch,
+    pub aspect_tolerance: Option<f64>,
+    /// Fixed seed for random picks, the same library then gets the same wallpapers.
+    pub seed: Option<u64>,
+    /// Media remembered per output so random picks do not repeat, 0 disables it.
+    pub history: Option<usize>,
+    /// Seconds a backend may take to exit before it is killed.
+    pub stop_timeout: Option<u64>,
+    /// Restarts of a crashing backend within a minute before giving up, 0 never restarts.
+    pub max_restarts: Option<u32>,
+    /// Milliseconds a new backend gets to show up before the one it replaces is stopped.
+    pub swap_delay: Option<u64>,
+    /// Settings of the built-in backends and custom command backends by name.
+    pub backends: Backends,
+    /// Overrides keyed by output name, or `make model` / description as shown by `list output`.
+    /// Kept in file order, see `SessionConfig::rules`.
+    pub outputs: IndexMap<String, OutputEntry>,
+    /// Directory of the file, relative media paths start there.
+    #[serde(skip)]
+    pub dir: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModeName {
+    Stretch,
+    Fit,
+    Fill,
+    Center,
+    Tile,
+    #[serde(alias = "solid-color")]
+    SolidColor,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LayoutName {
+    #[default]
+    Independent,
+    Span,
+}
+
+/// A single path or a list of paths.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum MediaPaths {
+    One(PathBuf),
+    Many(Vec<PathBuf>),
+}
+
+impl Default for MediaPaths {
+    fn default() -> Self {
+        MediaPaths::Many(Vec::new())
+    }
+}
+
+impl MediaPaths {
+    /// With `~` expanded and relative paths joined to `dir`.
+    fn expanded(self, dir: Option<&Path>) -> Vec<PathBuf> {
+        let paths = match self {
+            MediaPaths::One(path) => vec![path],
+            MediaPaths::Many(paths) => paths,
+        };
+        paths.into_iter().map(expand_home).map(|path| match dir {
+            Some(dir) if path.is_relative() => dir.join(path),
+            _ => path,
+        }).collect()
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Backends {
+    pub awww: Option<AwwwEntry>,
+    /// Any other name declares a custom command backend.
+    #[serde(flatten)]
+    pub custom: BTreeMap<String, BackendEntry>,
+}
+
+/// Transition of `awww img`, unset fields keep awww's defaults.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AwwwEntry {
+    pub transition: Option<AwwwTransition>,
+    /// Seconds.
+    pub transition_duration: Option<f32>,
+    pub transition_fps: Option<u32>,
+    pub transition_step: Option<u8>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BackendEntry {
+    pub command: String,
+    pub executable: Option<String>,
+    pub capabilities: Option<Vec<BackendCapability>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputEntry {
+    pub media: Option<MediaPaths>,
+    pub mode: Option<ModeName>,
+    pub color: Option<String>,
+    pub backend: Option<String>,
+    /// Minutes between rotations of this output.
+    pub interval: Option<u64>,
+}
+
+impl Config {
+    /// `$XDG_CONFIG_HOME/bg-settings/config.toml`, falling back to `~/.config`.
+    pub fn default_path() -> Option<PathBuf> {
+        let config_home = env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+        Some(config_home.join("bg-settings").join("config.toml"))
+    }
+
+    /// Load the config at the default path, `None` if there is no such file.
+    pub fn load_default() -> Result<Option<Config>> {
+        match Self::default_path() {
+            Some(path) if path.exists() => Self::load(&path).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    /// Parse errors are reported as `path:line:column: message`.
+    pub fn load(path: &Path) -> Result<Config> {
+        let content = std::fs::read_to_string(path).map_err(|e| match e.kind() {
+            ErrorKind::NotFound => Error::Config(format!("{}: config file not found", path.display())),
+            _ => Error::Config(format!("{}: {}", path.display(), e)),
+        })?;
+        info!("Loading config {}", path.display());
+
+        let mut config: Config = toml::from_str(&content).map_err(|e| match e.span() {
+            Some(span) => {
+                let (line, column) = line_column(&content, span.start);
+                Error::Config(format!("{}:{}:{}: {}", path.display(), line, column, e.message()))
+            }
+            None => Error::Config(format!("{}: {}", path.display(), e.message())),
+        })?;
+        config.dir = path.parent().map(Path::to_path_buf);
+        Ok(config)
+    }
+
+    pub fn into_session_config(self) -> Result<SessionConfig> {
+        let mode = to_mode(self.mode, self.color)?.unwrap_or(WallpaperMode::Fit);
+
+        let dir = self.dir.as_deref();
+        let rules = self.outputs.into_iter().map(|(output, entry)| {
+            Ok(OutputRule {
+                mode: to_mode(entry.mode, entry.color)
+                    .map_err(|e| Error::Config(format!("[outputs.{}]: {}", output, e)))?,
+                media_paths: entry.media.map(|media| media.expanded(dir)),
+                backend: entry.backend,
+                interval: entry.interval.map(minutes).transpose()
+                    .map_err(|e| Error::Config(format!("[outputs.{}]: {}", output, e)))?,
+                output,
+            })
+        }).collect::<Result<Vec<_>>>()?;
+
+        let awww = match self.backends.awww {
+            Some(entry) => AwwwTransitionOptions {
+                kind: entry.transition,
+                duration: entry.transition_duration
+                    .map(Duration::try_from_secs_f32)
+                    .transpose()
+                    .map_err(|e| Error::Config(format!("[backends.awww]: transition_duration: {}", e)))?,
+                fps: entry.transition_fps,
+                step: entry.transition_step,
+            },
+            None => AwwwTransitionOptions::default(),
+        };
+
+        let custom_backends = self.backends.custom.into_iter().map(|(name, entry)| CommandTemplateConfig {
+            name: Some(name),
+            command: entry.command,
+            executable: entry.executable,
+            capabilities: entry.capabilities,
+        }).collect();
+
+        Ok(SessionConfig {
+            media_paths: self.media.expanded(dir),
+            scan: ScanConfig {
+                recurse: self.recursive,
+                max_recurses: self.max_recurse_depth.unwrap_or(-1),
+            },
+            backend: self.backend,
+            outputs: None,
+            mode,
+            custom_backends,
+            rotation: self.interval.map(minutes).transpose()?.map(|interval| RotationConfig {
+                scope: if self.per_output { RotationScope::PerOutput } else { RotationScope::Global },
+                ..RotationConfig::every(interval)
+            }),
+            rules,
+            assign: AssignConfig {
+                strategy: self.assign,
+                aspect: self.aspect,
+                tolerance: self.aspect_tolerance.unwrap_or(AssignConfig::default().tolerance),
+                history: self.history.unwrap_or(AssignConfig::default().history),
+                ..AssignConfig::default()
+            },
+            awww,
+            layout: match self.layout {
+                LayoutName::Independent => Layout::Independent,
+                LayoutName::Span => Layout::Span(SpanConfig { bezel: self.bezel, cache_dir: None }),
+            },
+            seed: self.seed,
+            stop_grace: self.stop_timeout.map(Duration::from_secs).unwrap_or(DEFAULT_STOP_GRACE),
+            swap_delay: self.swap_delay.map(Duration::from_millis).unwrap_or(DEFAULT_SWAP_DELAY),
+            supervise: SupervisorConfig {
+                max_restarts: self.max_restarts.unwrap_or(SupervisorConfig::default().max_restarts),
+                ..SupervisorConfig::default()
+            },
+        })
+    }
+}
+
+/// A colour alone implies `solid_color`, other modes take no colour.
+fn to_mode(mode: Option<ModeName>, color: Option<String>) -> Result<Option<WallpaperMode>> {
+    Ok(match (mode, color) {
+        (Some(mode), Some(_)) if mode != ModeName::SolidColor => {
+            return Err(Error::Config("color requires mode solid_color".to_string()));
+        }
+        (Some(ModeName::Stretch), _) => Some(WallpaperMode::Stretch),
+        (Some(ModeName::Fit), _) => Some(WallpaperMode::Fit),
+        (Some(ModeName::Fill), _) => Some(WallpaperMode::Fill),
+        (Some(ModeName::Center), _) => Some(WallpaperMode::Center),
+        (Some(ModeName::Tile), _) => Some(WallpaperMode::Tile),
+        (Some(ModeName::SolidColor) | None, Some(color)) => Some(WallpaperMode::solid_color(color)?),
+        (Some(ModeName::SolidColor), None) => return Err(Error::Config("mode solid_color requires color".to_string())),
+        (None, None) => None,
+    })
+}
+
+/// A rotation interval given in minutes.
+pub fn minutes(minutes: u64) -> Result<Duration> {
+    minutes.checked_mul(60)
+        .map(Duration::from_secs)
+        .ok_or_else(|| Error::Config(format!("interval of {} minutes is too long", minutes)))
+}
+
+fn expand_home(path: PathBuf) -> PathBuf {
+    match (path.strip_prefix("~"), env::var_os("HOME")) {
+        (Ok(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => path,
+    }
+}
+
+/// 1-based line and column of a byte offset.
+fn line_column(content: &str, offset: usize) -> (usize, usize) {
+    let before = &content[..offset.min(content.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.rfind('\n').map_or(before.len(), |i| before.len() - i - 1) + 1;
+    (line, column)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(content: &str) -> SessionConfig {
+        toml::from_str::<Config>(content).unwrap().into_session_config().unwrap()
+    }
+
+    fn parse_err(content: &str) -> String {
+        toml::from_str::<Config>(content).unwrap().into_session_config().unwrap_err().to_string()
+    }
+
+    #[test]
+    fn color_must_be_rrggbb() {
+        assert_eq!(parse(r##"color = "#1E1e2e""##).mode, WallpaperMode::SolidColor("#1E1e2e".to_string()));
+        assert!(parse_err(r#"color = "red""#).contains("expected #rrggbb"));
+        assert!(parse_err(r##"color = "#12345""##).contains("expected #rrggbb"));
+    }
+
+    #[test]
+    fn bad_modes_are_config_errors() {
+        let config: Config = toml::from_str(r#"mode = "solid_color""#).unwrap();
+        assert!(matches!(config.into_session_config(), Err(Error::Config(_))));
+        assert!(matches!(WallpaperMode::solid_color("red"), Err(Error::Config(_))));
+    }
+
+    #[test]
+    fn color_with_other_mode_is_rejected() {
+        let err = parse_err(r##"
+            [outputs.DP-1]
+            mode = "fill"
+            color = "#000000"
+        "##);
+        assert!(err.contains("[outputs.DP-1]: color requires mode solid_color"), "{}", err);
+    }
+
+    #[test]
+    fn awww_table_sets_transition_next_to_custom_backends() {
+        let config = parse(r#"
+            [backends.awww]
+            transition = "wipe"
+            transition_duration = 1.5
+
+            [backends.wbg]
+            command = "wbg {media}"
+        "#);
+
+        assert_eq!(config.awww, AwwwTransitionOptions {
+            kind: Some(AwwwTransition::Wipe),
+            duration: Some(Duration::from_millis(1500)),
+            fps: None,
+            step: None,
+        });
+        assert_eq!(config.custom_backends.len(), 1);
+        assert_eq!(config.custom_backends[0].name.as_deref(), Some("wbg"));
+    }
+
+    #[test]
+    fn output_rules_keep_file_order() {
+        let config = parse(r#"
+            [outputs.HDMI-A-1]
+            mode = "fill"
+
+            [outputs."Acme Panel"]
+            mode = "center"
+
+            [outputs.DP-1]
+            mode = "tile"
+        "#);
+
+        let outputs: Vec<&str> = config.rules.iter().map(|rule| rule.output.as_str()).collect();
+        assert_eq!(outputs, ["HDMI-A-1", "Acme Panel", "DP-1"]);
+    }
+
+    #[test]
+    fn relative_media_starts_at_the_config_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, r#"
+            media = ["wallpapers", "/srv/wallpapers"]
+
+            [outputs.DP-1]
+            media = "portrait"
+        "#).unwrap();
+
+        let config = Config::load(&path).unwrap().into_session_config().unwrap();
+
+        assert_eq!(config.media_paths, [dir.path().join("wallpapers"), PathBuf::from("/srv/wallpapers")]);
+        assert_eq!(config.rules[0].media_paths, Some(vec![dir.path().join("portrait")]));
+    }
+
+    #[test]
+    fn malformed_file_reports_its_location() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, "backend = \"swaybg\"\ninterval = ten\n").unwrap();
+
+        let err = Config::load(&path).unwrap_err();
+        assert!(matches!(err, Error::Config(_)));
+        assert!(err.to_string().starts_with(&format!("{}:2:12: ", path.display())), "{}", err);
+    }
+
+    #[test]
+    fn overlong_interval_is_rejected() {
+        assert_eq!(minutes(2).unwrap(), Duration::from_secs(120));
+        assert!(matches!(minutes(u64::MAX), Err(Error::Config(_))));
+        assert!(parse_err(&format!("interval = {}", i64::MAX)).contains("too long"));
+        let err = parse_err(&format!("[outputs.DP-1]\ninterval = {}", i64::MAX));
+        assert!(err.starts_with("[outputs.DP-1]: "), "{}", err);
+    }
+}
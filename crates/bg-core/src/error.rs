@@ -0,0 +1,151 @@
+use std::fmt::{Display, Formatter};
+use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Everything that can go wrong in bg-core.
+/// Library code reports these instead of exiting, the caller decides what to do.
+#[derive(Debug)]
+pub enum Error {
+    /// `WAYLAND_DISPLAY` is unset or the compositor cannot be reached.
+    NoWaylandConnection(wayland_client::ConnectError),
+    /// The connection broke while talking to the compositor.
+    Wayland(wayland_client::DispatchError),
+    NoBackendAvailable,
+    /// The requested backend is unknown or not installed.
+    BackendNotFound(String),
+    NoOutputsMatched,
+    /// The output advertised no mode, so its size is unknown.
+    OutputWithoutMode(String),
+    NoMediaPath,
+    /// None of the scanned media can be shown by the backend.
+    NoMediaFound { backend: String },
+    /// The assignment strategy left these outputs without media.
+    NotEnoughMedia { outputs: Vec<String> },
+    SpawnFailed { backend: String, source: std::io::Error },
+    UpdateFailed { backend: String, source: std::io::Error },
+    StopFailed { backend: String, source: std::io::Error },
+    ScanIo { path: PathBuf, source: std::io::Error },
+    /// An image could not be decoded or written.
+    Image { path: PathBuf, source: image::ImageError },
+    CacheIo { path: PathBuf, source: std::io::Error },
+    /// The shuffle history or the last session could not be read or written.
+    StateIo { path: PathBuf, source: std::io::Error },
+    InvalidCommand { command: String, source: std::io::Error },
+    /// The config file is missing or malformed, the message carries its location.
+    Config(String),
+    NoSession,
+    /// Another orchestrator holds the lock of this Wayland display.
+    AlreadyRunning { pid: Option<u32> },
+    Lock { path: PathBuf, source: std::io::Error },
+    /// `restore` found no saved session.
+    NoLastSession,
+    Ipc(std::io::Error),
+    /// A signal handler could not be installed.
+    Signal(std::io::Error),
+    /// The thread following output hotplug could not be started.
+    Watcher(std::io::Error),
+}
+
+/// What went wrong, coarse enough to survive the trip from the daemon to the client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    Wayland,
+    NoBackend,
+    BackendNotFound,
+    Outputs,
+    Media,
+    Backend,
+    Scan,
+    Config,
+    Session,
+    Storage,
+    Instance,
+    Signal,
+}
+
+impl Error {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::NoWaylandConnection(_) | Error::Wayland(_) | Error::Watcher(_) => ErrorKind::Wayland,
+            Error::NoBackendAvailable => ErrorKind::NoBackend,
+            Error::BackendNotFound(_) => ErrorKind::BackendNotFound,
+            Error::NoOutputsMatched | Error::OutputWithoutMode(_) => ErrorKind::Outputs,
+            Error::NoMediaPath | Error::NoMediaFound { .. } | Error::NotEnoughMedia { .. } => ErrorKind::Media,
+            Error::SpawnFailed { .. } | Error::UpdateFailed { .. } | Error::StopFailed { .. } => ErrorKind::Backend,
+            Error::ScanIo { .. } => ErrorKind::Scan,
+            Error::Config(_) | Error::InvalidCommand { .. } => ErrorKind::Config,
+            Error::NoSession | Error::NoLastSession | Error::Ipc(_) => ErrorKind::Session,
+            Error::Image { .. } | Error::CacheIo { .. } | Error::StateIo { .. } => ErrorKind::Storage,
+            Error::AlreadyRunning { .. } | Error::Lock { .. } => ErrorKind::Instance,
+            Error::Signal(_) => ErrorKind::Signal,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::NoWaylandConnection(e) => write!(f, "Failed to connect to Wayland: {}", e),
+            Error::Wayland(e) => write!(f, "Wayland error: {}", e),
+            Error::NoBackendAvailable => write!(f, "No available backend found"),
+            Error::BackendNotFound(name) => write!(f, "Backend {} not found", name),
+            Error::NoOutputsMatched => write!(f, "No outputs selected"),
+            Error::OutputWithoutMode(name) => write!(f, "Output {} reports no mode", name),
+            Error::NoMediaPath => write!(f, "No media path provided"),
+            Error::NoMediaFound { backend } => write!(f, "No media supported by {} found", backend),
+            Error::NotEnoughMedia { outputs } => write!(f, "Not enough media for outputs {}", outputs.join(", ")),
+            Error::SpawnFailed { backend, source } => write!(f, "Failed to start {}: {}", backend, source),
+            Error::UpdateFailed { backend, source } => write!(f, "Failed to update {}: {}", backend, source),
+            Error::StopFailed { backend, source } => write!(f, "Failed to stop {}: {}", backend, source),
+            Error::ScanIo { path, source } => write!(f, "Error scanning media in {}: {}", path.display(), source),
+            Error::Image { path, source } => write!(f, "Image {}: {}", path.display(), source),
+            Error::CacheIo { path, source } => write!(f, "Cache {}: {}", path.display(), source),
+            Error::StateIo { path, source } => write!(f, "State {}: {}", path.display(), source),
+            Error::InvalidCommand { command, source } => write!(f, "Invalid custom command `{}`: {}", command, source),
+            Error::Config(message) => write!(f, "{}", message),
+            Error::NoSession => write!(f, "No wallpaper session running"),
+            Error::AlreadyRunning { pid: Some(pid) } => write!(f, "bg-settings is already running on this display (pid {})", pid),
+            Error::AlreadyRunning { pid: None } => write!(f, "bg-settings is already running on this display"),
+            Error::Lock { path, source } => write!(f, "Lock {}: {}", path.display(), source),
+            Error::NoLastSession => write!(f, "No previous wallpaper session to restore"),
+            Error::Ipc(e) => write!(f, "IPC error: {}", e),
+            Error::Signal(e) => write!(f, "Cannot listen for signals: {}", e),
+            Error::Watcher(e) => write!(f, "Cannot watch outputs: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::NoWaylandConnection(e) => Some(e),
+            Error::Wayland(e) => Some(e),
+            Error::SpawnFailed { source, .. }
+            | Error::UpdateFailed { source, .. }
+            | Error::StopFailed { source, .. }
+            | Error::ScanIo { source, .. }
+            | Error::CacheIo { source, .. }
+            | Error::StateIo { source, .. }
+            | Error::Lock { source, .. }
+            | Error::InvalidCommand { source, .. } => Some(source),
+            Error::Image { source, .. } => Some(source),
+            Error::Ipc(e) | Error::Signal(e) | Error::Watcher(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<wayland_client::ConnectError> for Error {
+    fn from(e: wayland_client::ConnectError) -> Self {
+        Error::NoWaylandConnection(e)
+    }
+}
+
+impl From<wayland_client::DispatchError> for Error {
+    fn from(e: wayland_client::DispatchError) -> Self {
+        Error::Wayland(e)
+    }
+}
@@ -0,0 +1,149 @@
+use std::env;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use log::info;
+use tokio::time::Instant;
+use crate::backend::send_signal;
+use crate::{Error, Result};
+
+/// How long `InstanceLock::replace` waits for the previous instance to stop its backends and exit.
+pub const REPLACE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// `WAYLAND_DISPLAY`, `wayland-0` if unset like libwayland does.
+pub fn wayland_display() -> String {
+    env::var("WAYLAND_DISPLAY").unwrap_or_else(|_| "wayland-0".to_string())
+}
+
+/// `$XDG_RUNTIME_DIR/bg-settings-<display>.<extension>`, or the temp dir if unset.
+pub fn runtime_path(display: &str, extension: &str) -> PathBuf {
+    // The display may be given as an absolute socket path.
+    let display = Path::new(display).file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or(display.to_string());
+    env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(env::temp_dir)
+        .join(format!("bg-settings-{}.{}", display, extension))
+}
+
+/// `$XDG_RUNTIME_DIR/bg-settings-<display>.lock`, see `runtime_path`.
+pub fn lock_path() -> PathBuf {
+    runtime_path(&wayland_display(), "lock")
+}
+
+/// Pid written into the lock file by the instance holding it.
+pub fn lock_holder() -> Option<u32> {
+    std::fs::read_to_string(lock_path()).ok()?.trim().parse().ok()
+}
+
+/// Held by the one orchestrator running on a Wayland display, released when dropped or on exit.
+#[derive(Debug)]
+pub struct InstanceLock {
+    #[allow(dead_code)]
+    file: File,
+    path: PathBuf,
+}
+
+impl InstanceLock {
+    /// Fails with `AlreadyRunning` if another orchestrator holds the lock of this display.
+    pub fn acquire() -> Result<Self> {
+        Self::acquire_at(&lock_path())
+    }
+
+    pub fn acquire_at(path: &Path) -> Result<Self> {
+        let lock_io = |source| Error::Lock { path: path.to_path_buf(), source };
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(lock_io)?;
+
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let pid = std::fs::read_to_string(path).ok().and_then(|pid| pid.trim().parse().ok());
+                return Err(Error::AlreadyRunning { pid });
+            }
+            Err(TryLockError::Error(e)) => return Err(lock_io(e)),
+        }
+
+        file.set_len(0).map_err(lock_io)?;
+        write!(file, "{}", std::process::id()).map_err(lock_io)?;
+        Ok(Self { file, path: path.to_path_buf() })
+    }
+
+    /// Take the lock, asking the instance holding it to exit first.
+    pub async fn replace() -> Result<Self> {
+        let path = lock_path();
+        let pid = match Self::acquire_at(&path) {
+            Err(Error::AlreadyRunning { pid: Some(pid) }) => pid,
+            other => return other,
+        };
+
+        info!("Stopping the running instance (pid {})", pid);
+        send_signal(pid, libc::SIGTERM).map_err(|source| Error::Lock { path: path.clone(), source })?;
+
+        let deadline = Instant::now() + REPLACE_TIMEOUT;
+        loop {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            match Self::acquire_at(&path) {
+                Err(Error::AlreadyRunning { .. }) if Instant::now() < deadline => continue,
+                other => return other,
+            }
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The lock file of `display`, in `dir` instead of the runtime dir.
+    fn lock_in(dir: &Path, display: &str) -> PathBuf {
+        dir.join(runtime_path(display, "lock").file_name().unwrap())
+    }
+
+    #[test]
+    fn display_is_keyed_by_its_socket_name() {
+        assert_eq!(runtime_path("/run/user/1000/wayland-1", "lock"), runtime_path("wayland-1", "lock"));
+        assert_ne!(runtime_path("wayland-1", "lock"), runtime_path("wayland-2", "lock"));
+        assert_eq!(runtime_path("wayland-1", "sock").file_name().unwrap(), "bg-settings-wayland-1.sock");
+    }
+
+    #[test]
+    fn second_lock_on_the_same_display_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = lock_in(dir.path(), "wayland-1");
+
+        let _held = InstanceLock::acquire_at(&path).unwrap();
+        let err = InstanceLock::acquire_at(&path).unwrap_err();
+
+        assert!(matches!(err, Error::AlreadyRunning { pid: Some(pid) } if pid == std::process::id()), "{}", err);
+    }
+
+    #[test]
+    fn lock_on_another_display_succeeds() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let _held = InstanceLock::acquire_at(&lock_in(dir.path(), "wayland-1")).unwrap();
+        let other = InstanceLock::acquire_at(&lock_in(dir.path(), "wayland-2")).unwrap();
+
+        assert_eq!(std::fs::read_to_string(other.path()).unwrap(), std::process::id().to_string());
+    }
+
+    #[test]
+    fn lock_is_released_when_dropped() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = lock_in(dir.path(), "wayland-1");
+
+        drop(InstanceLock::acquire_at(&path).unwrap());
+
+        InstanceLock::acquire_at(&path).unwrap();
+    }
+}
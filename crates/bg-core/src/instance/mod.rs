@@ -0,0 +1,5 @@
+mod lock;
+mod stray;
+
+pub use lock::*;
+pub use stray::*;
@@ -0,0 +1,148 @@
+use std::path::Path;
+use std::time::Duration;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+use crate::backend::{send_signal, StopOutcome};
+use crate::instance::wayland_display;
+
+/// Wallpaper programs whose processes may linger from other tools or earlier runs.
+pub const WALLPAPER_PROGRAMS: &[&str] = &["swaybg", "hyprpaper", "mpvpaper", "awww-daemon", "swww-daemon"];
+
+/// A wallpaper process on this display that the running orchestrator did not start.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StrayProcess {
+    pub pid: u32,
+    pub name: String,
+}
+
+/// Processes of `WALLPAPER_PROGRAMS` drawing on this Wayland display,
+/// except the children of `parents`. Processes of other users cannot be inspected and are skipped.
+pub fn find_strays(parents: &[u32]) -> Vec<StrayProcess> {
+    find_strays_on(&wayland_display(), parents)
+}
+
+/// Like `find_strays`, on `display` instead of `WAYLAND_DISPLAY`.
+pub fn find_strays_on(display: &str, parents: &[u32]) -> Vec<StrayProcess> {
+    let Ok(entries) = std::fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+
+    let mut strays: Vec<StrayProcess> = entries
+        .flatten()
+        .filter_map(|entry| entry.file_name().to_str()?.parse::<u32>().ok())
+        .filter_map(|pid| {
+            let dir = Path::new("/proc").join(pid.to_string());
+            let name = std::fs::read_to_string(dir.join("comm")).ok()?.trim().to_string();
+            if !WALLPAPER_PROGRAMS.contains(&name.as_str()) {
+                return None;
+            }
+            if parent(&dir).is_some_and(|ppid| parents.contains(&ppid)) {
+                return None;
+            }
+            let environ = std::fs::read(dir.join("environ")).ok()?;
+            let process_display = environ.split(|b| *b == 0)
+                .find_map(|var| var.strip_prefix(b"WAYLAND_DISPLAY="))
+                .map(|d| String::from_utf8_lossy(d).into_owned())
+                .unwrap_or_else(|| "wayland-0".to_string());
+            (process_display == display).then_some(StrayProcess { pid, name })
+        })
+        .collect();
+    strays.sort_by_key(|s| s.pid);
+    strays
+}
+
+/// The parent pid, from `/proc/<pid>/stat`.
+fn parent(dir: &Path) -> Option<u32> {
+    let stat = std::fs::read_to_string(dir.join("stat")).ok()?;
+    // `pid (comm) state ppid ...`, comm may contain spaces and parentheses.
+    stat.rsplit_once(')')?.1.split_whitespace().nth(1)?.parse().ok()
+}
+
+/// SIGTERM the strays, SIGKILL the ones still running after `grace`.
+pub async fn terminate_strays(strays: Vec<StrayProcess>, grace: Duration) -> Vec<(StrayProcess, StopOutcome)> {
+    let mut pending = Vec::new();
+    let mut stopped = Vec::new();
+    for stray in strays {
+        info!("Stopping stray {} (pid {})", stray.name, stray.pid);
+        match send_signal(stray.pid, libc::SIGTERM) {
+            Ok(()) => pending.push(stray),
+            Err(e) => warn!("Failed stopping {} (pid {}): {}", stray.name, stray.pid, e),
+        }
+    }
+
+    let deadline = Instant::now() + grace;
+    while !pending.is_empty() && Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let (gone, running): (Vec<_>, Vec<_>) = pending.into_iter().partition(|s| !is_running(s.pid));
+        stopped.extend(gone.into_iter().map(|s| (s, StopOutcome::Terminated)));
+        pending = running;
+    }
+
+    for stray in pending {
+        warn!("{} (pid {}) still running after {:?}, killing it", stray.name, stray.pid, grace);
+        match send_signal(stray.pid, libc::SIGKILL) {
+            Ok(()) => stopped.push((stray, StopOutcome::Killed)),
+            Err(e) => warn!("Failed killing {} (pid {}): {}", stray.name, stray.pid, e),
+        }
+    }
+    stopped
+}
+
+/// Whether `pid` exists and is not a zombie waiting for its parent.
+fn is_running(pid: u32) -> bool {
+    let Ok(stat) = std::fs::read_to_string(format!("/proc/{}/stat", pid)) else {
+        return false;
+    };
+    stat.rsplit_once(')').and_then(|(_, rest)| rest.split_whitespace().next()) != Some("Z")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::{Child, Command};
+    use bg_test_support::write_script;
+
+    /// A `swaybg` standing in on `display`, the shell keeps the name while it sleeps.
+    fn swaybg(dir: &Path, display: &str) -> Child {
+        let script = write_script(dir, "swaybg", "sleep 60; true").unwrap();
+        Command::new(script).env("WAYLAND_DISPLAY", display).spawn().unwrap()
+    }
+
+    /// Wait for the shell to be listed under its own name.
+    fn wait_for(display: &str, pid: u32) {
+        for _ in 0..500 {
+            if find_strays_on(display, &[]).iter().any(|s| s.pid == pid) {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        panic!("pid {} never showed up as a stray on {}", pid, display);
+    }
+
+    #[tokio::test]
+    async fn only_strays_on_this_display_are_found_and_stopped() {
+        let dir = tempfile::tempdir().unwrap();
+        let display = format!("bg-test-{}", std::process::id());
+        let other_display = format!("{}-other", display);
+        let mut ours = swaybg(dir.path(), &display);
+        let mut other = swaybg(dir.path(), &other_display);
+        wait_for(&display, ours.id());
+        wait_for(&other_display, other.id());
+
+        let strays = find_strays_on(&display, &[]);
+        let pids: Vec<u32> = strays.iter().map(|s| s.pid).collect();
+        assert_eq!(pids, [ours.id()]);
+        assert_eq!(strays[0].name, "swaybg");
+        assert!(find_strays_on(&display, &[std::process::id()]).is_empty());
+
+        let stopped = terminate_strays(strays, Duration::from_secs(5)).await;
+        assert_eq!(stopped.len(), 1);
+        assert_eq!(stopped[0].1, StopOutcome::Terminated);
+        assert!(ours.wait().is_ok());
+        assert!(is_running(other.id()));
+
+        other.kill().unwrap();
+        other.wait().unwrap();
+    }
+}
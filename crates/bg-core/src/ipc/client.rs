@@ -0,0 +1,43 @@
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use log::{debug, warn};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::UnixStream;
+use crate::ipc::{socket_path, Command, Reply, Request, Response, PROTOCOL_VERSION};
+use crate::Result;
+
+/// Send a command to the daemon on the default socket.
+pub async fn send(command: Command) -> Result<Reply> {
+    send_to(&socket_path(), command).await
+}
+
+pub async fn send_to(socket: &Path, command: Command) -> Result<Reply> {
+    exchange(socket, command).await.map_err(crate::Error::Ipc)
+}
+
+async fn exchange(socket: &Path, command: Command) -> std::io::Result<Reply> {
+    let stream = UnixStream::connect(socket).await?;
+    let (read, mut write) = stream.into_split();
+
+    let mut line = serde_json::to_string(&Request::new(command))?;
+    debug!("Sending request: {}", line);
+    line.push('\n');
+    write.write_all(line.as_bytes()).await?;
+
+    let mut reply = String::new();
+    BufReader::new(read).read_line(&mut reply).await?;
+    if reply.is_empty() {
+        return Err(Error::new(ErrorKind::UnexpectedEof, "Daemon closed the connection"));
+    }
+
+    let response: Response = serde_json::from_str(&reply)?;
+    if response.version != PROTOCOL_VERSION {
+        warn!("Daemon speaks protocol version {}, expected {}", response.version, PROTOCOL_VERSION);
+    }
+    Ok(response.reply)
+}
+
+/// Whether a daemon is listening on the default socket.
+pub async fn is_running() -> bool {
+    UnixStream::connect(socket_path()).await.is_ok()
+}
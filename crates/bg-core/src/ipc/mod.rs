@@ -0,0 +1,7 @@
+mod protocol;
+mod server;
+mod client;
+
+pub use protocol::*;
+pub use server::*;
+pub use client::*;
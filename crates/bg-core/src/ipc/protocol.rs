@@ -0,0 +1,65 @@
+use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use crate::ErrorKind;
+use crate::instance::{runtime_path, wayland_display};
+use crate::orchestrator::{LastSession, SessionConfig, SessionStatus};
+
+/// Bumped whenever a request or response changes incompatibly.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// `$XDG_RUNTIME_DIR/bg-settings-<display>.sock`, one daemon per Wayland display like the lock.
+pub fn socket_path() -> PathBuf {
+    runtime_path(&wayland_display(), "sock")
+}
+
+/// One JSON object per line, e.g. `{"version":2,"command":"next","outputs":null}`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Request {
+    pub version: u32,
+    #[serde(flatten)]
+    pub command: Command,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Command {
+    /// Replace the running session.
+    Set(Box<SessionConfig>),
+    /// Show new media, only on `outputs` if given.
+    Next { outputs: Option<Vec<String>> },
+    Status,
+    /// Stop all backends and exit the daemon.
+    Stop,
+    /// Rescan media and set up every output again.
+    Reload,
+    /// Replace the running session with one showing what `LastSession` showed.
+    Restore(Box<LastSession>),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Response {
+    pub version: u32,
+    #[serde(flatten)]
+    pub reply: Reply,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum Reply {
+    Ok,
+    Status { session: Option<SessionStatus> },
+    /// `kind` lets the client exit as it would have run the command itself.
+    Error { kind: ErrorKind, message: String },
+}
+
+impl Request {
+    pub fn new(command: Command) -> Self {
+        Self { version: PROTOCOL_VERSION, command }
+    }
+}
+
+impl Response {
+    pub fn new(reply: Reply) -> Self {
+        Self { version: PROTOCOL_VERSION, reply }
+    }
+}
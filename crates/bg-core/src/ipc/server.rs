@@ -0,0 +1,216 @@
+use std::future::Future;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::time::Duration;
+use log::{debug, error, info};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinSet;
+use crate::ipc::{Command, Reply, Request, Response, PROTOCOL_VERSION};
+use crate::orchestrator::{Session, SessionEvent};
+use crate::{Error, Result};
+
+/// Bind the socket, replacing a stale one left behind by a crashed daemon.
+fn bind(socket: &Path) -> std::io::Result<UnixListener> {
+    if socket.exists() {
+        if std::os::unix::net::UnixStream::connect(socket).is_ok() {
+            return Err(std::io::Error::new(
+                ErrorKind::AddrInUse,
+                format!("A daemon is already listening on {}", socket.display()),
+            ));
+        }
+        debug!("Removing stale socket {}", socket.display());
+        std::fs::remove_file(socket)?;
+    }
+    UnixListener::bind(socket)
+}
+
+/// How long replies still being written get when the daemon exits.
+const REPLY_GRACE: Duration = Duration::from_secs(1);
+
+/// A command read by a connection task, with the way back to its client.
+type PendingCommand = (Command, oneshot::Sender<Reply>);
+
+/// Run the daemon until a `stop` request or `shutdown` resolves.
+/// The session is stopped and the socket removed on the way out.
+/// Every connection is read and answered by its own task, the commands
+/// themselves are run one after the other on the session.
+pub async fn serve(socket: &Path, shutdown: impl Future<Output = ()>) -> Result<()> {
+    let listener = bind(socket).map_err(Error::Ipc)?;
+    info!("Listening on {}", socket.display());
+
+    let mut session: Option<Session> = None;
+    let (commands_tx, mut commands) = mpsc::unbounded_channel::<PendingCommand>();
+    let mut connections = JoinSet::new();
+    tokio::pin!(shutdown);
+
+    loop {
+        tokio::select! {
+            _ = &mut shutdown => break,
+            Some((command, reply)) = commands.recv() => {
+                let exit = matches!(command, Command::Stop);
+                let _ = reply.send(handle_command(command, &mut session).await);
+                if exit {
+                    break;
+                }
+            }
+            Some(_) = connections.join_next(), if !connections.is_empty() => {}
+            event = session_event(&mut session) => {
+                if let Some(s) = session.as_mut() {
+                    if let Err(e) = s.handle(event).await {
+                        error!("{}", e);
+                    }
+                }
+            }
+            accepted = listener.accept() => {
+                let stream = match accepted {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        error!("Failed accepting connection: {}", e);
+                        continue;
+                    }
+                };
+                let commands = commands_tx.clone();
+                connections.spawn(async move {
+                    if let Err(e) = handle_connection(stream, commands).await {
+                        error!("Failed handling request: {}", e);
+                    }
+                });
+            }
+        }
+    }
+
+    info!("Daemon shutting down.");
+    if let Some(session) = session.take() {
+        if let Err(e) = session.stop().await {
+            error!("{}", e);
+        }
+    }
+    let _ = std::fs::remove_file(socket);
+
+    // Let the `stop` reply reach its client, clients still waiting are dropped.
+    drop(commands);
+    let _ = tokio::time::timeout(REPLY_GRACE, async {
+        while connections.join_next().await.is_some() {}
+    }).await;
+    Ok(())
+}
+
+async fn session_event(session: &mut Option<Session>) -> SessionEvent {
+    match session {
+        Some(s) => s.event().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Read a single request, have the serving loop run it and write the reply.
+async fn handle_connection(stream: UnixStream, commands: mpsc::UnboundedSender<PendingCommand>) -> std::io::Result<()> {
+    let (read, mut write) = stream.into_split();
+    let mut line = String::new();
+    BufReader::new(read).read_line(&mut line).await?;
+    debug!("Got request: {}", line.trim());
+
+    let reply = match serde_json::from_str::<Request>(&line) {
+        Ok(request) if request.version != PROTOCOL_VERSION => Reply::Error {
+            kind: crate::ErrorKind::Session,
+            message: format!("Unsupported protocol version {}, expected {}", request.version, PROTOCOL_VERSION),
+        },
+        Ok(request) => {
+            let (reply_tx, reply) = oneshot::channel();
+            commands.send((request.command, reply_tx)).map_err(|_| daemon_gone())?;
+            reply.await.map_err(|_| daemon_gone())?
+        }
+        Err(e) => Reply::Error { kind: crate::ErrorKind::Session, message: format!("Malformed request: {}", e) },
+    };
+
+    let mut response = serde_json::to_string(&Response::new(reply))?;
+    response.push('\n');
+    write.write_all(response.as_bytes()).await
+}
+
+fn daemon_gone() -> std::io::Error {
+    std::io::Error::new(ErrorKind::BrokenPipe, "Daemon is shutting down")
+}
+
+async fn handle_command(command: Command, session: &mut Option<Session>) -> Reply {
+    let result = match command {
+        Command::Set(config) => {
+            if let Some(old) = session.take() {
+                if let Err(e) = old.stop().await {
+                    error!("{}", e);
+                }
+            }
+            Session::start(*config).await.map(|s| *session = Some(s))
+        }
+        Command::Next { outputs } => match session.as_mut() {
+            Some(s) => s.next(outputs.as_deref()).await,
+            None => Err(Error::NoSession),
+        },
+        Command::Status => {
+            return Reply::Status { session: session.as_ref().map(Session::status) };
+        }
+        Command::Stop => match session.take() {
+            Some(s) => s.stop().await.map(|_| ()),
+            None => Ok(()),
+        },
+        // The running session can swap to the old media without flicker if it was set up the same way.
+        Command::Restore(last) => match session.as_mut() {
+            Some(s) if *s.config() == last.config => s.apply(*last).await,
+            _ => {
+                if let Some(old) = session.take() {
+                    if let Err(e) = old.stop().await {
+                        error!("{}", e);
+                    }
+                }
+                Session::restore(*last).await.map(|s| *session = Some(s))
+            }
+        },
+        Command::Reload => match session.take() {
+            Some(s) => s.reload().await.map(|s| *session = Some(s)),
+            None => Err(Error::NoSession),
+        },
+    };
+
+    match result {
+        Ok(()) => Reply::Ok,
+        Err(e) => {
+            error!("{}", e);
+            Reply::Error { kind: e.kind(), message: e.to_string() }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ipc::send_to;
+
+    #[tokio::test]
+    async fn silent_client_does_not_block_others() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("bg-settings.sock");
+        // The session is not `Send`, so the daemon runs on this thread.
+        let local = tokio::task::LocalSet::new();
+        local.run_until(async {
+            let daemon = tokio::task::spawn_local({
+                let socket = socket.clone();
+                async move { serve(&socket, std::future::pending()).await }
+            });
+            while UnixStream::connect(&socket).await.is_err() {
+                tokio::task::yield_now().await;
+            }
+
+            // Connects and never sends its request.
+            let _silent = UnixStream::connect(&socket).await.unwrap();
+
+            let status = tokio::time::timeout(Duration::from_secs(5), send_to(&socket, Command::Status)).await;
+            assert!(matches!(status, Ok(Ok(Reply::Status { session: None }))), "{:?}", status);
+
+            let stop = send_to(&socket, Command::Stop).await.unwrap();
+            assert!(matches!(stop, Reply::Ok), "{:?}", stop);
+            daemon.await.unwrap().unwrap();
+        }).await;
+        assert!(!socket.exists());
+    }
+}
@@ -0,0 +1,16 @@
+mod span;
+
+pub use span::*;
+
+use serde::{Deserialize, Serialize};
+
+/// How media is laid out over the outputs.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Layout {
+    /// Every output gets its own media.
+    #[default]
+    Independent,
+    /// One image is cut into pieces following the output arrangement.
+    Span(SpanConfig),
+}
@@ -0,0 +1,285 @@
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::{Path, PathBuf};
+use image::imageops::FilterType;
+use image::ImageFormat;
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+use crate::wl::OutputInfo;
+use crate::{Error, Result};
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SpanConfig {
+    /// Logical pixels hidden behind the bezels between two neighbouring outputs.
+    #[serde(default)]
+    pub bezel: u32,
+    /// Where the pieces are written, `$XDG_CACHE_HOME/bg-settings/span` if unset.
+    #[serde(default)]
+    pub cache_dir: Option<PathBuf>,
+}
+
+impl SpanConfig {
+    pub fn cache_dir(&self) -> PathBuf {
+        self.cache_dir.clone().unwrap_or_else(|| {
+            env::var_os("XDG_CACHE_HOME")
+                .map(PathBuf::from)
+                .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+                .unwrap_or_else(env::temp_dir)
+                .join("bg-settings")
+                .join("span")
+        })
+    }
+}
+
+/// Where an output sits on the span canvas, in logical pixels from its top left corner.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SpanRect {
+    pub output: String,
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+    /// Size of the piece in pixels, larger than the logical size on scaled outputs.
+    pub pixel_width: i32,
+    pub pixel_height: i32,
+}
+
+/// Place the outputs on one canvas.
+/// Every distinct output edge left of (or above) an output pushes it by `bezel`.
+pub fn span_rects(outputs: &[OutputInfo], bezel: u32) -> Result<Vec<SpanRect>> {
+    let placed = outputs.iter().map(|o| {
+        let (width, height) = o.logical_size().ok_or_else(|| Error::OutputWithoutMode(o.name.clone()))?;
+        let (x, y) = o.position();
+        let scale = o.scale().max(1);
+        let (pixel_width, pixel_height) = o.pixel_size().unwrap_or((width * scale, height * scale));
+        Ok(SpanRect { output: o.name.clone(), x, y, width, height, pixel_width, pixel_height })
+    }).collect::<Result<Vec<_>>>()?;
+
+    let bezel = bezel as i32;
+    let mut rects: Vec<SpanRect> = placed.iter().map(|r| {
+        let left: HashSet<i32> = placed.iter().map(|o| o.x + o.width).filter(|edge| *edge <= r.x).collect();
+        let above: HashSet<i32> = placed.iter().map(|o| o.y + o.height).filter(|edge| *edge <= r.y).collect();
+        SpanRect {
+            x: r.x + bezel * left.len() as i32,
+            y: r.y + bezel * above.len() as i32,
+            ..r.clone()
+        }
+    }).collect();
+
+    let min_x = rects.iter().map(|r| r.x).min().unwrap_or_default();
+    let min_y = rects.iter().map(|r| r.y).min().unwrap_or_default();
+    for rect in rects.iter_mut() {
+        rect.x -= min_x;
+        rect.y -= min_y;
+    }
+    Ok(rects)
+}
+
+/// Where the pieces of `image` go, without cutting anything: `split_span` writes them there.
+pub fn plan_span(image: &Path, outputs: &[OutputInfo], config: &SpanConfig) -> Result<Vec<(SpanRect, PathBuf)>> {
+    let rects = span_rects(outputs, config.bezel)?;
+    let (canvas_width, canvas_height) = canvas_size(&rects);
+
+    let cache_dir = config.cache_dir();
+    let image_key = image_key(image);
+    Ok(rects.into_iter().map(|rect| {
+        let mut hasher = DefaultHasher::new();
+        (image_key, &rect, canvas_width, canvas_height).hash(&mut hasher);
+        let path = cache_dir.join(format!("{:016x}-{}.png", hasher.finish(), rect.output));
+        (rect, path)
+    }).collect())
+}
+
+/// Cut `image` into one piece per output, scaled to cover the whole canvas.
+/// Pieces are cached by image, placement and scale. Returns the piece for each output name.
+pub fn split_span(image: &Path, outputs: &[OutputInfo], config: &SpanConfig) -> Result<HashMap<String, PathBuf>> {
+    let pieces = plan_span(image, outputs, config)?;
+    let (canvas_width, canvas_height) = canvas_size(pieces.iter().map(|(rect, _)| rect));
+
+    let cache_dir = config.cache_dir();
+    std::fs::create_dir_all(&cache_dir)
+        .map_err(|source| Error::CacheIo { path: cache_dir.clone(), source })?;
+
+    let missing: Vec<&(SpanRect, PathBuf)> = pieces.iter().filter(|(_, path)| !path.exists()).collect();
+    if !missing.is_empty() {
+        info!("Cutting {} into {} pieces", image.display(), missing.len());
+        let source = image::open(image)
+            .map_err(|source| Error::Image { path: image.to_path_buf(), source })?;
+
+        // Canvas pixels per image pixel, so the image covers the canvas, centered.
+        let factor = f64::max(
+            canvas_width as f64 / source.width() as f64,
+            canvas_height as f64 / source.height() as f64,
+        );
+        let offset_x = (source.width() as f64 * factor - canvas_width as f64) / 2.0;
+        let offset_y = (source.height() as f64 * factor - canvas_height as f64) / 2.0;
+
+        for (rect, path) in missing {
+            let piece = source
+                .crop_imm(
+                    ((rect.x as f64 + offset_x) / factor).round() as u32,
+                    ((rect.y as f64 + offset_y) / factor).round() as u32,
+                    ((rect.width as f64 / factor).round() as u32).max(1),
+                    ((rect.height as f64 / factor).round() as u32).max(1),
+                )
+                .resize_exact(
+                    rect.pixel_width.max(1) as u32,
+                    rect.pixel_height.max(1) as u32,
+                    FilterType::Lanczos3,
+                );
+
+            // Backends may read the piece while it is written.
+            let partial = path.with_extension("partial");
+            piece.save_with_format(&partial, ImageFormat::Png)
+                .map_err(|source| Error::Image { path: partial.clone(), source })?;
+            std::fs::rename(&partial, path)
+                .map_err(|source| Error::CacheIo { path: path.clone(), source })?;
+            debug!("Wrote {} for {}", path.display(), rect.output);
+        }
+    }
+
+    let pieces: HashMap<String, PathBuf> = pieces.into_iter().map(|(rect, path)| (rect.output, path)).collect();
+    prune(&cache_dir, &pieces);
+    Ok(pieces)
+}
+
+/// Width and height of the canvas holding `rects`.
+fn canvas_size<'a>(rects: impl IntoIterator<Item = &'a SpanRect>) -> (i32, i32) {
+    rects.into_iter().fold((0, 0), |(width, height), r| (width.max(r.x + r.width), height.max(r.y + r.height)))
+}
+
+/// Changes when the image file does.
+fn image_key(image: &Path) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    image.hash(&mut hasher);
+    if let Ok(metadata) = std::fs::metadata(image) {
+        metadata.len().hash(&mut hasher);
+        metadata.modified().ok().hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+/// Remove pieces of earlier images. Other files in `cache_dir`, which may be any
+/// directory of the user, are left alone.
+fn prune(cache_dir: &Path, keep: &HashMap<String, PathBuf>) {
+    let keep: HashSet<&PathBuf> = keep.values().collect();
+    let Ok(entries) = std::fs::read_dir(cache_dir) else {
+        return;
+    };
+    for path in entries.flatten().map(|e| e.path()) {
+        if is_piece(&path) && !keep.contains(&path) {
+            debug!("Removing stale piece {}", path.display());
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// Whether `path` is named like the pieces of `plan_span`, `{hash:016x}-{output}.png`.
+fn is_piece(path: &Path) -> bool {
+    let Some(stem) = path.file_name().and_then(|n| n.to_str()).and_then(|n| n.strip_suffix(".png")) else {
+        return false;
+    };
+    match stem.split_once('-') {
+        Some((hash, output)) => hash.len() == 16 && hash.bytes().all(|b| b.is_ascii_hexdigit()) && !output.is_empty(),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_writes_the_planned_pieces() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = bg_test_support::write_image(dir.path(), "wide.png", 64, 18);
+        let outputs = [
+            OutputInfo::new("DP-1").with_mode(320, 180, 60000),
+            OutputInfo::new("HDMI-A-1").with_position(320, 0).with_scale(2).with_mode(480, 270, 60000),
+        ];
+        let config = SpanConfig { bezel: 0, cache_dir: Some(dir.path().join("span")) };
+
+        let planned = plan_span(&image, &outputs, &config).unwrap();
+        assert!(!config.cache_dir().exists());
+
+        let pieces = split_span(&image, &outputs, &config).unwrap();
+        assert_eq!(pieces.len(), 2);
+        for (rect, path) in planned {
+            assert_eq!(pieces[&rect.output], path);
+            let (width, height) = image::image_dimensions(&path).unwrap();
+            assert_eq!((width as i32, height as i32), (rect.pixel_width, rect.pixel_height));
+        }
+    }
+
+    fn rect(output: &str, (x, y): (i32, i32), (width, height): (i32, i32), pixels: (i32, i32)) -> SpanRect {
+        SpanRect { output: output.to_string(), x, y, width, height, pixel_width: pixels.0, pixel_height: pixels.1 }
+    }
+
+    #[test]
+    fn outputs_are_arranged_by_position() {
+        let outputs = [
+            OutputInfo::new("DP-1").with_position(0, -1080).with_mode(1920, 1080, 60000),
+            OutputInfo::new("HDMI-A-1").with_position(-1920, 0).with_mode(1920, 1080, 60000),
+            OutputInfo::new("eDP-1").with_mode(1920, 1200, 60000),
+        ];
+
+        let rects = span_rects(&outputs, 0).unwrap();
+
+        assert_eq!(rects, [
+            rect("DP-1", (1920, 0), (1920, 1080), (1920, 1080)),
+            rect("HDMI-A-1", (0, 1080), (1920, 1080), (1920, 1080)),
+            rect("eDP-1", (1920, 1080), (1920, 1200), (1920, 1200)),
+        ]);
+        assert_eq!(canvas_size(&rects), (3840, 2280));
+    }
+
+    #[test]
+    fn bezels_push_the_outputs_apart() {
+        let outputs = [
+            OutputInfo::new("DP-1").with_mode(1920, 1080, 60000),
+            OutputInfo::new("DP-2").with_position(1920, 0).with_mode(1920, 1080, 60000),
+            OutputInfo::new("DP-3").with_position(3840, 0).with_mode(1920, 1080, 60000),
+            OutputInfo::new("HDMI-A-1").with_position(0, 1080).with_mode(1920, 1080, 60000),
+        ];
+
+        let rects = span_rects(&outputs, 40).unwrap();
+
+        let origins: Vec<(i32, i32)> = rects.iter().map(|r| (r.x, r.y)).collect();
+        assert_eq!(origins, [(0, 0), (1960, 0), (3920, 0), (0, 1120)]);
+        assert_eq!(canvas_size(&rects), (5840, 2200));
+    }
+
+    #[test]
+    fn scaled_outputs_take_their_logical_size_and_keep_their_pixels() {
+        let outputs = [
+            OutputInfo::new("DP-1").with_scale(2).with_mode(3840, 2160, 60000),
+            OutputInfo::new("HDMI-A-1").with_position(1920, 0).with_mode(1920, 1080, 60000),
+        ];
+
+        let rects = span_rects(&outputs, 0).unwrap();
+
+        assert_eq!(rects, [
+            rect("DP-1", (0, 0), (1920, 1080), (3840, 2160)),
+            rect("HDMI-A-1", (1920, 0), (1920, 1080), (1920, 1080)),
+        ]);
+    }
+
+    #[test]
+    fn prune_only_removes_pieces() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = bg_test_support::write_image(dir.path(), "wide.png", 64, 18);
+        let stale = dir.path().join("0123456789abcdef-DP-1.png");
+        std::fs::write(&stale, b"").unwrap();
+        let outputs = [OutputInfo::new("DP-1").with_mode(32, 18, 60000)];
+        let config = SpanConfig { bezel: 0, cache_dir: Some(dir.path().to_path_buf()) };
+
+        let pieces = split_span(&image, &outputs, &config).unwrap();
+
+        assert!(pieces["DP-1"].exists());
+        assert!(image.exists(), "removed an image of the user");
+        assert!(!stale.exists());
+        assert!(is_piece(&pieces["DP-1"]));
+        assert!(!is_piece(Path::new("holiday-2024.png")));
+    }
+}
@@ -2,3 +2,10 @@ pub mod backend;
 pub mod wl;
 pub mod media;
 pub mod orchestrator;
+pub mod ipc;
+pub mod config;
+pub mod layout;
+pub mod instance;
+mod error;
+
+pub use error::{Error, ErrorKind, Result};
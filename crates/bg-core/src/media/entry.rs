@@ -0,0 +1,73 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+use serde::{Deserialize, Serialize};
+use crate::media::{detect_media_kind, MediaKind};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Dimensions {
+    pub width: u32,
+    pub height: u32,
+}
+
+/// What `list media` shows about a file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MediaEntry {
+    pub path: PathBuf,
+    pub kind: MediaKind,
+    /// In bytes.
+    pub size: u64,
+    /// Only known for images, read from the file header.
+    pub dimensions: Option<Dimensions>,
+}
+
+impl MediaEntry {
+    pub fn of(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref();
+        let kind = detect_media_kind(path);
+        let dimensions = match kind {
+            MediaKind::StaticImage | MediaKind::AnimatedImage => image_dimensions(path),
+            _ => None,
+        };
+
+        Self {
+            path: path.to_path_buf(),
+            kind,
+            size: std::fs::metadata(path).map(|m| m.len()).unwrap_or_default(),
+            dimensions,
+        }
+    }
+}
+
+pub fn image_dimensions(path: &Path) -> Option<Dimensions> {
+    let size = imagesize::size(path).ok()?;
+    Some(Dimensions {
+        width: size.width.try_into().ok()?,
+        height: size.height.try_into().ok()?,
+    })
+}
+
+/// Image dimensions by path, each header read only once.
+/// A clone starts empty so a reloaded session sees replaced files, and all caches
+/// compare equal as they are not part of a configuration.
+#[derive(Debug, Default)]
+pub struct DimensionCache(Mutex<HashMap<PathBuf, Option<Dimensions>>>);
+
+impl DimensionCache {
+    pub fn get(&self, path: &Path) -> Option<Dimensions> {
+        let mut cache = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        *cache.entry(path.to_path_buf()).or_insert_with(|| image_dimensions(path))
+    }
+}
+
+impl Clone for DimensionCache {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+impl PartialEq for DimensionCache {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
@@ -1,7 +1,9 @@
 use std::{fs::File, io::Read, path::Path};
 use infer::Infer;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum MediaKind {
     StaticImage,
     AnimatedImage,
@@ -1,6 +1,8 @@
 mod scan;
 mod mime;
+mod entry;
 
 pub use mime::*;
 
-pub use scan::*;
\ No newline at end of file
+pub use scan::*;
+pub use entry::*;
@@ -1,10 +1,13 @@
-use std::io::ErrorKind;
 use std::path::{Path, PathBuf};
 
 use log::{error, info};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
 use crate::media::detect_media_kind;
 use crate::media::mime::MediaKind;
+use crate::{Error, Result};
 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ScanConfig {
     pub recurse: bool,
     pub max_recurses: isize,
@@ -14,9 +17,11 @@ pub fn scan_media_recursive(
     root: impl AsRef<Path>,
     filter: MediaKind,
     scan_config: &mut ScanConfig
-) -> std::io::Result<Vec<PathBuf>> {
+) -> Result<Vec<PathBuf>> {
     let mut result = Vec::new();
     scan_dir(root.as_ref(), filter, scan_config, &mut result)?;
+    // `read_dir` order depends on the filesystem, sort for reproducible picks.
+    result.sort();
     Ok(result)
 }
 
@@ -25,9 +30,10 @@ fn scan_dir(
     filter: MediaKind,
     scan_config: &mut ScanConfig,
     out: &mut Vec<PathBuf>,
-) -> std::io::Result<()> {
-    for entry in std::fs::read_dir(dir)? {
-        let entry = entry?;
+) -> Result<()> {
+    let scan_io = |source| Error::ScanIo { path: dir.to_path_buf(), source };
+    for entry in std::fs::read_dir(dir).map_err(scan_io)? {
+        let entry = entry.map_err(scan_io)?;
         let path = entry.path();
 
         if path.is_dir() {
@@ -49,7 +55,7 @@ fn scan_dir(
         }
 
         let kind = detect_media_kind(&path);
-        let is_any_supported = filter != MediaKind::Unsupported && filter == MediaKind::Any;
+        let is_any_supported = filter == MediaKind::Any && kind != MediaKind::Unsupported;
         if is_any_supported || kind == filter {
             out.push(path);
         }
@@ -62,7 +68,18 @@ pub fn scan_media(root: Option<PathBuf>,
                   random: bool,
                   random_amount: Option<usize>,
                   scan_config: &mut ScanConfig
-) -> Result<Vec<PathBuf>, std::io::Error> {
+) -> Result<Vec<PathBuf>> {
+    scan_media_with_rng(root, mode, random, random_amount, scan_config, &mut rand::rng())
+}
+
+/// Like `scan_media`, picking with `rng` so a seeded one gives the same picks every time.
+pub fn scan_media_with_rng(root: Option<PathBuf>,
+                           mode: MediaKind,
+                           random: bool,
+                           random_amount: Option<usize>,
+                           scan_config: &mut ScanConfig,
+                           rng: &mut impl Rng,
+) -> Result<Vec<PathBuf>> {
     if let Some(root) = root {
         let scan_result = scan_media_recursive(
             root,
@@ -82,11 +99,10 @@ pub fn scan_media(root: Option<PathBuf>,
                 info!("Listing {} media. Count: {} ", media_type_str, paths.len());
 
                 if random && !paths.is_empty() {
-                    let mut rng = rand::rng();
                     let random_amount = random_amount.unwrap_or(1).min(paths.len());
 
                     let indices = rand::seq::index::sample(
-                        &mut rng,
+                        rng,
                         paths.len(), random_amount
                     ).into_vec();
 
@@ -106,7 +122,7 @@ pub fn scan_media(root: Option<PathBuf>,
         }
     } else {
         error!("No media path provided.");
-        Err(std::io::Error::new(ErrorKind::InvalidInput, "No media path provided."))
+        Err(Error::NoMediaPath)
     }
 }
 
@@ -116,7 +132,7 @@ pub fn list_media(
     kind: MediaKind,
     recursive: bool,
     max_recurse_depth: i8,
-) -> std::io::Result<()> {
+) -> Result<()> {
     let mut scan_config = ScanConfig {
         recurse: recursive,
         max_recurses: max_recurse_depth.into(),
@@ -130,3 +146,28 @@ pub fn list_media(
     
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn same_seed_picks_the_same_media() {
+        let dir = tempfile::tempdir().unwrap();
+        for i in 0..10 {
+            bg_test_support::write_image(dir.path(), &format!("{}.png", i), 16, 9);
+        }
+        let pick = |seed| {
+            let mut config = ScanConfig { recurse: false, max_recurses: -1 };
+            let root = Some(dir.path().to_path_buf());
+            scan_media_with_rng(root, MediaKind::Any, true, Some(3), &mut config, &mut StdRng::seed_from_u64(seed))
+                .unwrap()
+        };
+
+        let picked = pick(42);
+        assert_eq!(picked.len(), 3);
+        assert_eq!(pick(42), picked);
+    }
+}
@@ -0,0 +1,334 @@
+use std::path::{Path, PathBuf};
+use log::{debug, warn};
+use rand::prelude::IndexedRandom;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use crate::media::DimensionCache;
+use crate::orchestrator::History;
+use crate::wl::OutputInfo;
+use crate::{Error, Result};
+
+/// How media is spread over the outputs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AssignStrategy {
+    /// Every output shows different media, fails if there is not enough.
+    #[default]
+    Unique,
+    /// All outputs show the same media.
+    Same,
+    /// Every output picks at random, outputs may show the same media.
+    #[serde(alias = "with-replacement")]
+    WithReplacement,
+    /// Outputs walk through the media in path order.
+    Cycle,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AspectMatch {
+    /// Media of any shape.
+    #[default]
+    Off,
+    /// Media fitting the output first, any media if none does.
+    Prefer,
+    /// Only media fitting the output.
+    Require,
+}
+
+/// How media is picked for an output.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AssignConfig {
+    #[serde(default)]
+    pub strategy: AssignStrategy,
+    #[serde(default)]
+    pub aspect: AspectMatch,
+    /// Largest accepted relative difference between the media and output aspect ratios.
+    #[serde(default = "default_tolerance")]
+    pub tolerance: f64,
+    /// Media remembered per output so random picks do not repeat, 0 disables the history.
+    #[serde(default = "default_history")]
+    pub history: usize,
+    #[serde(skip)]
+    pub(crate) dimensions: DimensionCache,
+}
+
+fn default_tolerance() -> f64 {
+    0.1
+}
+
+fn default_history() -> usize {
+    100
+}
+
+impl Default for AssignConfig {
+    fn default() -> Self {
+        Self {
+            strategy: AssignStrategy::default(),
+            aspect: AspectMatch::Off,
+            tolerance: default_tolerance(),
+            history: default_history(),
+            dimensions: DimensionCache::default(),
+        }
+    }
+}
+
+impl AssignConfig {
+    /// Whether the aspect ratio of `media` is close to the one of `output`, turned by its transform.
+    /// Media without dimensions in its header, such as videos, never fits.
+    /// Headers are read once per session, see `DimensionCache`.
+    pub fn fits(&self, output: &OutputInfo, media: &Path) -> bool {
+        let (Some((width, height)), Some(dimensions)) = (output.logical_size(), self.dimensions.get(media)) else {
+            return false;
+        };
+        if width <= 0 || height <= 0 || dimensions.height == 0 {
+            return false;
+        }
+        let output_ratio = width as f64 / height as f64;
+        let media_ratio = dimensions.width as f64 / dimensions.height as f64;
+        (media_ratio / output_ratio - 1.0).abs() <= self.tolerance
+    }
+
+    /// The media of `pool` that may be shown on `output`.
+    pub fn candidates<'a>(&self, output: &OutputInfo, pool: &'a [PathBuf]) -> Vec<&'a PathBuf> {
+        if self.aspect == AspectMatch::Off {
+            return pool.iter().collect();
+        }
+
+        let fitting: Vec<&PathBuf> = pool.iter().filter(|media| self.fits(output, media)).collect();
+        if fitting.is_empty() && self.aspect == AspectMatch::Prefer {
+            debug!("No media fits {}, falling back to any", output.name);
+            return pool.iter().collect();
+        }
+        fitting
+    }
+
+    /// Pick media for `output` instead of `current` and record it in `history`.
+    /// `shown` is what the other outputs show: `Unique` avoids it, `Same` follows it
+    /// and `Cycle` continues after it when there is no `current`.
+    /// Random picks come from the media `output` has not shown yet, see `History::bag`.
+    pub fn pick(
+        &self,
+        output: &OutputInfo,
+        current: Option<&Path>,
+        pool: &[PathBuf],
+        shown: &[PathBuf],
+        history: &mut History,
+        rng: &mut impl Rng,
+    ) -> Option<PathBuf> {
+        let candidates = self.candidates(output, pool);
+
+        let random = match self.strategy {
+            AssignStrategy::Cycle => false,
+            AssignStrategy::Same => shown.is_empty(),
+            _ => true,
+        };
+        let picked = if random {
+            let bag = history.bag(&output.name, &candidates);
+            // The bag may only hold what `Unique` has to avoid.
+            self.choose(bag, current, shown, rng)
+                .or_else(|| self.choose(candidates, current, shown, rng))
+        } else {
+            self.choose(candidates, current, shown, rng)
+        };
+
+        let picked = picked.cloned();
+        if let Some(media) = &picked {
+            history.record(&output.name, media);
+        }
+        picked
+    }
+
+    fn choose<'a>(
+        &self,
+        mut candidates: Vec<&'a PathBuf>,
+        current: Option<&Path>,
+        shown: &[PathBuf],
+        rng: &mut impl Rng,
+    ) -> Option<&'a PathBuf> {
+        match self.strategy {
+            AssignStrategy::Unique => candidates.into_iter()
+                .filter(|m| Some(m.as_path()) != current && !shown.contains(m))
+                .collect::<Vec<_>>()
+                .choose(rng)
+                .copied(),
+            AssignStrategy::Same => match candidates.iter().find(|m| shown.contains(m)) {
+                Some(media) => Some(*media),
+                None => candidates.into_iter()
+                    .filter(|m| Some(m.as_path()) != current)
+                    .collect::<Vec<_>>()
+                    .choose(rng)
+                    .copied(),
+            },
+            AssignStrategy::WithReplacement => {
+                let others: Vec<&PathBuf> = candidates.iter().copied().filter(|m| Some(m.as_path()) != current).collect();
+                // Keep the current media only if there is nothing else.
+                others.choose(rng).or(candidates.choose(rng)).copied()
+            }
+            AssignStrategy::Cycle => {
+                candidates.sort();
+                let after = |last: &Path| candidates.iter().position(|m| *m == last).map(|i| i + 1);
+                let start = match current {
+                    Some(current) => after(current),
+                    None => shown.last().and_then(|last| after(last)),
+                };
+                if candidates.is_empty() {
+                    None
+                } else {
+                    Some(candidates[start.unwrap_or(0) % candidates.len()])
+                }
+            }
+        }
+    }
+
+    /// Pick media for every output. Fails with the outputs left without media,
+    /// which only happens when nothing fits or `Unique` runs out of media.
+    pub fn assign(
+        &self,
+        outputs: &[OutputInfo],
+        pool: &[PathBuf],
+        history: &mut History,
+        rng: &mut impl Rng,
+    ) -> Result<Vec<PathBuf>> {
+        let mut assigned: Vec<PathBuf> = Vec::new();
+        let mut missing: Vec<String> = Vec::new();
+        for output in outputs {
+            match self.pick(output, None, pool, &assigned, history, rng) {
+                Some(media) => assigned.push(media),
+                None => missing.push(output.name.clone()),
+            }
+        }
+
+        if !missing.is_empty() {
+            warn!("{} media for {} outputs, nothing left for {:?}", pool.len(), outputs.len(), missing);
+            return Err(Error::NotEnoughMedia { outputs: missing });
+        }
+        Ok(assigned)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use bg_test_support::write_image;
+    use wayland_client::protocol::wl_output::Transform;
+
+    fn outputs(count: usize) -> Vec<OutputInfo> {
+        ["DP-1", "DP-2", "HDMI-A-1"][..count].iter()
+            .map(|name| OutputInfo::new(*name).with_mode(1920, 1080, 60000))
+            .collect()
+    }
+
+    fn pool(names: &[&str]) -> Vec<PathBuf> {
+        names.iter().map(PathBuf::from).collect()
+    }
+
+    fn assign(strategy: AssignStrategy, outputs: &[OutputInfo], pool: &[PathBuf]) -> Result<Vec<PathBuf>> {
+        let config = AssignConfig { strategy, ..AssignConfig::default() };
+        config.assign(outputs, pool, &mut History::new(100), &mut StdRng::seed_from_u64(7))
+    }
+
+    #[test]
+    fn same_shows_one_media_everywhere() {
+        let assigned = assign(AssignStrategy::Same, &outputs(3), &pool(&["a", "b", "c", "d"])).unwrap();
+        assert_eq!(assigned, pool(&["b", "b", "b"]));
+    }
+
+    #[test]
+    fn unique_shows_different_media_on_every_output() {
+        let assigned = assign(AssignStrategy::Unique, &outputs(3), &pool(&["a", "b", "c"])).unwrap();
+        assert_eq!(assigned, pool(&["b", "a", "c"]));
+    }
+
+    #[test]
+    fn cycle_offsets_every_output() {
+        let media = pool(&["d", "c", "b", "a"]);
+        let assigned = assign(AssignStrategy::Cycle, &outputs(3), &media).unwrap();
+        assert_eq!(assigned, pool(&["a", "b", "c"]));
+
+        // Every output moves on from what it shows, wrapping around at the end.
+        let config = AssignConfig { strategy: AssignStrategy::Cycle, ..AssignConfig::default() };
+        let next: Vec<PathBuf> = outputs(3).iter().zip(&assigned)
+            .map(|(output, current)| {
+                let mut rng = StdRng::seed_from_u64(7);
+                config.pick(output, Some(current), &media, &[], &mut History::new(100), &mut rng).unwrap()
+            })
+            .collect();
+        assert_eq!(next, pool(&["b", "c", "d"]));
+        let wrapped = config.pick(&outputs(1)[0], Some(Path::new("d")), &media, &[], &mut History::new(100), &mut StdRng::seed_from_u64(7));
+        assert_eq!(wrapped, Some(PathBuf::from("a")));
+    }
+
+    #[test]
+    fn with_replacement_picks_independently() {
+        let assigned = assign(AssignStrategy::WithReplacement, &outputs(3), &pool(&["a", "b", "c", "d"])).unwrap();
+        assert_eq!(assigned, pool(&["b", "a", "a"]));
+    }
+
+    #[test]
+    fn pool_smaller_than_the_outputs() {
+        let (outputs, small) = (outputs(3), pool(&["a", "b"]));
+
+        let err = assign(AssignStrategy::Unique, &outputs, &small).unwrap_err();
+        assert!(matches!(err, Error::NotEnoughMedia { ref outputs } if outputs == &["HDMI-A-1"]), "{}", err);
+
+        assert_eq!(assign(AssignStrategy::Same, &outputs, &small).unwrap(), pool(&["a", "a", "a"]));
+        assert_eq!(assign(AssignStrategy::Cycle, &outputs, &small).unwrap(), pool(&["a", "b", "a"]));
+        assert_eq!(assign(AssignStrategy::WithReplacement, &outputs, &small).unwrap(), pool(&["a", "a", "a"]));
+    }
+
+    fn aspect(aspect: AspectMatch) -> AssignConfig {
+        AssignConfig { aspect, ..AssignConfig::default() }
+    }
+
+    #[test]
+    fn media_fitting_the_output_is_preferred() {
+        let dir = tempfile::tempdir().unwrap();
+        let wide = write_image(dir.path(), "wide.png", 32, 18);
+        let tall = write_image(dir.path(), "tall.png", 18, 32);
+        let media = vec![wide.clone(), tall.clone()];
+        let landscape = OutputInfo::new("DP-1").with_mode(2560, 1440, 60000);
+        let portrait = OutputInfo::new("DP-2").with_mode(1080, 1920, 60000);
+        let turned = OutputInfo::new("HDMI-A-1").with_mode(1920, 1080, 60000).with_transform(Transform::_90);
+
+        let config = aspect(AspectMatch::Require);
+        assert_eq!(config.candidates(&landscape, &media), [&wide]);
+        assert_eq!(config.candidates(&portrait, &media), [&tall]);
+        assert_eq!(config.candidates(&turned, &media), [&tall]);
+        assert_eq!(aspect(AspectMatch::Off).candidates(&portrait, &media), [&wide, &tall]);
+    }
+
+    #[test]
+    fn nothing_fitting_falls_back_only_when_preferred() {
+        let dir = tempfile::tempdir().unwrap();
+        let wide = write_image(dir.path(), "wide.png", 32, 18);
+        let video = dir.path().join("clip.mp4");
+        std::fs::write(&video, b"").unwrap();
+        let media = vec![wide.clone(), video.clone()];
+        let portrait = OutputInfo::new("DP-2").with_mode(1080, 1920, 60000);
+
+        assert_eq!(aspect(AspectMatch::Prefer).candidates(&portrait, &media), [&wide, &video]);
+        assert!(aspect(AspectMatch::Require).candidates(&portrait, &media).is_empty());
+
+        let err = aspect(AspectMatch::Require)
+            .assign(&[portrait], &media, &mut History::new(100), &mut StdRng::seed_from_u64(7))
+            .unwrap_err();
+        assert!(matches!(err, Error::NotEnoughMedia { .. }), "{}", err);
+    }
+
+    #[test]
+    fn dimensions_are_read_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let media = write_image(dir.path(), "a.png", 32, 18);
+        let landscape = OutputInfo::new("DP-1").with_mode(2560, 1440, 60000);
+        let config = aspect(AspectMatch::Require);
+        assert!(config.fits(&landscape, &media));
+
+        write_image(dir.path(), "a.png", 18, 32);
+
+        assert!(config.fits(&landscape, &media));
+        assert!(!config.clone().fits(&landscape, &media));
+    }
+}
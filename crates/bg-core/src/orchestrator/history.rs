@@ -0,0 +1,186 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::path::{Path, PathBuf};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use crate::orchestrator::{state_dir, write_state};
+use crate::Result;
+
+/// Media recently shown on each output, so random picks walk the whole pool
+/// before showing anything again. Kept in `$XDG_STATE_HOME/bg-settings/history.json`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct History {
+    #[serde(skip)]
+    path: Option<PathBuf>,
+    /// Entries kept per output, 0 keeps none.
+    #[serde(skip)]
+    length: usize,
+    outputs: BTreeMap<String, VecDeque<PathBuf>>,
+}
+
+impl History {
+    /// A history that is never written to disk.
+    pub fn new(length: usize) -> Self {
+        Self { length, ..Self::default() }
+    }
+
+    pub fn default_path() -> Option<PathBuf> {
+        state_dir().map(|dir| dir.join("history.json"))
+    }
+
+    /// Load the history at the default path.
+    pub fn open(length: usize) -> Self {
+        match Self::default_path() {
+            Some(path) => Self::load(&path, length),
+            None => Self::new(length),
+        }
+    }
+
+    /// A missing or broken file gives an empty history, it is rewritten on the next save.
+    pub fn load(path: &Path, length: usize) -> Self {
+        let mut history = match std::fs::read(path) {
+            Ok(content) => serde_json::from_slice(&content).unwrap_or_else(|e| {
+                warn!("Ignoring history {}: {}", path.display(), e);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        };
+        history.path = Some(path.to_path_buf());
+        history.length = length;
+        for shown in history.outputs.values_mut() {
+            truncate(shown, length);
+        }
+        history
+    }
+
+    /// Where `save` writes to, `None` for a history that is never written.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// What `output` showed, oldest first.
+    pub fn shown(&self, output: &str) -> impl Iterator<Item = &PathBuf> {
+        self.outputs.get(output).into_iter().flatten()
+    }
+
+    /// The shuffle bag of `output`: the candidates it has not shown yet.
+    /// Once every candidate was shown a new round starts, leaving out only the last one.
+    pub fn bag<'a>(&mut self, output: &str, candidates: &[&'a PathBuf]) -> Vec<&'a PathBuf> {
+        if self.length == 0 {
+            return candidates.to_vec();
+        }
+        let shown = self.outputs.entry(output.to_string()).or_default();
+        let unseen: Vec<&PathBuf> = candidates.iter().copied().filter(|m| !shown.contains(m)).collect();
+        if !unseen.is_empty() {
+            return unseen;
+        }
+
+        debug!("Shown all media on {}, starting over", output);
+        let last = shown.pop_back();
+        shown.clear();
+        shown.extend(last);
+        candidates.iter().copied().filter(|m| shown.back() != Some(*m)).collect()
+    }
+
+    pub fn record(&mut self, output: &str, media: &Path) {
+        if self.length == 0 {
+            return;
+        }
+        let shown = self.outputs.entry(output.to_string()).or_default();
+        if shown.back().is_some_and(|last| last == media) {
+            return;
+        }
+        shown.retain(|m| m != media);
+        shown.push_back(media.to_path_buf());
+        truncate(shown, self.length);
+    }
+
+    /// Write the history back to where it was loaded from.
+    pub fn save(&self) -> Result<()> {
+        match self.path.as_ref().filter(|_| self.length > 0) {
+            Some(path) => write_state(path, self),
+            None => Ok(()),
+        }
+    }
+}
+
+fn truncate(shown: &mut VecDeque<PathBuf>, length: usize) {
+    while shown.len() > length {
+        shown.pop_front();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool(names: &[&str]) -> Vec<PathBuf> {
+        names.iter().map(PathBuf::from).collect()
+    }
+
+    /// Take the first media of the bag every time, as a deterministic pick.
+    fn draw(history: &mut History, pool: &[PathBuf], count: usize) -> Vec<PathBuf> {
+        let candidates: Vec<&PathBuf> = pool.iter().collect();
+        (0..count).map(|_| {
+            let picked = history.bag("DP-1", &candidates)[0].clone();
+            history.record("DP-1", &picked);
+            picked
+        }).collect()
+    }
+
+    #[test]
+    fn every_media_is_shown_once_per_bag() {
+        let media = pool(&["a", "b", "c"]);
+        let mut history = History::new(100);
+
+        assert_eq!(draw(&mut history, &media, 3), pool(&["a", "b", "c"]));
+        assert_eq!(draw(&mut history, &media, 2), pool(&["a", "b"]));
+    }
+
+    #[test]
+    fn new_bag_does_not_start_with_the_last_media() {
+        let media = pool(&["a", "b", "c"]);
+        let mut history = History::new(100);
+        draw(&mut history, &media, 3);
+
+        let bag = history.bag("DP-1", &media.iter().collect::<Vec<_>>());
+
+        assert_eq!(bag, [&media[0], &media[1]]);
+        assert!(history.shown("DP-1").eq([&media[2]]));
+    }
+
+    #[test]
+    fn bag_follows_a_changed_pool() {
+        let mut history = History::new(100);
+        draw(&mut history, &pool(&["a", "b", "c"]), 2);
+
+        // `c` is still unseen, `d` is new, `a` is gone.
+        let changed = pool(&["b", "c", "d"]);
+        let bag = history.bag("DP-1", &changed.iter().collect::<Vec<_>>());
+        assert_eq!(bag, [&changed[1], &changed[2]]);
+
+        // The pool shrank to what was shown already.
+        let shrunk = pool(&["a", "b"]);
+        let bag = history.bag("DP-1", &shrunk.iter().collect::<Vec<_>>());
+        assert_eq!(bag, [&shrunk[0]]);
+    }
+
+    #[test]
+    fn outputs_have_their_own_bags() {
+        let media = pool(&["a", "b"]);
+        let mut history = History::new(100);
+        history.record("DP-1", &media[0]);
+
+        assert_eq!(history.bag("HDMI-A-1", &media.iter().collect::<Vec<_>>()), [&media[0], &media[1]]);
+        assert_eq!(history.bag("DP-1", &media.iter().collect::<Vec<_>>()), [&media[1]]);
+    }
+
+    #[test]
+    fn disabled_history_keeps_nothing() {
+        let media = pool(&["a", "b"]);
+        let mut history = History::new(0);
+        history.record("DP-1", &media[0]);
+
+        assert_eq!(history.bag("DP-1", &media.iter().collect::<Vec<_>>()), [&media[0], &media[1]]);
+        assert_eq!(history.shown("DP-1").count(), 0);
+    }
+}
@@ -1,8 +1,28 @@
+mod session;
+mod rotation;
+mod assign;
+mod history;
+mod state;
+mod supervisor;
+
+pub use session::*;
+pub use rotation::*;
+pub use assign::*;
+pub use history::*;
+pub use state::*;
+pub use supervisor::*;
+
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
-use log::{info, error};
-use rand::prelude::IndexedRandom;
-use crate::backend::{BackendSpawnSpec, WallpaperMode, select_backend, available_backends};
+use log::{info, error, warn};
+use std::process::ExitStatus;
+use std::sync::Arc;
+use std::time::Duration;
+use rand::Rng;
+use crate::backend::{BackendCapability, BackendSpawnSpec, WallpaperBackend, WallpaperMode, WallpaperProcess, select_backend, installed_backends, Backend};
+use crate::media::detect_media_kind;
 use crate::wl::{OutputInfo, get_info};
+use crate::{Error, Result};
 
 /// Filter outputs by names. If target_names is None, returns all outputs.
 pub fn filter_outputs_by_names(
@@ -20,6 +40,7 @@ pub fn filter_outputs_by_names(
 }
 
 /// Create spawn specs for given outputs, media path, and mode.
+/// `media_path` holds one entry per output, see `AssignConfig::assign`.
 pub fn create_spawn_specs(
     outputs: Vec<OutputInfo>,
     media_path: Vec<impl AsRef<Path>>,
@@ -27,8 +48,8 @@ pub fn create_spawn_specs(
 ) -> Vec<BackendSpawnSpec> {
     
     outputs.into_iter()
-        .zip(media_path.into_iter())
-        .into_iter().map(
+        .zip(media_path)
+        .map(
         |(output, media_path)| {
             BackendSpawnSpec {
                 media: media_path.as_ref().to_path_buf(),
@@ -40,65 +61,656 @@ pub fn create_spawn_specs(
 }
 
 /// Orchestrate wallpaper setup.
-/// Returns a vector of child processes if successful.
+/// Returns the started processes if successful.
 pub async fn setup_wallpaper(
     media_path: Vec<PathBuf>,
     backend_name: Option<String>,
     target_outputs: Option<Vec<String>>,
     mode: WallpaperMode,
-) -> Result<Vec<tokio::process::Child>, String> {
+    custom_backends: Vec<Box<dyn WallpaperBackend>>,
+    rng: &mut impl Rng,
+) -> Result<Vec<WallpaperProcess>> {
     // Get outputs and backends
-    let (all_outputs, _) = get_info();
-    let available = available_backends();
+    let (all_outputs, _) = get_info()?;
+    let candidates = custom_backends.into_iter().chain(Backend::supported_backends()).collect();
+    setup_wallpaper_on(all_outputs, candidates, media_path, backend_name, target_outputs, mode, rng)
+}
+
+/// Like `setup_wallpaper`, on fixed `all_outputs` instead of the ones of the compositor,
+/// choosing among the installed `backends` only.
+pub fn setup_wallpaper_on(
+    all_outputs: Vec<OutputInfo>,
+    backends: Vec<Box<dyn WallpaperBackend>>,
+    media_path: Vec<PathBuf>,
+    backend_name: Option<String>,
+    target_outputs: Option<Vec<String>>,
+    mode: WallpaperMode,
+    rng: &mut impl Rng,
+) -> Result<Vec<WallpaperProcess>> {
+    let available = installed_backends(backends);
 
     if available.is_empty() {
-        return Err("No available backend found".to_string());
+        return Err(Error::NoBackendAvailable);
     }
 
-    let rng = &mut rand::rng();
     // Filter outputs
     let selected_outputs = filter_outputs_by_names(all_outputs, target_outputs);
     if selected_outputs.is_empty() {
-        return Err("No outputs selected".to_string());
+        return Err(Error::NoOutputsMatched);
     }
 
-    let selected_media = media_path.choose_multiple(rng, selected_outputs.len()).collect();
-
     // Select backend
-    let backend = select_backend(backend_name, available);
+    let backend: Arc<dyn WallpaperBackend> = select_backend(backend_name, available)?.into();
+
+    let assign = AssignConfig::default();
+    setup_outputs(selected_outputs, &media_path, backend, mode, &assign, &mut History::new(assign.history), rng)
+}
+
+/// Assign media to the given outputs and start the backend for them.
+pub fn setup_outputs(
+    selected_outputs: Vec<OutputInfo>,
+    media_path: &[PathBuf],
+    backend: Arc<dyn WallpaperBackend>,
+    mode: WallpaperMode,
+    assign: &AssignConfig,
+    history: &mut History,
+    rng: &mut impl Rng,
+) -> Result<Vec<WallpaperProcess>> {
+    let spawn_specs = plan_outputs(selected_outputs, media_path, backend.as_ref(), mode, assign, history, rng)?;
+    start_backend(backend, spawn_specs)
+}
+
+/// Assign media to the given outputs, returning what `backend` would be started with.
+pub fn plan_outputs(
+    selected_outputs: Vec<OutputInfo>,
+    media_path: &[PathBuf],
+    backend: &dyn WallpaperBackend,
+    mode: WallpaperMode,
+    assign: &AssignConfig,
+    history: &mut History,
+    rng: &mut impl Rng,
+) -> Result<Vec<BackendSpawnSpec>> {
+    let selected_media = if let WallpaperMode::SolidColor(_) = mode {
+        // A solid colour needs no media.
+        vec![PathBuf::new(); selected_outputs.len()]
+    } else {
+        let supported_media = supported_media(backend, media_path);
+        if supported_media.is_empty() {
+            return Err(Error::NoMediaFound { backend: backend.name().to_string() });
+        }
+        assign.assign(&selected_outputs, &supported_media, history, rng)?
+    };
 
     // Create spawn specs
-    let spawn_specs = create_spawn_specs(
+    Ok(create_spawn_specs(
         selected_outputs,
         selected_media,
         mode
-    );
+    ))
+}
 
-    // Start backend(s)
-    let mut children = Vec::new();
-    if backend.capabilities().contains(&crate::backend::BackendCapability::MultiOutput) {
+/// Scanned media, by output when per-output media directories are configured.
+#[derive(Debug, Clone, Default)]
+pub struct MediaPools {
+    pub default: Vec<PathBuf>,
+    pub per_output: HashMap<String, Vec<PathBuf>>,
+}
+
+impl MediaPools {
+    pub fn for_output(&self, output: &str) -> &[PathBuf] {
+        self.per_output.get(output).unwrap_or(&self.default)
+    }
+}
+
+/// Only keep media the backend can show.
+pub fn supported_media(backend: &dyn WallpaperBackend, media_path: &[PathBuf]) -> Vec<PathBuf> {
+    media_path.iter()
+        .filter(|path| backend.supports(detect_media_kind(path)))
+        .cloned()
+        .collect()
+}
+
+/// Start the backend for the given specs.
+/// If one output fails, the processes started so far are stopped again.
+pub fn start_backend(
+    backend: Arc<dyn WallpaperBackend>,
+    spawn_specs: Vec<BackendSpawnSpec>,
+) -> Result<Vec<WallpaperProcess>> {
+    if let Some(spec) = spawn_specs.iter().find(|spec| !backend.supports_mode(&spec.mode)) {
+        return Err(Error::SpawnFailed {
+            backend: backend.name().to_string(),
+            source: std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                format!("{} cannot show mode {}", backend.name(), spec.mode),
+            ),
+        });
+    }
+
+    if backend.capabilities().contains(&BackendCapability::MultiOutput) {
         info!("Calling start_multi for {}", backend.name());
-        match backend.start_multi(spawn_specs) {
-            Ok(mut cs) => {
-                children.append(&mut cs);
-                Ok(children)
-            }
-            Err(e) => Err(format!("Failed to start multi: {}", e)),
+        let mut children = backend.start_multi(spawn_specs.clone())
+            .map_err(|source| Error::SpawnFailed { backend: backend.name().to_string(), source })?;
+
+        if children.len() == spawn_specs.len() {
+            // One process per output after all.
+            Ok(spawn_specs.into_iter().zip(children)
+                .map(|(spec, child)| WallpaperProcess::new(backend.clone(), vec![spec], Some(child)))
+                .collect())
+        } else {
+            // One shared process, or none when a running one was reused.
+            let child = if children.is_empty() { None } else { Some(children.remove(0)) };
+            Ok(vec![WallpaperProcess::new(backend, spawn_specs, child)])
         }
     } else {
         info!("Calling start for {}", backend.name());
+        let mut processes: Vec<WallpaperProcess> = Vec::new();
         for spec in spawn_specs {
             match backend.start(&spec) {
                 Ok(c) => {
-                    children.push(c);
                     info!("Spawned client {}, with media in {:?}", backend.name(), spec.media);
+                    processes.push(WallpaperProcess::new(backend.clone(), vec![spec], c));
                 }
                 Err(e) => {
                     error!("Spawn failed: {}", e);
-                    return Err(format!("Failed to start backend: {}", e));
+                    // Rollback
+                    let children = processes.into_iter().filter_map(|p| p.child).collect();
+                    crate::backend::roll_back(backend.as_ref(), children);
+                    return Err(Error::SpawnFailed { backend: backend.name().to_string(), source: e });
+                }
+            }
+        }
+        Ok(processes)
+    }
+}
+
+/// Pick new media for the running processes, only on `target_outputs` if given.
+pub async fn next_wallpaper(
+    processes: &mut Vec<WallpaperProcess>,
+    media: &MediaPools,
+    target_outputs: Option<&[String]>,
+    assign: &AssignConfig,
+    history: &mut History,
+    rng: &mut impl Rng,
+    swap_delay: Duration,
+) -> Result<()> {
+    let mut chosen = HashMap::new();
+
+    // `Unique` avoids everything on screen, `Same` follows what this round picked.
+    let mut shown: Vec<PathBuf> = match assign.strategy {
+        AssignStrategy::Unique => processes.iter()
+            .flat_map(|p| p.specs.iter().map(|s| s.media.clone()))
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    for process in processes.iter() {
+        for spec in process.specs.iter() {
+            let targeted = target_outputs.is_none_or(|names| names.contains(&spec.output.name));
+            if !targeted || matches!(spec.mode, WallpaperMode::SolidColor(_)) {
+                continue;
+            }
+            let pool = supported_media(process.backend(), media.for_output(&spec.output.name));
+            match assign.pick(&spec.output, Some(&spec.media), &pool, &shown, history, rng) {
+                Some(media) => {
+                    shown.push(media.clone());
+                    chosen.insert(spec.output.name.clone(), media);
+                }
+                None => warn!("No other media for {}, keeping {}", spec.output.name, spec.media.display()),
+            }
+        }
+    }
+
+    set_media(processes, &chosen, swap_delay).await
+}
+
+/// How long a replacement backend gets to put up its surface before the old one is stopped.
+pub const DEFAULT_SWAP_DELAY: Duration = Duration::from_millis(500);
+
+/// Show `media`, by output name, on the running processes.
+/// `HotReload` backends are updated in place. For the others a new process is started,
+/// and the old one only stopped once the new one survived `swap_delay`,
+/// so the output never shows the bare compositor background.
+pub async fn set_media(
+    processes: &mut Vec<WallpaperProcess>,
+    media: &HashMap<String, PathBuf>,
+    swap_delay: Duration,
+) -> Result<()> {
+    let change = |spec: &mut BackendSpawnSpec| match media.get(&spec.output.name).filter(|new| **new != spec.media) {
+        Some(new) => {
+            spec.media = new.clone();
+            true
+        }
+        None => false,
+    };
+    swap(processes, change, swap_delay).await
+}
+
+/// Draw the wallpaper of `outputs`, by name, again for their new mode, scale or transform.
+/// Backends are swapped like in `set_media`.
+pub async fn set_outputs(
+    processes: &mut Vec<WallpaperProcess>,
+    outputs: &HashMap<String, OutputInfo>,
+    swap_delay: Duration,
+) -> Result<()> {
+    let change = |spec: &mut BackendSpawnSpec| match outputs.get(&spec.output.name) {
+        Some(output) => {
+            spec.output = output.clone();
+            true
+        }
+        None => false,
+    };
+    swap(processes, change, swap_delay).await
+}
+
+/// Apply `change` to the specs of the running processes, and show the changed ones.
+/// `change` returns whether it changed the spec.
+async fn swap(
+    processes: &mut Vec<WallpaperProcess>,
+    change: impl Fn(&mut BackendSpawnSpec) -> bool,
+    swap_delay: Duration,
+) -> Result<()> {
+    let mut result = Ok(());
+    let mut swaps: Vec<(WallpaperProcess, Vec<WallpaperProcess>)> = Vec::new();
+
+    for mut process in std::mem::take(processes) {
+        let backend = process.backend.clone();
+
+        let mut specs = process.specs.clone();
+        let changed: Vec<bool> = specs.iter_mut().map(&change).collect();
+        if !changed.contains(&true) {
+            processes.push(process);
+            continue;
+        }
+
+        if backend.capabilities().contains(&BackendCapability::HotReload) {
+            for ((old, new), changed) in process.specs.iter_mut().zip(specs).zip(changed) {
+                if !changed {
+                    continue;
+                }
+                match backend.update(&new) {
+                    Ok(()) => *old = new,
+                    Err(e) => {
+                        error!("Failed updating {}: {}", new.output.name, e);
+                        result = Err(Error::UpdateFailed { backend: backend.name().to_string(), source: e });
+                    }
                 }
             }
+            processes.push(process);
+        } else {
+            match start_backend(backend, specs) {
+                Ok(started) => swaps.push((process, started)),
+                Err(e) => {
+                    processes.push(process);
+                    result = Err(e);
+                }
+            }
+        }
+    }
+
+    if swaps.is_empty() {
+        return result;
+    }
+    tokio::time::sleep(swap_delay).await;
+
+    for (old, mut started) in swaps {
+        let backend = old.backend.clone();
+        match exited_early(&mut started) {
+            None => {
+                if let Err(e) = old.stop().await {
+                    error!("Failed stopping {}: {}", backend.name(), e);
+                }
+                processes.extend(started);
+            }
+            Some(status) => {
+                error!("New {} exited right away ({}), keeping the old one", backend.name(), status);
+                for process in started {
+                    let _ = process.stop().await;
+                }
+                processes.push(old);
+                result = Err(Error::SpawnFailed {
+                    backend: backend.name().to_string(),
+                    source: std::io::Error::other(format!("exited during startup, {}", status)),
+                });
+            }
+        }
+    }
+
+    result
+}
+
+/// The exit status of the first of `started` that is not running anymore.
+fn exited_early(started: &mut [WallpaperProcess]) -> Option<ExitStatus> {
+    started.iter_mut()
+        .filter_map(|p| p.child.as_mut())
+        .find_map(|c| c.try_wait().ok().flatten())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use bg_test_support::{process_exists, write_image, write_script};
+    use crate::backend::{CommandTemplateBackend, MockBackend};
+
+    fn outputs() -> Vec<OutputInfo> {
+        vec![
+            OutputInfo::new("DP-1").with_mode(2560, 1440, 60000),
+            OutputInfo::new("HDMI-A-1").with_mode(1920, 1080, 60000).with_position(2560, 0),
+        ]
+    }
+
+    fn library(dir: &Path, count: usize) -> Vec<PathBuf> {
+        (0..count).map(|i| write_image(dir, &format!("{}.png", i), 16, 9)).collect()
+    }
+
+    fn setup(
+        backends: Vec<MockBackend>,
+        media: Vec<PathBuf>,
+        backend_name: Option<&str>,
+        target_outputs: Option<Vec<&str>>,
+    ) -> Result<Vec<WallpaperProcess>> {
+        let backends = backends.into_iter().map(|b| Box::new(b) as Box<dyn WallpaperBackend>).collect();
+        setup_wallpaper_on(
+            outputs(),
+            backends,
+            media,
+            backend_name.map(String::from),
+            target_outputs.map(|names| names.into_iter().map(String::from).collect()),
+            WallpaperMode::Fill,
+            &mut StdRng::seed_from_u64(7),
+        )
+    }
+
+    fn started_outputs(backend: &MockBackend) -> Vec<String> {
+        backend.started().into_iter().map(|spec| spec.output.name).collect()
+    }
+
+    #[tokio::test]
+    async fn only_targeted_outputs_are_started() {
+        let dir = tempfile::tempdir().unwrap();
+        let mock = MockBackend::new("mock");
+
+        let processes = setup(vec![mock.clone()], library(dir.path(), 2), None, Some(vec!["HDMI-A-1"])).unwrap();
+
+        assert_eq!(started_outputs(&mock), ["HDMI-A-1"]);
+        assert_eq!(processes.len(), 1);
+        assert_eq!(processes[0].outputs(), ["HDMI-A-1"]);
+
+        let err = setup(vec![mock], library(dir.path(), 2), None, Some(vec!["eDP-1"])).err().unwrap();
+        assert!(matches!(err, Error::NoOutputsMatched), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn every_output_gets_different_media_of_the_library() {
+        let dir = tempfile::tempdir().unwrap();
+        let media = library(dir.path(), 3);
+        let mock = MockBackend::new("mock");
+
+        setup(vec![mock.clone()], media.clone(), None, None).unwrap();
+
+        let started = mock.started();
+        assert_eq!(started_outputs(&mock), ["DP-1", "HDMI-A-1"]);
+        assert!(started.iter().all(|spec| media.contains(&spec.media) && spec.mode == WallpaperMode::Fill));
+        let shown: HashSet<&PathBuf> = started.iter().map(|spec| &spec.media).collect();
+        assert_eq!(shown.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn missing_backend_falls_back_to_the_next() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = MockBackend::new("missing").missing();
+        let fallback = MockBackend::new("fallback");
+
+        setup(vec![missing.clone(), fallback.clone()], library(dir.path(), 2), None, None).unwrap();
+        assert!(missing.started().is_empty());
+        assert_eq!(started_outputs(&fallback), ["DP-1", "HDMI-A-1"]);
+
+        let err = setup(vec![missing, fallback], library(dir.path(), 2), Some("missing"), None).err().unwrap();
+        assert!(matches!(err, Error::BackendNotFound(ref name) if name == "missing"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn failure_on_second_output_stops_the_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let mock = MockBackend::new("mock").with_command(&["sleep", "60"]).failing_on("HDMI-A-1");
+
+        let err = setup(vec![mock.clone()], library(dir.path(), 2), None, None).err().unwrap();
+
+        assert!(matches!(err, Error::SpawnFailed { ref backend, .. } if backend == "mock"), "{}", err);
+        assert_eq!(started_outputs(&mock), ["DP-1", "HDMI-A-1"]);
+        let stopped = mock.stopped();
+        assert_eq!(stopped.len(), 1);
+        assert!(!bg_test_support::process_exists(stopped[0].unwrap()), "rolled back child not reaped");
+    }
+
+    #[tokio::test]
+    async fn multi_output_failure_stops_the_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let mock = MockBackend::new("mock")
+            .with_capabilities(vec![BackendCapability::Static, BackendCapability::MultiOutput])
+            .with_command(&["sleep", "60"])
+            .failing_on("HDMI-A-1");
+
+        let err = setup(vec![mock.clone()], library(dir.path(), 2), None, None).err().unwrap();
+
+        assert!(matches!(err, Error::SpawnFailed { .. }), "{}", err);
+        let stopped = mock.stopped();
+        assert_eq!(stopped.len(), 1);
+        assert!(!bg_test_support::process_exists(stopped[0].unwrap()), "rolled back child not reaped");
+    }
+
+    #[tokio::test]
+    async fn session_rolls_back_earlier_backends() {
+        let dir = tempfile::tempdir().unwrap();
+        library(dir.path(), 2);
+        let first = MockBackend::new("first").with_command(&["sleep", "60"]);
+        let second = MockBackend::new("second").failing_on("HDMI-A-1");
+        let config = SessionConfig {
+            rules: vec![OutputRule {
+                output: "HDMI-A-1".to_string(),
+                media_paths: None,
+                mode: None,
+                backend: Some("second".to_string()),
+                interval: None,
+            }],
+            ..SessionConfig::new(vec![dir.path().to_path_buf()])
+        };
+        let backends: Vec<Box<dyn WallpaperBackend>> = vec![Box::new(first.clone()), Box::new(second.clone())];
+
+        let err = Session::start_on(config, outputs(), backends).await.err().unwrap();
+
+        assert!(matches!(err, Error::SpawnFailed { ref backend, .. } if backend == "second"), "{}", err);
+        assert_eq!(started_outputs(&first), ["DP-1"]);
+        assert_eq!(first.stopped().len(), 1);
+    }
+
+    /// Handle session events until `done` holds for the backend's records.
+    async fn handle_until(session: &mut Session, done: impl Fn() -> bool) {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !done() {
+                let event = session.event().await;
+                session.handle(event).await.unwrap();
+            }
+        }).await.expect("session did not follow the compositor within 5s");
+    }
+
+    #[tokio::test]
+    async fn session_follows_output_hotplug() {
+        use bg_test_support::{FakeCompositor, FakeOutput};
+
+        let dir = tempfile::tempdir().unwrap();
+        library(dir.path(), 3);
+        let compositor = FakeCompositor::start(
+            vec![FakeOutput::new("DP-1").with_mode(2560, 1440, 60000)],
+            Vec::new(),
+        ).unwrap();
+        let (outputs, _) = crate::wl::get_info_from(&compositor.connect().unwrap()).unwrap();
+        let mock = MockBackend::new("mock").with_command(&["sleep", "60"]);
+        let config = SessionConfig {
+            swap_delay: Duration::from_millis(10),
+            ..SessionConfig::new(vec![dir.path().to_path_buf()])
+        };
+
+        let mut session = Session::start_on(config, outputs, vec![Box::new(mock.clone())]).await.unwrap();
+        session.follow_outputs(crate::wl::watch_outputs_on(compositor.connect().unwrap()).unwrap());
+        assert_eq!(started_outputs(&mock), ["DP-1"]);
+
+        compositor.add_output(FakeOutput::new("HDMI-A-1").with_position(2560, 0).with_mode(1920, 1080, 60000));
+        handle_until(&mut session, || mock.started().len() == 2).await;
+        assert_eq!(started_outputs(&mock), ["DP-1", "HDMI-A-1"]);
+
+        compositor.update_output(FakeOutput::new("DP-1").with_scale(2).with_mode(3840, 2160, 60000));
+        handle_until(&mut session, || mock.started().len() == 3).await;
+        let redrawn = mock.started().remove(2);
+        assert_eq!(redrawn.output.name, "DP-1");
+        assert_eq!(redrawn.output.pixel_size(), Some((3840, 2160)));
+        assert_eq!(redrawn.output.logical_size(), Some((1920, 1080)));
+
+        compositor.remove_output("HDMI-A-1");
+        handle_until(&mut session, || mock.stopped().len() == 2).await;
+        let served: Vec<String> = session.processes().iter().flat_map(|p| p.outputs()).collect();
+        assert_eq!(served, ["DP-1"]);
+
+        session.stop().await.unwrap();
+    }
+
+    fn shown(backend: &MockBackend) -> Vec<(String, PathBuf)> {
+        backend.started().into_iter().map(|spec| (spec.output.name, spec.media)).collect()
+    }
+
+    #[tokio::test]
+    async fn same_seed_gives_the_same_assignment() {
+        let dir = tempfile::tempdir().unwrap();
+        let media = library(dir.path(), 10);
+        let (first, second) = (MockBackend::new("mock"), MockBackend::new("mock"));
+
+        setup(vec![first.clone()], media.clone(), None, None).unwrap();
+        setup(vec![second.clone()], media, None, None).unwrap();
+
+        assert_eq!(shown(&first).len(), 2);
+        assert_eq!(shown(&first), shown(&second));
+    }
+
+    #[tokio::test]
+    async fn seeded_sessions_show_the_same_media() {
+        let dir = tempfile::tempdir().unwrap();
+        library(dir.path(), 10);
+        let config = SessionConfig {
+            seed: Some(42),
+            ..SessionConfig::new(vec![dir.path().to_path_buf()])
+        };
+        let (first, second) = (MockBackend::new("mock"), MockBackend::new("mock"));
+
+        let session = Session::start_on(config.clone(), outputs(), vec![Box::new(first.clone())]).await.unwrap();
+        assert_eq!(session.seed(), 42);
+        Session::start_on(config, outputs(), vec![Box::new(second.clone())]).await.unwrap();
+
+        assert_eq!(shown(&first), shown(&second));
+    }
+
+    /// `a.png` shown on DP-1 by `backend`, and the media to swap it for.
+    fn swap_setup(dir: &Path) -> (Vec<BackendSpawnSpec>, HashMap<String, PathBuf>) {
+        let media = library(dir, 2);
+        let specs = create_spawn_specs(outputs()[..1].to_vec(), vec![media[0].clone()], WallpaperMode::Fill);
+        (specs, HashMap::from([("DP-1".to_string(), media[1].clone())]))
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn swap_stops_the_old_process_once_the_new_one_survived() {
+        let dir = tempfile::tempdir().unwrap();
+        let (specs, new_media) = swap_setup(dir.path());
+        let mock = MockBackend::new("mock").with_command(&["sleep", "60"]);
+        let mut processes = start_backend(Arc::new(mock.clone()), specs).unwrap();
+        let old = processes[0].id();
+        let delay = Duration::from_secs(2);
+
+        let swapping = tokio::spawn(async move {
+            set_media(&mut processes, &new_media, delay).await.map(|()| processes)
+        });
+        tokio::time::sleep(delay - Duration::from_millis(1)).await;
+        assert_eq!(mock.started().len(), 2);
+        assert!(mock.stopped().is_empty(), "old process stopped before the swap delay");
+
+        let processes = swapping.await.unwrap().unwrap();
+        assert_eq!(mock.stopped(), [old]);
+        assert_eq!(processes.len(), 1);
+        assert_ne!(processes[0].id(), old);
+        assert_eq!(processes[0].specs()[0].media, mock.started()[1].media);
+        for process in processes {
+            process.stop().await.unwrap();
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn swap_keeps_the_old_process_when_the_new_one_exits() {
+        let dir = tempfile::tempdir().unwrap();
+        let (specs, new_media) = swap_setup(dir.path());
+        // Replacements exit right away, the shown one keeps running.
+        let mock = MockBackend::new("mock").with_command(&["true"]);
+        let running = tokio::process::Command::new("sleep").arg("60").spawn().unwrap();
+        let old = running.id();
+        let mut processes = vec![WallpaperProcess::new(Arc::new(mock.clone()), specs, Some(running))];
+
+        let swapping = tokio::spawn(async move {
+            let result = set_media(&mut processes, &new_media, Duration::from_secs(2)).await;
+            (result, processes)
+        });
+        // Let the replacement exit before the paused clock reaches the swap delay.
+        tokio::task::yield_now().await;
+        std::thread::sleep(Duration::from_millis(200));
+
+        let (result, processes) = swapping.await.unwrap();
+        let err = result.unwrap_err();
+        assert!(matches!(err, Error::SpawnFailed { ref backend, .. } if backend == "mock"), "{}", err);
+        assert_eq!(mock.started().len(), 1);
+        assert_eq!(processes.len(), 1);
+        assert_eq!(processes[0].id(), old);
+        assert_eq!(processes[0].specs()[0].media, dir.path().join("0.png"));
+        for process in processes {
+            process.stop().await.unwrap();
+        }
+    }
+
+    /// Wait until `log` has `count` lines.
+    async fn wait_for_lines(log: &Path, count: usize) {
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while std::fs::read_to_string(log).unwrap_or_default().lines().count() < count {
+            assert!(std::time::Instant::now() < deadline, "backend did not start within 5s");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn resized_output_is_drawn_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("args");
+        let script = write_script(dir.path(), "wall", &format!("echo \"$@\" >> {}; exec sleep 60", log.display())).unwrap();
+        let backend = CommandTemplateBackend::new("wall", &format!("{} {{output}} {{scale}}", script.display())).unwrap();
+        let spec = BackendSpawnSpec {
+            media: "/a.png".into(),
+            mode: WallpaperMode::Fit,
+            output: OutputInfo::new("DP-1"),
+            extra_args: Vec::new(),
+        };
+
+        let mut processes = start_backend(Arc::new(backend), vec![spec]).unwrap();
+        let old = processes[0].id().unwrap();
+        wait_for_lines(&log, 1).await;
+
+        let mut resized = OutputInfo::new("DP-1");
+        resized.scale = 2;
+        set_outputs(&mut processes, &HashMap::from([("DP-1".to_string(), resized)]), DEFAULT_SWAP_DELAY).await.unwrap();
+        assert_eq!(processes[0].specs()[0].output.scale, 2);
+        assert_ne!(processes[0].id(), Some(old));
+        assert!(!process_exists(old));
+
+        wait_for_lines(&log, 2).await;
+        assert_eq!(std::fs::read_to_string(&log).unwrap(), "DP-1 1\nDP-1 2\n");
+
+        for process in processes {
+            process.stop().await.unwrap();
         }
-        Ok(children)
     }
 }
@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use tokio::time::{sleep_until, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RotationScope {
+    /// All outputs change together.
+    #[default]
+    Global,
+    /// Every output keeps its own timer.
+    PerOutput,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RotationConfig {
+    /// `None` leaves outputs without an override alone.
+    pub interval: Option<Duration>,
+    #[serde(default)]
+    pub scope: RotationScope,
+    /// Interval overrides by output name, implies per output timers for those.
+    #[serde(default)]
+    pub per_output: HashMap<String, Duration>,
+}
+
+impl RotationConfig {
+    pub fn every(interval: Duration) -> Self {
+        Self {
+            interval: Some(interval),
+            scope: RotationScope::Global,
+            per_output: HashMap::new(),
+        }
+    }
+}
+
+/// Timers deciding when outputs get new media.
+/// Uses tokio time, so tests can drive it with `tokio::time::pause`.
+pub struct Rotation {
+    config: RotationConfig,
+    /// `None` is the global timer.
+    deadlines: HashMap<Option<String>, Instant>,
+}
+
+impl Rotation {
+    pub fn new(config: RotationConfig, outputs: &[String]) -> Self {
+        let mut rotation = Self {
+            config,
+            deadlines: HashMap::new(),
+        };
+        for output in outputs {
+            rotation.add_output(output, None);
+        }
+        rotation
+    }
+
+    fn interval(&self, key: &Option<String>) -> Option<Duration> {
+        key.as_ref()
+            .and_then(|output| self.config.per_output.get(output))
+            .copied()
+            .or(self.config.interval)
+    }
+
+    /// Whether the config gives `output` its own timer.
+    fn configured_own_timer(&self, output: &str) -> bool {
+        self.config.scope == RotationScope::PerOutput || self.config.per_output.contains_key(output)
+    }
+
+    /// `None` for outputs following the global timer. Besides the configured ones,
+    /// outputs split off by a targeted `reset` have their own.
+    fn key(&self, output: &str) -> Option<String> {
+        let own_timer = self.configured_own_timer(output)
+            || self.deadlines.contains_key(&Some(output.to_string()));
+        own_timer.then(|| output.to_string())
+    }
+
+    /// Start timing `output`, with its own `interval` if given.
+    pub fn add_output(&mut self, output: &str, interval: Option<Duration>) {
+        if let Some(interval) = interval {
+            self.config.per_output.insert(output.to_string(), interval);
+        }
+        let key = self.key(output);
+        if let Some(interval) = self.interval(&key) {
+            self.deadlines.entry(key).or_insert(Instant::now() + interval);
+        }
+    }
+
+    pub fn remove_output(&mut self, output: &str) {
+        self.deadlines.remove(&Some(output.to_string()));
+    }
+
+    /// Restart the timers of `outputs`, or all timers if `None`, e.g. after a manual `next`.
+    /// Outputs following the global timer get their own, so the others keep their deadline,
+    /// until a reset of all timers puts them back on the global one.
+    pub fn reset(&mut self, outputs: Option<&[String]>) {
+        let now = Instant::now();
+        match outputs {
+            Some(names) => {
+                for name in names {
+                    let timed = self.deadlines.contains_key(&self.key(name));
+                    let key = Some(name.clone());
+                    if let (true, Some(interval)) = (timed, self.interval(&key)) {
+                        self.deadlines.insert(key, now + interval);
+                    }
+                }
+            }
+            None => {
+                let split_off: Vec<Option<String>> = self.deadlines.keys()
+                    .filter(|key| key.as_ref().is_some_and(|output| !self.configured_own_timer(output)))
+                    .cloned()
+                    .collect();
+                for key in split_off {
+                    self.deadlines.remove(&key);
+                }
+                let intervals: Vec<(Option<String>, Duration)> = self.deadlines.keys()
+                    .filter_map(|key| Some((key.clone(), self.interval(key)?)))
+                    .collect();
+                for (key, interval) in intervals {
+                    self.deadlines.insert(key, now + interval);
+                }
+            }
+        }
+    }
+
+    /// Wait for the earliest timer and return its output, `None` for the global timer.
+    /// Never resolves without timers.
+    pub async fn tick(&mut self) -> Option<String> {
+        let Some((key, deadline)) = self.deadlines.iter()
+            .min_by_key(|(_, deadline)| **deadline)
+            .map(|(key, deadline)| (key.clone(), *deadline))
+        else {
+            return std::future::pending().await;
+        };
+
+        sleep_until(deadline).await;
+        if let Some(interval) = self.interval(&key) {
+            self.deadlines.insert(key.clone(), Instant::now() + interval);
+        }
+        key
+    }
+
+    /// Outputs following the global timer.
+    pub fn global_outputs<'a>(&self, outputs: impl IntoIterator<Item = &'a String>) -> Vec<String> {
+        outputs.into_iter()
+            .filter(|o| self.key(o).is_none())
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    fn outputs() -> Vec<String> {
+        vec!["DP-1".to_string(), "HDMI-A-1".to_string()]
+    }
+
+    /// The next timer to fire and when, relative to `start`.
+    async fn tick(rotation: &mut Rotation, start: Instant) -> (Option<String>, Duration) {
+        let key = rotation.tick().await;
+        (key, start.elapsed())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn global_timer_covers_every_output() {
+        let start = Instant::now();
+        let mut rotation = Rotation::new(RotationConfig::every(MINUTE), &outputs());
+
+        assert_eq!(tick(&mut rotation, start).await, (None, MINUTE));
+        assert_eq!(tick(&mut rotation, start).await, (None, 2 * MINUTE));
+        assert_eq!(rotation.global_outputs(&outputs()), outputs());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn per_output_interval_runs_its_own_timer() {
+        let start = Instant::now();
+        let mut config = RotationConfig::every(MINUTE);
+        config.per_output.insert("HDMI-A-1".to_string(), Duration::from_secs(25));
+        let mut rotation = Rotation::new(config, &outputs());
+
+        let hdmi = Some("HDMI-A-1".to_string());
+        assert_eq!(tick(&mut rotation, start).await, (hdmi.clone(), Duration::from_secs(25)));
+        assert_eq!(tick(&mut rotation, start).await, (hdmi, Duration::from_secs(50)));
+        assert_eq!(tick(&mut rotation, start).await, (None, MINUTE));
+        assert_eq!(rotation.global_outputs(&outputs()), ["DP-1"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn targeted_reset_leaves_the_global_timer_alone() {
+        let start = Instant::now();
+        let mut rotation = Rotation::new(RotationConfig::every(MINUTE), &outputs());
+
+        tokio::time::advance(Duration::from_secs(30)).await;
+        rotation.reset(Some(&["DP-1".to_string()]));
+
+        assert_eq!(rotation.global_outputs(&outputs()), ["HDMI-A-1"]);
+        assert_eq!(tick(&mut rotation, start).await, (None, MINUTE));
+        assert_eq!(tick(&mut rotation, start).await, (Some("DP-1".to_string()), Duration::from_secs(90)));
+
+        rotation.reset(None);
+        assert_eq!(rotation.global_outputs(&outputs()), outputs());
+        assert_eq!(tick(&mut rotation, start).await, (None, Duration::from_secs(150)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn untimed_output_is_not_reset_into_a_timer() {
+        let mut config = RotationConfig::default();
+        config.per_output.insert("HDMI-A-1".to_string(), MINUTE);
+        let mut rotation = Rotation::new(config, &outputs());
+
+        rotation.reset(Some(&outputs()));
+
+        assert_eq!(rotation.deadlines.len(), 1);
+        assert!(rotation.deadlines.contains_key(&Some("HDMI-A-1".to_string())));
+    }
+}
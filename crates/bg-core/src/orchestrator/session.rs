@@ -0,0 +1,1160 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::process::ExitStatus;
+use std::sync::Arc;
+use std::time::Duration;
+use log::{debug, error, info, warn};
+use rand::prelude::IndexedRandom;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::task::JoinSet;
+use crate::backend::{installed_backends, installed_first, select_shared_backend, AwwwTransitionOptions, Backend, BackendCapability, BackendSpawnSpec, CommandTemplateConfig, StopReport, WallpaperBackend, WallpaperMode, WallpaperProcess, DEFAULT_STOP_GRACE};
+use crate::layout::{plan_span, split_span, Layout};
+use crate::media::{detect_media_kind, scan_media, MediaKind, ScanConfig};
+use crate::orchestrator::{exited, DEFAULT_SWAP_DELAY, AssignConfig, History, LastSession, ProcessState, Supervisor, SupervisorConfig, Rotation, RotationConfig, MediaPools, create_spawn_specs, filter_outputs_by_names, next_wallpaper, plan_outputs, set_media, set_outputs, start_backend, supported_media};
+use crate::wl::{get_info, watch_outputs, OutputEvent, OutputInfo};
+use crate::{Error, Result};
+
+/// History key of the images cut across outputs.
+const SPAN_HISTORY: &str = "*span*";
+
+/// A backend and the specs it is to be started with.
+type Plan = (Arc<dyn WallpaperBackend>, Vec<BackendSpawnSpec>);
+
+/// What a session is prepared for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Purpose {
+    /// Shown on the outputs of the compositor, keeping history and last session in the state directory.
+    Live,
+    /// Shown on fixed outputs, see `start_on`. The state directory is left alone.
+    Fixed,
+    /// Only planned, see `dry_run`.
+    DryRun,
+}
+
+/// Everything needed to set up (and later re-create) a wallpaper session.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionConfig {
+    pub media_paths: Vec<PathBuf>,
+    pub scan: ScanConfig,
+    pub backend: Option<String>,
+    pub outputs: Option<Vec<String>>,
+    pub mode: WallpaperMode,
+    /// User declared backends, checked before the built-in ones.
+    #[serde(default)]
+    pub custom_backends: Vec<CommandTemplateConfig>,
+    /// Pick new media periodically.
+    #[serde(default)]
+    pub rotation: Option<RotationConfig>,
+    /// Per-output overrides. Fields unset by the rule naming the output's connector
+    /// come from the other matching rules, in order.
+    #[serde(default)]
+    pub rules: Vec<OutputRule>,
+    /// Transition of the built-in awww backend.
+    #[serde(default)]
+    pub awww: AwwwTransitionOptions,
+    /// A span takes its images from `media_paths`, media paths of rules are not used.
+    #[serde(default)]
+    pub layout: Layout,
+    #[serde(default)]
+    pub assign: AssignConfig,
+    /// Seed for every random pick, a random one when unset.
+    /// A seeded session leaves the history alone, so the same seed gives the same picks.
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// How long backends may take to exit before they are killed.
+    #[serde(default = "default_stop_grace")]
+    pub stop_grace: Duration,
+    /// Restarting of crashed backends.
+    #[serde(default)]
+    pub supervise: SupervisorConfig,
+    /// How long a new backend gets to show up before the one it replaces is stopped.
+    #[serde(default = "default_swap_delay")]
+    pub swap_delay: Duration,
+}
+
+fn default_swap_delay() -> Duration {
+    DEFAULT_SWAP_DELAY
+}
+
+fn default_stop_grace() -> Duration {
+    DEFAULT_STOP_GRACE
+}
+
+/// Overrides for one output. Unset fields fall back to the session defaults.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OutputRule {
+    /// Output name such as `DP-1`, or `make model` as shown by `list output`.
+    pub output: String,
+    pub media_paths: Option<Vec<PathBuf>>,
+    pub mode: Option<WallpaperMode>,
+    pub backend: Option<String>,
+    pub interval: Option<Duration>,
+}
+
+/// What an output ends up with after applying its rule.
+#[derive(Debug, Clone, PartialEq)]
+struct OutputSettings {
+    backend: Option<String>,
+    mode: WallpaperMode,
+    media_paths: Vec<PathBuf>,
+}
+
+impl SessionConfig {
+    /// Showing `media_paths` on every output with the defaults of the config file.
+    pub fn new(media_paths: Vec<PathBuf>) -> Self {
+        Self {
+            media_paths,
+            scan: ScanConfig { recurse: false, max_recurses: -1 },
+            backend: None,
+            outputs: None,
+            mode: WallpaperMode::Fit,
+            custom_backends: Vec::new(),
+            rotation: None,
+            rules: Vec::new(),
+            layout: Layout::Independent,
+            assign: AssignConfig::default(),
+            seed: None,
+            stop_grace: DEFAULT_STOP_GRACE,
+            supervise: SupervisorConfig::default(),
+            swap_delay: DEFAULT_SWAP_DELAY,
+            awww: AwwwTransitionOptions::default(),
+        }
+    }
+
+    /// The rules matching `output`: the one naming its connector first, then the others in order.
+    pub fn rules_for(&self, output: &OutputInfo) -> Vec<&OutputRule> {
+        let (exact, others): (Vec<&OutputRule>, Vec<&OutputRule>) = self.rules.iter()
+            .filter(|rule| output.matches(&rule.output))
+            .partition(|rule| rule.output == output.name);
+        exact.into_iter().chain(others).collect()
+    }
+
+    /// The rotation interval of `output` set by its rules.
+    pub fn interval_for(&self, output: &OutputInfo) -> Option<Duration> {
+        self.rules_for(output).iter().find_map(|rule| rule.interval)
+    }
+
+    /// Make the media paths, those of rules included, absolute,
+    /// so they mean the same to a daemon running in another directory.
+    pub fn canonicalize_media(&mut self) -> Result<()> {
+        let rule_paths = self.rules.iter_mut().flat_map(|rule| rule.media_paths.iter_mut().flatten());
+        for path in self.media_paths.iter_mut().chain(rule_paths) {
+            *path = path.canonicalize().map_err(|source| Error::ScanIo { path: path.clone(), source })?;
+        }
+        Ok(())
+    }
+
+    fn settings_for(&self, output: &OutputInfo) -> OutputSettings {
+        let rules = self.rules_for(output);
+        OutputSettings {
+            backend: rules.iter().find_map(|r| r.backend.clone()).or(self.backend.clone()),
+            mode: rules.iter().find_map(|r| r.mode.clone()).unwrap_or(self.mode.clone()),
+            media_paths: rules.iter().find_map(|r| r.media_paths.clone()).unwrap_or(self.media_paths.clone()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutputStatus {
+    pub output: String,
+    pub backend: String,
+    pub mode: WallpaperMode,
+    pub media: PathBuf,
+    pub pid: Option<u32>,
+    #[serde(default)]
+    pub state: ProcessState,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionStatus {
+    pub outputs: Vec<OutputStatus>,
+    #[serde(default)]
+    pub seed: u64,
+}
+
+/// The commands a backend would run for its outputs, see `Session::dry_run`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackendPlan {
+    pub backend: String,
+    pub outputs: Vec<String>,
+    pub commands: Vec<Vec<String>>,
+}
+
+/// Something a running session has to react to.
+#[derive(Debug)]
+pub enum SessionEvent {
+    Output(OutputEvent),
+    /// A rotation timer fired, for one output or for all following the global timer.
+    Rotate(Option<String>),
+    /// The backend process serving these outputs exited on its own.
+    Exited { outputs: Vec<String>, status: ExitStatus },
+    /// A crashed backend is due for a restart.
+    Restart(Vec<String>),
+}
+
+/// The running wallpaper processes and the config they were created from.
+pub struct Session {
+    config: SessionConfig,
+    media: MediaPools,
+    available: Vec<Arc<dyn WallpaperBackend>>,
+    processes: Vec<WallpaperProcess>,
+    output_events: Option<UnboundedReceiver<OutputEvent>>,
+    rotation: Option<Rotation>,
+    /// The image cut across the outputs with a span layout.
+    span: Option<PathBuf>,
+    seed: u64,
+    rng: StdRng,
+    history: History,
+    supervisor: Supervisor,
+    /// Whether history and last session are written to the state directory.
+    persist: bool,
+}
+
+impl Session {
+    pub async fn start(config: SessionConfig) -> Result<Self> {
+        let (outputs, _) = get_info()?;
+        let backends = Backend::supported_backends_with(config.awww.clone());
+        let mut session = Self::launch(config, outputs, backends, Vec::new(), None, Purpose::Live).await?;
+        session.watch_outputs();
+        Ok(session)
+    }
+
+    /// Like `start`, on fixed `outputs` instead of the ones of the compositor,
+    /// with `backends` in place of the built-in ones. Output hotplug is not watched,
+    /// and neither the history nor the last session is read or written.
+    pub async fn start_on(
+        config: SessionConfig,
+        outputs: Vec<OutputInfo>,
+        backends: Vec<Box<dyn WallpaperBackend>>,
+    ) -> Result<Self> {
+        Self::launch(config, outputs, backends, Vec::new(), None, Purpose::Fixed).await
+    }
+
+    /// Show what `last` showed again. Outputs that are new, or whose media is gone,
+    /// get a fresh pick.
+    pub async fn restore(last: LastSession) -> Result<Self> {
+        let (outputs, _) = get_info()?;
+        let backends = Backend::supported_backends_with(last.config.awww.clone());
+        let mut session = Self::launch(last.config, outputs, backends, last.outputs, last.span, Purpose::Live).await?;
+        session.watch_outputs();
+        Ok(session)
+    }
+
+    /// Like `restore`, see `start_on`.
+    pub async fn restore_on(
+        last: LastSession,
+        outputs: Vec<OutputInfo>,
+        backends: Vec<Box<dyn WallpaperBackend>>,
+    ) -> Result<Self> {
+        Self::launch(last.config, outputs, backends, last.outputs, last.span, Purpose::Fixed).await
+    }
+
+    /// Go through output selection, scanning, media assignment and backend selection
+    /// like `start`, then return the commands the backends would run without running any.
+    /// Backends that are not installed are still planned, after the installed ones.
+    pub fn dry_run(config: SessionConfig) -> Result<Vec<BackendPlan>> {
+        let (outputs, _) = get_info()?;
+        let backends = Backend::supported_backends_with(config.awww.clone());
+        Self::dry_run_on(config, outputs, backends)
+    }
+
+    /// Like `dry_run`, see `start_on`.
+    pub fn dry_run_on(
+        config: SessionConfig,
+        outputs: Vec<OutputInfo>,
+        backends: Vec<Box<dyn WallpaperBackend>>,
+    ) -> Result<Vec<BackendPlan>> {
+        let (_, plans) = Self::prepare(config, outputs, backends, Vec::new(), None, Purpose::DryRun)?;
+        Ok(plans.into_iter().map(|(backend, specs)| BackendPlan {
+            backend: backend.name().to_string(),
+            outputs: specs.iter().map(|spec| spec.output.name.clone()).collect(),
+            commands: backend.planned_commands(&specs).into_iter()
+                .map(|argv| argv.iter().map(|arg| arg.to_string_lossy().into_owned()).collect())
+                .collect(),
+        }).collect())
+    }
+
+    async fn launch(
+        config: SessionConfig,
+        outputs: Vec<OutputInfo>,
+        backends: Vec<Box<dyn WallpaperBackend>>,
+        restored: Vec<OutputStatus>,
+        span_source: Option<PathBuf>,
+        purpose: Purpose,
+    ) -> Result<Self> {
+        let (mut session, plans) = Self::prepare(config, outputs, backends, restored, span_source, purpose)?;
+        for (backend, specs) in plans {
+            match start_backend(backend, specs) {
+                Ok(processes) => session.processes.extend(processes),
+                Err(e) => {
+                    // Rollback
+                    let _ = session.stop().await;
+                    return Err(e);
+                }
+            }
+        }
+
+        session.save_state();
+
+        session.rotation = session.rotation_config()
+            .map(|rotation| Rotation::new(rotation, &session.output_names()));
+        Ok(session)
+    }
+
+    fn watch_outputs(&mut self) {
+        self.output_events = watch_outputs()
+            .inspect_err(|e| warn!("Not watching output hotplug: {}", e))
+            .ok();
+    }
+
+    /// Follow the output hotplug reported by `events`, e.g. from `wl::watch_outputs_on`.
+    /// `start` and `restore` watch the compositor on their own.
+    pub fn follow_outputs(&mut self, events: UnboundedReceiver<OutputEvent>) {
+        self.output_events = Some(events);
+    }
+
+    /// A session without processes, and the backends to start with their specs.
+    /// The custom backends of `config` are tried before `backends`.
+    fn prepare(
+        config: SessionConfig,
+        all_outputs: Vec<OutputInfo>,
+        backends: Vec<Box<dyn WallpaperBackend>>,
+        restored: Vec<OutputStatus>,
+        span_source: Option<PathBuf>,
+        purpose: Purpose,
+    ) -> Result<(Self, Vec<Plan>)> {
+        let restored: HashMap<String, OutputStatus> = restored.into_iter()
+            .map(|status| (status.output.clone(), status))
+            .collect();
+
+        let selected_outputs = filter_outputs_by_names(all_outputs, config.outputs.clone());
+        if selected_outputs.is_empty() {
+            return Err(Error::NoOutputsMatched);
+        }
+
+        let candidates = custom_backends(&config)?.into_iter().chain(backends).collect();
+        let backends = match purpose {
+            Purpose::DryRun => installed_first(candidates),
+            Purpose::Live | Purpose::Fixed => installed_backends(candidates),
+        };
+        let available: Vec<Arc<dyn WallpaperBackend>> = backends
+            .into_iter()
+            .map(Arc::from)
+            .collect();
+        if available.is_empty() {
+            return Err(Error::NoBackendAvailable);
+        }
+
+        let seed = config.seed.unwrap_or_else(rand::random);
+        info!("Using seed {}", seed);
+
+        let persist = purpose == Purpose::Live;
+        let mut session = Self {
+            history: open_history(&config, purpose),
+            supervisor: Supervisor::new(config.supervise.clone()),
+            config,
+            media: MediaPools::default(),
+            available,
+            processes: Vec::new(),
+            output_events: None,
+            rotation: None,
+            span: None,
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+            persist,
+        };
+
+        // Outputs sharing backend, mode and media are set up together.
+        let mut scanned: HashMap<Vec<PathBuf>, Vec<PathBuf>> = HashMap::new();
+        let mut groups: Vec<(OutputSettings, Vec<OutputInfo>)> = Vec::new();
+        for output in selected_outputs {
+            let mut settings = session.config.settings_for(&output);
+            if let Some(status) = restored.get(&output.name) {
+                settings.mode = status.mode.clone();
+                if session.available.iter().any(|b| b.name() == status.backend) {
+                    settings.backend = Some(status.backend.clone());
+                }
+            }
+            let pool = match scanned.get(&settings.media_paths) {
+                Some(pool) => pool.clone(),
+                None => {
+                    let pool = scan(&session.config.scan, &settings)?;
+                    scanned.insert(settings.media_paths.clone(), pool.clone());
+                    pool
+                }
+            };
+            session.media.per_output.insert(output.name.clone(), pool);
+
+            match groups.iter_mut().find(|(s, _)| *s == settings) {
+                Some((_, outputs)) => outputs.push(output),
+                None => groups.push((settings, vec![output])),
+            }
+        }
+        session.media.default = scanned.remove(&session.config.media_paths).unwrap_or_default();
+
+        // A span cuts one image for every output showing media.
+        let span_outputs: Vec<OutputInfo> = groups.iter()
+            .filter(|(settings, _)| !matches!(settings.mode, WallpaperMode::SolidColor(_)))
+            .flat_map(|(_, outputs)| outputs.clone())
+            .collect();
+        let pieces = match &session.config.layout {
+            Layout::Span(span) if !span_outputs.is_empty() => {
+                let source = match span_source.filter(|source| source.exists()) {
+                    Some(source) => source,
+                    None => choose_span_source(&session.media.default, None, &mut session.history, &mut session.rng)?,
+                };
+                // A dry run names the pieces without cutting them.
+                let pieces = match purpose {
+                    Purpose::DryRun => plan_span(&source, &span_outputs, span)?.into_iter()
+                        .map(|(rect, path)| (rect.output, path))
+                        .collect(),
+                    Purpose::Live | Purpose::Fixed => split_span(&source, &span_outputs, span)?,
+                };
+                session.span = Some(source);
+                Some(pieces)
+            }
+            _ => None,
+        };
+
+        // Restored outputs in different groups still avoid each other's media.
+        let mut taken: Vec<PathBuf> = groups.iter()
+            .flat_map(|(_, outputs)| outputs)
+            .filter_map(|output| restored.get(&output.name))
+            .map(|status| status.media.clone())
+            .filter(|media| media.exists())
+            .collect();
+
+        let mut plans = Vec::new();
+        for (settings, outputs) in groups {
+            let backend = session.backend_for(&settings)?;
+            let specs = match &pieces {
+                Some(pieces) if !matches!(settings.mode, WallpaperMode::SolidColor(_)) => {
+                    let media = outputs.iter().map(|o| pieces[&o.name].clone()).collect();
+                    create_spawn_specs(outputs, media, settings.mode)
+                }
+                _ if !restored.is_empty() => {
+                    let media = session.restored_media(&outputs, backend.as_ref(), &settings, &restored, &taken)?;
+                    taken.extend(media.iter().cloned());
+                    create_spawn_specs(outputs, media, settings.mode)
+                }
+                _ => {
+                    let pool = session.media.for_output(&outputs[0].name).to_vec();
+                    plan_outputs(outputs, &pool, backend.as_ref(), settings.mode, &session.config.assign, &mut session.history, &mut session.rng)?
+                }
+            };
+            plans.push((backend, specs));
+        }
+        Ok((session, plans))
+    }
+
+    fn backend_for(&self, settings: &OutputSettings) -> Result<Arc<dyn WallpaperBackend>> {
+        select_shared_backend(settings.backend.as_deref(), &self.available)
+    }
+
+    /// The configured rotation with the intervals of matching output rules.
+    fn rotation_config(&self) -> Option<RotationConfig> {
+        let mut rotation = self.config.rotation.clone();
+        for process in &self.processes {
+            for spec in &process.specs {
+                if let Some(interval) = self.config.interval_for(&spec.output) {
+                    rotation.get_or_insert_with(RotationConfig::default)
+                        .per_output.insert(spec.output.name.clone(), interval);
+                }
+            }
+        }
+        rotation
+    }
+
+    fn output_names(&self) -> Vec<String> {
+        self.processes.iter()
+            .flat_map(|p| p.specs.iter().map(|s| s.output.name.clone()))
+            .collect()
+    }
+
+    /// Outputs showing media, solid colour ones have nothing to span.
+    fn media_outputs(&self) -> Vec<OutputInfo> {
+        self.processes.iter()
+            .flat_map(|p| p.specs.iter())
+            .filter(|s| !matches!(s.mode, WallpaperMode::SolidColor(_)))
+            .map(|s| s.output.clone())
+            .collect()
+    }
+
+    /// Cut `source` for the media outputs and show the pieces.
+    async fn show_span(&mut self, source: PathBuf) -> Result<()> {
+        let Layout::Span(span) = &self.config.layout else {
+            return Ok(());
+        };
+        let pieces = split_span(&source, &self.media_outputs(), span)?;
+        self.span = Some(source);
+        set_media(&mut self.processes, &pieces, self.config.swap_delay).await
+    }
+
+    /// The saved media of `outputs` where it still exists, fresh picks for the others.
+    fn restored_media(
+        &mut self,
+        outputs: &[OutputInfo],
+        backend: &dyn WallpaperBackend,
+        settings: &OutputSettings,
+        restored: &HashMap<String, OutputStatus>,
+        taken: &[PathBuf],
+    ) -> Result<Vec<PathBuf>> {
+        if let WallpaperMode::SolidColor(_) = settings.mode {
+            return Ok(vec![PathBuf::new(); outputs.len()]);
+        }
+
+        let kept: Vec<Option<PathBuf>> = outputs.iter().map(|output| {
+            let media = restored.get(&output.name)?.media.clone();
+            if media.exists() {
+                self.history.record(&output.name, &media);
+                Some(media)
+            } else {
+                info!("{} is gone, picking new media for {}", media.display(), output.name);
+                None
+            }
+        }).collect();
+
+        // Fresh picks avoid the `taken` media with `Unique`, the kept ones among them.
+        let pool = supported_media(backend, self.media.for_output(&outputs[0].name));
+        let mut shown = taken.to_vec();
+        let mut media = Vec::new();
+        let mut missing = Vec::new();
+        for (output, kept) in outputs.iter().zip(kept) {
+            let picked = kept.or_else(|| {
+                self.config.assign.pick(output, None, &pool, &shown, &mut self.history, &mut self.rng)
+                    .inspect(|m| shown.push(m.clone()))
+            });
+            match picked {
+                Some(m) => media.push(m),
+                None => missing.push(output.name.clone()),
+            }
+        }
+
+        if !missing.is_empty() {
+            return Err(Error::NotEnoughMedia { outputs: missing });
+        }
+        Ok(media)
+    }
+
+    /// Keep the history and what is shown now for the next run.
+    fn save_state(&self) {
+        if !self.persist {
+            return;
+        }
+        if let Err(e) = self.history.save() {
+            warn!("Not keeping the media history: {}", e);
+        }
+        if let Some(path) = LastSession::default_path() {
+            if let Err(e) = self.last_session().save(&path) {
+                warn!("Not keeping the last wallpapers: {}", e);
+            }
+        }
+    }
+
+    /// What `restore` needs to show the same media again.
+    pub fn last_session(&self) -> LastSession {
+        LastSession {
+            config: self.config.clone(),
+            outputs: self.status().outputs,
+            span: self.span.clone(),
+        }
+    }
+
+    pub fn config(&self) -> &SessionConfig {
+        &self.config
+    }
+
+    pub fn processes(&self) -> &[WallpaperProcess] {
+        &self.processes
+    }
+
+    /// Seed of the session's random picks, pass it back through `SessionConfig::seed` to repeat them.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Show new media, only on `outputs` if given.
+    pub async fn next(&mut self, outputs: Option<&[String]>) -> Result<()> {
+        if let Some(rotation) = self.rotation.as_mut() {
+            rotation.reset(outputs);
+        }
+        // The pieces of a span only change together.
+        let result = if let Layout::Span(_) = self.config.layout {
+            let source = choose_span_source(&self.media.default, self.span.as_ref(), &mut self.history, &mut self.rng)?;
+            self.show_span(source).await
+        } else {
+            next_wallpaper(
+                &mut self.processes, &self.media, outputs, &self.config.assign, &mut self.history, &mut self.rng,
+                self.config.swap_delay,
+            ).await
+        };
+        self.save_state();
+        result
+    }
+
+    /// Show what `last` showed on the running outputs, new media where it is gone.
+    /// Unlike `restore`, the backends are swapped without flicker.
+    pub async fn apply(&mut self, last: LastSession) -> Result<()> {
+        if let (Layout::Span(_), Some(source)) = (&self.config.layout, last.span.filter(|s| s.exists())) {
+            let result = self.show_span(source).await;
+            self.save_state();
+            return result;
+        }
+
+        let mut media = HashMap::new();
+        let mut gone = Vec::new();
+        for status in last.outputs {
+            if !self.serves(&status.output) || matches!(status.mode, WallpaperMode::SolidColor(_)) {
+                continue;
+            }
+            if status.media.exists() {
+                self.history.record(&status.output, &status.media);
+                media.insert(status.output, status.media);
+            } else {
+                info!("{} is gone, picking new media for {}", status.media.display(), status.output);
+                gone.push(status.output);
+            }
+        }
+
+        let mut result = set_media(&mut self.processes, &media, self.config.swap_delay).await;
+        if !gone.is_empty() {
+            result = result.and(next_wallpaper(
+                &mut self.processes, &self.media, Some(&gone), &self.config.assign, &mut self.history, &mut self.rng,
+                self.config.swap_delay,
+            ).await);
+        }
+        self.save_state();
+        result
+    }
+
+    /// Rescan the media and set up every output again.
+    pub async fn reload(self) -> Result<Self> {
+        let config = self.config.clone();
+        self.stop().await?;
+        Self::start(config).await
+    }
+
+    /// Wait for the next event. Never resolves if there is nothing to wait for.
+    pub async fn event(&mut self) -> SessionEvent {
+        let Self { output_events, rotation, processes, supervisor, .. } = self;
+
+        let output_event = async {
+            match output_events.as_mut() {
+                Some(rx) => rx.recv().await,
+                None => std::future::pending().await,
+            }
+        };
+        let rotate = async {
+            match rotation.as_mut() {
+                Some(r) => r.tick().await,
+                None => std::future::pending().await,
+            }
+        };
+
+        tokio::select! {
+            Some(event) = output_event => SessionEvent::Output(event),
+            due = rotate => SessionEvent::Rotate(due),
+            (outputs, status) = exited(processes) => SessionEvent::Exited { outputs, status },
+            outputs = supervisor.due() => SessionEvent::Restart(outputs),
+            else => std::future::pending().await,
+        }
+    }
+
+    pub async fn handle(&mut self, event: SessionEvent) -> Result<()> {
+        match event {
+            SessionEvent::Output(OutputEvent::Added(output)) => self.add_output(output).await,
+            SessionEvent::Output(OutputEvent::Removed(output)) => self.remove_output(&output).await,
+            SessionEvent::Rotate(due) => {
+                let outputs = match (due, self.rotation.as_ref()) {
+                    (Some(output), _) => vec![output],
+                    (None, Some(rotation)) => rotation.global_outputs(&self.output_names()),
+                    (None, None) => Vec::new(),
+                };
+                if outputs.is_empty() {
+                    return Ok(());
+                }
+                debug!("Rotating {:?}", outputs);
+                if let Layout::Span(_) = self.config.layout {
+                    return self.next(None).await;
+                }
+                let result = next_wallpaper(
+                    &mut self.processes, &self.media, Some(&outputs), &self.config.assign, &mut self.history, &mut self.rng,
+                    self.config.swap_delay,
+                ).await;
+                self.save_state();
+                result
+            }
+            SessionEvent::Exited { outputs, status } => {
+                self.crashed(&outputs, &status.to_string());
+                Ok(())
+            }
+            SessionEvent::Restart(outputs) => self.restart(&outputs),
+            SessionEvent::Output(OutputEvent::Changed(output)) => self.change_output(output).await,
+        }
+    }
+
+    /// Follow a new mode, scale, transform or position of `output`.
+    async fn change_output(&mut self, output: OutputInfo) -> Result<()> {
+        let Some(known) = self.processes.iter()
+            .flat_map(|p| p.specs.iter())
+            .find(|s| s.output.name == output.name)
+            .map(|s| s.output.clone())
+        else {
+            return Ok(());
+        };
+        debug!("Output {} changed", output.name);
+
+        let resized = known.pixel_size() != output.pixel_size()
+            || known.logical_size() != output.logical_size()
+            || known.scale() != output.scale()
+            || known.transform() != output.transform();
+        if !resized || self.span.is_some() {
+            for spec in self.processes.iter_mut().flat_map(|p| p.specs.iter_mut()) {
+                if spec.output.name == output.name {
+                    spec.output = output.clone();
+                }
+            }
+        }
+
+        // A new size or position moves every piece.
+        if let Some(source) = self.span.clone() {
+            return self.show_span(source).await;
+        }
+        if !resized {
+            return Ok(());
+        }
+
+        info!("Output {} changed size, showing its wallpaper again", output.name);
+        let outputs = HashMap::from([(output.name.clone(), output)]);
+        let result = set_outputs(&mut self.processes, &outputs, self.config.swap_delay).await;
+        self.save_state();
+        result
+    }
+
+    /// Schedule a restart of the process serving `outputs`, unless it crashed too often.
+    fn crashed(&mut self, outputs: &[String], reason: &str) {
+        let Some(process) = self.processes.iter().find(|p| p.outputs() == outputs) else {
+            return;
+        };
+        let backend = process.backend().name().to_string();
+        match self.supervisor.crashed(outputs) {
+            Some(delay) => warn!("{} for {} exited ({}), restarting in {:?}", backend, outputs.join(", "), reason, delay),
+            None => error!("{} for {} keeps exiting ({}), giving up", backend, outputs.join(", "), reason),
+        }
+    }
+
+    fn restart(&mut self, outputs: &[String]) -> Result<()> {
+        let Some(i) = self.processes.iter().position(|p| p.outputs() == outputs && p.child.is_none()) else {
+            return Ok(());
+        };
+        let process = self.processes.remove(i);
+        let (backend, specs) = (process.backend.clone(), process.specs.clone());
+        info!("Restarting {} for {}", backend.name(), outputs.join(", "));
+
+        match start_backend(backend.clone(), specs.clone()) {
+            Ok(started) => {
+                self.processes.extend(started);
+                Ok(())
+            }
+            Err(e) => {
+                self.processes.push(WallpaperProcess::new(backend, specs, None));
+                self.crashed(outputs, &e.to_string());
+                Err(e)
+            }
+        }
+    }
+
+    fn serves(&self, output: &str) -> bool {
+        self.processes.iter().any(|p| p.specs.iter().any(|s| s.output.name == output))
+    }
+
+    async fn add_output(&mut self, output: OutputInfo) -> Result<()> {
+        let targeted = self.config.outputs.as_ref().is_none_or(|names| names.contains(&output.name));
+        if !targeted || self.serves(&output.name) {
+            return Ok(());
+        }
+        info!("Output {} added", output.name);
+
+        let settings = self.config.settings_for(&output);
+        let backend = self.backend_for(&settings)?;
+        let pool = scan(&self.config.scan, &settings)?;
+
+        // With a span, the other outputs get new pieces once this one is up.
+        let mut pieces = None;
+        let media = if let WallpaperMode::SolidColor(_) = settings.mode {
+            PathBuf::new()
+        } else if let (Layout::Span(span), Some(source)) = (&self.config.layout, self.span.as_ref()) {
+            let mut outputs = self.media_outputs();
+            outputs.push(output.clone());
+            let split = split_span(source, &outputs, span)?;
+            let piece = split[&output.name].clone();
+            pieces = Some(split);
+            piece
+        } else {
+            let supported = supported_media(backend.as_ref(), &pool);
+            if supported.is_empty() {
+                return Err(Error::NoMediaFound { backend: backend.name().to_string() });
+            }
+            let shown: Vec<PathBuf> = self.processes.iter()
+                .flat_map(|p| p.specs.iter().map(|s| s.media.clone()))
+                .collect();
+            self.config.assign.pick(&output, None, &supported, &shown, &mut self.history, &mut self.rng)
+                .ok_or_else(|| Error::NotEnoughMedia { outputs: vec![output.name.clone()] })?
+        };
+        self.media.per_output.insert(output.name.clone(), pool);
+        self.save_state();
+
+        let interval = self.config.interval_for(&output);
+        match self.rotation.as_mut() {
+            Some(rotation) => rotation.add_output(&output.name, interval),
+            None => if let Some(interval) = interval {
+                let mut rotation = RotationConfig::default();
+                rotation.per_output.insert(output.name.clone(), interval);
+                self.rotation = Some(Rotation::new(rotation, std::slice::from_ref(&output.name)));
+            }
+        }
+
+        let spec = create_spawn_specs(vec![output], vec![media], settings.mode).remove(0);
+
+        // A running daemon style backend just gets another output.
+        let running = match backend.capabilities().contains(&BackendCapability::HotReload) {
+            true => self.processes.iter_mut().find(|p| Arc::ptr_eq(&p.backend, &backend)),
+            false => None,
+        };
+        match running {
+            Some(process) => {
+                backend.update(&spec)
+                    .map_err(|source| Error::UpdateFailed { backend: backend.name().to_string(), source })?;
+                process.specs.push(spec);
+            }
+            None => {
+                let started = start_backend(backend, vec![spec])?;
+                self.processes.extend(started);
+            }
+        }
+
+        match pieces {
+            Some(pieces) => set_media(&mut self.processes, &pieces, self.config.swap_delay).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn remove_output(&mut self, output: &OutputInfo) -> Result<()> {
+        if !self.serves(&output.name) {
+            return Ok(());
+        }
+        info!("Output {} removed", output.name);
+        if let Some(rotation) = self.rotation.as_mut() {
+            rotation.remove_output(&output.name);
+        }
+        self.media.per_output.remove(&output.name);
+        self.supervisor.forget(&output.name);
+
+        let mut result = Ok(());
+        for mut process in std::mem::take(&mut self.processes) {
+            process.specs.retain(|s| s.output.name != output.name);
+            if !process.specs.is_empty() {
+                self.processes.push(process);
+            } else {
+                let backend = process.backend().name().to_string();
+                if let Err(source) = process.stop_within(self.config.stop_grace).await {
+                    result = Err(Error::StopFailed { backend, source });
+                }
+            }
+        }
+
+        // The remaining outputs make a smaller canvas.
+        if let Some(source) = self.span.clone() {
+            self.show_span(source).await?;
+        }
+        result
+    }
+
+    pub fn status(&self) -> SessionStatus {
+        let outputs = self.processes.iter()
+            .flat_map(|p| p.specs().iter().map(|spec| OutputStatus {
+                output: spec.output.name.clone(),
+                backend: p.backend().name().to_string(),
+                mode: spec.mode.clone(),
+                media: spec.media.clone(),
+                pid: p.id(),
+                state: self.supervisor.state(&p.outputs()),
+            }))
+            .collect();
+
+        SessionStatus { outputs, seed: self.seed }
+    }
+
+    /// Stop every backend, all at once so the grace periods overlap.
+    /// Fails with the last error once all were tried.
+    pub async fn stop(self) -> Result<Vec<StopReport>> {
+        let grace = self.config.stop_grace;
+        let mut stopping = JoinSet::new();
+        for process in self.processes {
+            if let Some(pid) = process.id() {
+                info!("Stopping child process: {}", pid);
+            }
+            let backend = process.backend().name().to_string();
+            stopping.spawn(async move { (backend, process.stop_within(grace).await) });
+        }
+
+        let mut reports = Vec::new();
+        let mut result = Ok(());
+        while let Some(joined) = stopping.join_next().await {
+            match joined {
+                Ok((_, Ok(Some(report)))) => {
+                    info!("{}", report);
+                    reports.push(report);
+                }
+                Ok((_, Ok(None))) => {}
+                Ok((backend, Err(source))) => {
+                    error!("Failed stopping {}: {}", backend, source);
+                    result = Err(Error::StopFailed { backend, source });
+                }
+                Err(e) => error!("Stopping task failed: {}", e),
+            }
+        }
+        result.map(|()| reports)
+    }
+}
+
+fn scan(scan_config: &ScanConfig, settings: &OutputSettings) -> Result<Vec<PathBuf>> {
+    // A solid colour needs no media.
+    if let WallpaperMode::SolidColor(_) = settings.mode {
+        return Ok(Vec::new());
+    }
+    if settings.media_paths.is_empty() {
+        return Err(Error::NoMediaPath);
+    }
+
+    // The orchestrator keeps what the chosen backend supports.
+    let mut media = Vec::new();
+    for path in &settings.media_paths {
+        media.extend(scan_media(
+            Some(path.clone()),
+            MediaKind::Any,
+            false,
+            None,
+            &mut scan_config.clone(),
+        )?);
+    }
+    Ok(media)
+}
+
+/// A still image for a span, other than `current` when there is a choice.
+fn choose_span_source(
+    pool: &[PathBuf],
+    current: Option<&PathBuf>,
+    history: &mut History,
+    rng: &mut impl Rng,
+) -> Result<PathBuf> {
+    let images: Vec<&PathBuf> = pool.iter()
+        .filter(|path| detect_media_kind(path) == MediaKind::StaticImage)
+        .collect();
+    let candidates: Vec<&PathBuf> = images.iter().copied().filter(|path| Some(*path) != current).collect();
+    let source = history.bag(SPAN_HISTORY, &candidates).choose(rng).copied()
+        .or(candidates.choose(rng).copied())
+        .or(images.first().copied())
+        .cloned()
+        .ok_or_else(|| Error::NoMediaFound { backend: "span layout".to_string() })?;
+    history.record(SPAN_HISTORY, &source);
+    Ok(source)
+}
+
+fn custom_backends(config: &SessionConfig) -> Result<Vec<Box<dyn WallpaperBackend>>> {
+    let mut backends: Vec<Box<dyn WallpaperBackend>> = Vec::new();
+    for custom in &config.custom_backends {
+        let backend = custom.clone().into_backend()
+            .map_err(|source| Error::InvalidCommand { command: custom.command.clone(), source })?;
+        backends.push(Box::new(backend));
+    }
+    Ok(backends)
+}
+
+/// The history in the state directory for a live session, otherwise one that is never saved.
+/// A seed would not repeat the picks if they also depended on the history.
+fn open_history(config: &SessionConfig, purpose: Purpose) -> History {
+    match purpose == Purpose::Live && config.seed.is_none() {
+        true => History::open(config.assign.history),
+        false => History::new(config.assign.history),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use crate::backend::MockBackend;
+    use crate::layout::SpanConfig;
+
+    #[test]
+    fn media_paths_become_absolute() {
+        let mut config = SessionConfig::new(vec!["src".into()]);
+        config.rules.push(OutputRule {
+            output: "DP-1".to_string(),
+            media_paths: Some(vec!["src/../src/lib.rs".into()]),
+            mode: None,
+            backend: None,
+            interval: None,
+        });
+
+        config.canonicalize_media().unwrap();
+        let crate_dir = Path::new(env!("CARGO_MANIFEST_DIR")).canonicalize().unwrap();
+        assert_eq!(config.media_paths, [crate_dir.join("src")]);
+        assert_eq!(config.rules[0].media_paths, Some(vec![crate_dir.join("src/lib.rs")]));
+
+        let err = SessionConfig::new(vec!["no/such/dir".into()]).canonicalize_media().unwrap_err();
+        assert!(matches!(err, Error::ScanIo { .. }), "{}", err);
+    }
+
+    #[test]
+    fn seeded_session_keeps_history_in_memory() {
+        let mut config = SessionConfig::new(vec!["src".into()]);
+        config.seed = Some(7);
+        assert_eq!(open_history(&config, Purpose::Live).path(), None);
+        assert_eq!(open_history(&config, Purpose::Fixed).path(), None);
+
+        config.seed = None;
+        assert_eq!(open_history(&config, Purpose::Live).path(), History::default_path().as_deref());
+        assert_eq!(open_history(&config, Purpose::DryRun).path(), None);
+    }
+
+    #[test]
+    fn dry_run_does_not_cut_a_span() {
+        let dir = tempfile::tempdir().unwrap();
+        let media = dir.path().join("media");
+        std::fs::create_dir(&media).unwrap();
+        bg_test_support::write_image(&media, "wide.png", 64, 18);
+        let cache = dir.path().join("span");
+        std::fs::create_dir(&cache).unwrap();
+        std::fs::write(cache.join("stale-DP-1.png"), b"").unwrap();
+
+        let mut config = SessionConfig::new(vec![media]);
+        config.layout = Layout::Span(SpanConfig { bezel: 0, cache_dir: Some(cache.clone()) });
+        let outputs = vec![
+            OutputInfo::new("DP-1").with_mode(2560, 1440, 60000),
+            OutputInfo::new("HDMI-A-1").with_position(2560, 0).with_mode(1920, 1080, 60000),
+        ];
+        let backend = MockBackend::new("mock")
+            .with_capabilities(vec![BackendCapability::Static, BackendCapability::MultiOutput]);
+
+        let plans = Session::dry_run_on(config, outputs, vec![Box::new(backend)]).unwrap();
+
+        assert_eq!(plans.len(), 1);
+        assert_eq!(plans[0].outputs, ["DP-1", "HDMI-A-1"]);
+        let files: Vec<PathBuf> = std::fs::read_dir(&cache).unwrap().map(|e| e.unwrap().path()).collect();
+        assert_eq!(files, [cache.join("stale-DP-1.png")]);
+    }
+
+    #[test]
+    fn connector_rule_goes_first_and_others_fill_in() {
+        let rule = |output: &str, mode: Option<WallpaperMode>, backend: Option<&str>| OutputRule {
+            output: output.to_string(),
+            media_paths: None,
+            mode,
+            backend: backend.map(str::to_string),
+            interval: None,
+        };
+        let mut config = SessionConfig::new(vec!["media".into()]);
+        config.rules = vec![
+            rule("Acme Panel", Some(WallpaperMode::Fill), Some("swaybg")),
+            rule("HDMI-A-1", Some(WallpaperMode::Tile), None),
+            rule("DP-1", Some(WallpaperMode::Center), None),
+        ];
+        let output = OutputInfo::new("DP-1").with_make_model("Acme", "Panel");
+
+        let settings = config.settings_for(&output);
+
+        assert_eq!(settings.mode, WallpaperMode::Center);
+        assert_eq!(settings.backend.as_deref(), Some("swaybg"));
+        assert_eq!(settings.media_paths, [PathBuf::from("media")]);
+    }
+
+    #[tokio::test]
+    async fn restart_only_starts_the_exited_process() {
+        let dir = tempfile::tempdir().unwrap();
+        bg_test_support::write_image(dir.path(), "a.png", 16, 9);
+        bg_test_support::write_image(dir.path(), "b.png", 16, 9);
+        let outputs = vec![
+            OutputInfo::new("DP-1").with_mode(2560, 1440, 60000),
+            OutputInfo::new("HDMI-A-1").with_position(2560, 0).with_mode(1920, 1080, 60000),
+        ];
+        let mock = MockBackend::new("mock").with_command(&["sleep", "60"]);
+        let config = SessionConfig::new(vec![dir.path().to_path_buf()]);
+        let mut session = Session::start_on(config, outputs, vec![Box::new(mock.clone())]).await.unwrap();
+        let dp = vec!["DP-1".to_string()];
+
+        let crashed = session.processes.iter_mut().find(|p| p.outputs() == dp).unwrap();
+        let mut child = crashed.child.take().unwrap();
+        crate::backend::kill_and_reap(&mut child).await;
+
+        session.restart(&["HDMI-A-1".to_string()]).unwrap();
+        assert_eq!(mock.started().len(), 2);
+        session.restart(&dp).unwrap();
+        session.restart(&dp).unwrap();
+
+        let started: Vec<String> = mock.started().into_iter().map(|spec| spec.output.name).collect();
+        assert_eq!(started, ["DP-1", "HDMI-A-1", "DP-1"]);
+        assert!(session.processes.iter().all(|p| p.child.is_some()));
+        session.stop().await.unwrap();
+    }
+
+    fn shown(session: &Session) -> Vec<(String, PathBuf)> {
+        session.status().outputs.into_iter().map(|status| (status.output, status.media)).collect()
+    }
+
+    #[tokio::test]
+    async fn restored_session_shows_the_same_media() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["a.png", "b.png", "c.png", "d.png"] {
+            bg_test_support::write_image(dir.path(), name, 16, 9);
+        }
+        let outputs = vec![
+            OutputInfo::new("DP-1").with_mode(2560, 1440, 60000),
+            OutputInfo::new("HDMI-A-1").with_position(2560, 0).with_mode(1920, 1080, 60000),
+        ];
+        let config = SessionConfig::new(vec![dir.path().to_path_buf()]);
+        let session = Session::start_on(config, outputs.clone(), vec![Box::new(MockBackend::new("mock"))]).await.unwrap();
+        let state = dir.path().join("state").join("last.json");
+        session.last_session().save(&state).unwrap();
+
+        let last = LastSession::load(&state).unwrap().unwrap();
+        let restored = Session::restore_on(last, outputs, vec![Box::new(MockBackend::new("mock"))]).await.unwrap();
+
+        assert_eq!(shown(&restored), shown(&session));
+    }
+
+    #[tokio::test]
+    async fn restore_picks_anew_for_gone_media_and_new_outputs() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["a.png", "b.png", "c.png", "d.png"] {
+            bg_test_support::write_image(dir.path(), name, 16, 9);
+        }
+        let outputs = vec![
+            OutputInfo::new("DP-1").with_mode(2560, 1440, 60000),
+            OutputInfo::new("HDMI-A-1").with_position(2560, 0).with_mode(1920, 1080, 60000),
+        ];
+        let config = SessionConfig::new(vec![dir.path().to_path_buf()]);
+        let session = Session::start_on(config, outputs, vec![Box::new(MockBackend::new("mock"))]).await.unwrap();
+        let last = session.last_session();
+        let before: HashMap<String, PathBuf> = shown(&session).into_iter().collect();
+        std::fs::remove_file(&before["DP-1"]).unwrap();
+
+        // HDMI-A-1 was unplugged, eDP-1 is new.
+        let now = vec![
+            OutputInfo::new("DP-1").with_mode(2560, 1440, 60000),
+            OutputInfo::new("eDP-1").with_position(2560, 0).with_mode(1920, 1080, 60000),
+        ];
+        let restored = Session::restore_on(last, now, vec![Box::new(MockBackend::new("mock"))]).await.unwrap();
+
+        let after: HashMap<String, PathBuf> = shown(&restored).into_iter().collect();
+        let mut names: Vec<&String> = after.keys().collect();
+        names.sort();
+        assert_eq!(names, ["DP-1", "eDP-1"]);
+        assert!(after.values().all(|media| media.exists()));
+        assert_ne!(after["DP-1"], after["eDP-1"]);
+    }
+}
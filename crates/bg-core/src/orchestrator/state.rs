@@ -0,0 +1,106 @@
+use std::env;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use crate::orchestrator::{OutputStatus, SessionConfig};
+use crate::{Error, Result};
+
+/// `$XDG_STATE_HOME/bg-settings`, falling back to `~/.local/state`.
+pub fn state_dir() -> Option<PathBuf> {
+    let state_home = env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("state")))?;
+    Some(state_home.join("bg-settings"))
+}
+
+/// Write `value` as JSON through a temporary file, so readers never see half of it.
+pub(crate) fn write_state(path: &Path, value: &impl Serialize) -> Result<()> {
+    let state_io = |source| Error::StateIo { path: path.to_path_buf(), source };
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(state_io)?;
+    }
+    let content = serde_json::to_vec_pretty(value).map_err(|e| state_io(e.into()))?;
+    let partial = path.with_extension("partial");
+    std::fs::write(&partial, content).map_err(state_io)?;
+    std::fs::rename(&partial, path).map_err(state_io)
+}
+
+/// What a session showed last, kept in `$XDG_STATE_HOME/bg-settings/last.json` for `restore`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LastSession {
+    pub config: SessionConfig,
+    pub outputs: Vec<OutputStatus>,
+    /// The image cut across the outputs with a span layout.
+    #[serde(default)]
+    pub span: Option<PathBuf>,
+}
+
+impl LastSession {
+    pub fn default_path() -> Option<PathBuf> {
+        state_dir().map(|dir| dir.join("last.json"))
+    }
+
+    /// `None` if no session was saved yet.
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        let state_io = |source| Error::StateIo { path: path.to_path_buf(), source };
+        let content = match std::fs::read(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(state_io(e)),
+        };
+        serde_json::from_slice(&content).map(Some).map_err(|e| state_io(e.into()))
+    }
+
+    pub fn load_default() -> Result<Option<Self>> {
+        match Self::default_path() {
+            Some(path) => Self::load(&path),
+            None => Ok(None),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        write_state(path, self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_state_is_no_session() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(LastSession::load(&dir.path().join("last.json")).unwrap().is_none());
+    }
+
+    #[test]
+    fn corrupt_state_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("last.json");
+        std::fs::write(&path, b"{\"config\": ").unwrap();
+
+        let err = LastSession::load(&path).unwrap_err();
+
+        assert!(matches!(err, Error::StateIo { path: ref p, .. } if *p == path), "{}", err);
+    }
+
+    #[test]
+    fn state_is_replaced_whole() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nested").join("last.json");
+        let last = LastSession {
+            config: SessionConfig::new(vec!["media".into()]),
+            outputs: Vec::new(),
+            span: Some("media/wide.png".into()),
+        };
+
+        last.save(&path).unwrap();
+        last.save(&path).unwrap();
+
+        let loaded = LastSession::load(&path).unwrap().unwrap();
+        assert_eq!(loaded.config, last.config);
+        assert_eq!(loaded.span, last.span);
+        assert!(!path.with_extension("partial").exists());
+    }
+}
@@ -0,0 +1,258 @@
+use std::collections::HashMap;
+use std::process::ExitStatus;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+use crate::backend::WallpaperProcess;
+
+/// How often the children are checked for an exit.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How crashed backends are restarted.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SupervisorConfig {
+    /// Restarts within `window` before giving up, 0 never restarts.
+    #[serde(default = "default_max_restarts")]
+    pub max_restarts: u32,
+    #[serde(default = "default_window")]
+    pub window: Duration,
+    /// Delay before the first restart, doubled for every further crash within `window`.
+    #[serde(default = "default_backoff")]
+    pub backoff: Duration,
+    #[serde(default = "default_max_backoff")]
+    pub max_backoff: Duration,
+}
+
+fn default_max_restarts() -> u32 {
+    5
+}
+
+fn default_window() -> Duration {
+    Duration::from_secs(60)
+}
+
+fn default_backoff() -> Duration {
+    Duration::from_secs(1)
+}
+
+fn default_max_backoff() -> Duration {
+    Duration::from_secs(30)
+}
+
+impl Default for SupervisorConfig {
+    fn default() -> Self {
+        Self {
+            max_restarts: default_max_restarts(),
+            window: default_window(),
+            backoff: default_backoff(),
+            max_backoff: default_max_backoff(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProcessState {
+    #[default]
+    Running,
+    /// Crashed, waiting for its restart.
+    Restarting,
+    /// Crashed too often, left alone.
+    GaveUp,
+}
+
+#[derive(Debug, Default)]
+struct Crashes {
+    times: Vec<Instant>,
+    restart_at: Option<Instant>,
+    gave_up: bool,
+}
+
+/// Crash bookkeeping of the backend processes, keyed by the outputs they serve.
+#[derive(Debug, Default)]
+pub struct Supervisor {
+    config: SupervisorConfig,
+    crashes: HashMap<Vec<String>, Crashes>,
+}
+
+impl Supervisor {
+    pub fn new(config: SupervisorConfig) -> Self {
+        Self { config, crashes: HashMap::new() }
+    }
+
+    /// Record a crash of the process serving `outputs`.
+    /// Returns the delay before it is restarted, `None` once it crashed too often.
+    pub fn crashed(&mut self, outputs: &[String]) -> Option<Duration> {
+        let now = Instant::now();
+        let window = self.config.window;
+        let crashes = self.crashes.entry(outputs.to_vec()).or_default();
+        crashes.times.retain(|t| now.duration_since(*t) < window);
+        crashes.times.push(now);
+
+        let count = crashes.times.len() as u32;
+        if count > self.config.max_restarts {
+            crashes.gave_up = true;
+            crashes.restart_at = None;
+            return None;
+        }
+        let delay = self.config.backoff
+            .saturating_mul(2u32.saturating_pow(count - 1))
+            .min(self.config.max_backoff);
+        crashes.restart_at = Some(now + delay);
+        Some(delay)
+    }
+
+    pub fn state(&self, outputs: &[String]) -> ProcessState {
+        match self.crashes.get(outputs) {
+            Some(crashes) if crashes.gave_up => ProcessState::GaveUp,
+            Some(crashes) if crashes.restart_at.is_some() => ProcessState::Restarting,
+            _ => ProcessState::Running,
+        }
+    }
+
+    /// Drop the history of processes serving `output`.
+    pub fn forget(&mut self, output: &str) {
+        self.crashes.retain(|outputs, _| !outputs.iter().any(|o| o == output));
+    }
+
+    /// Wait until a restart is due. Returns the outputs of the process to restart.
+    pub async fn due(&mut self) -> Vec<String> {
+        let next = self.crashes.iter()
+            .filter_map(|(outputs, crashes)| crashes.restart_at.map(|at| (at, outputs.clone())))
+            .min();
+        let Some((at, outputs)) = next else {
+            return std::future::pending().await;
+        };
+
+        tokio::time::sleep_until(at).await;
+        if let Some(crashes) = self.crashes.get_mut(&outputs) {
+            crashes.restart_at = None;
+        }
+        outputs
+    }
+}
+
+/// Wait for a child to exit and reap it.
+/// Returns the outputs of its process and how it ended.
+pub async fn exited(processes: &mut [WallpaperProcess]) -> (Vec<String>, ExitStatus) {
+    if processes.iter().all(|p| p.child.is_none()) {
+        return std::future::pending().await;
+    }
+    loop {
+        for process in processes.iter_mut() {
+            let status = process.child.as_mut().and_then(|c| c.try_wait().ok().flatten());
+            if let Some(status) = status {
+                process.child = None;
+                return (process.outputs(), status);
+            }
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::process::ExitStatusExt;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use crate::backend::{BackendSpawnSpec, MockBackend, WallpaperMode};
+    use crate::wl::OutputInfo;
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    fn outputs() -> Vec<String> {
+        vec!["DP-1".to_string()]
+    }
+
+    fn supervisor(max_restarts: u32, window: Duration) -> Supervisor {
+        Supervisor::new(SupervisorConfig { max_restarts, window, ..SupervisorConfig::default() })
+    }
+
+    fn process(child: Option<tokio::process::Child>) -> WallpaperProcess {
+        let spec = BackendSpawnSpec {
+            media: PathBuf::from("a.png"),
+            mode: WallpaperMode::Fill,
+            output: OutputInfo::new("DP-1"),
+            extra_args: Vec::new(),
+        };
+        WallpaperProcess::new(Arc::new(MockBackend::new("mock")), vec![spec], child)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn backoff_doubles_up_to_the_cap() {
+        let mut supervisor = supervisor(10, Duration::from_secs(600));
+
+        let delays: Vec<Option<Duration>> = (0..7).map(|_| supervisor.crashed(&outputs())).collect();
+
+        let expected = [1, 2, 4, 8, 16, 30, 30].map(|s| Some(Duration::from_secs(s)));
+        assert_eq!(delays, expected);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn gives_up_after_max_restarts_within_the_window() {
+        let mut supervisor = supervisor(2, Duration::from_secs(60));
+
+        assert_eq!(supervisor.crashed(&outputs()), Some(SECOND));
+        tokio::time::advance(10 * SECOND).await;
+        assert_eq!(supervisor.crashed(&outputs()), Some(2 * SECOND));
+        assert_eq!(supervisor.state(&outputs()), ProcessState::Restarting);
+        tokio::time::advance(10 * SECOND).await;
+        assert_eq!(supervisor.crashed(&outputs()), None);
+
+        assert_eq!(supervisor.state(&outputs()), ProcessState::GaveUp);
+        assert_eq!(supervisor.state(&["HDMI-A-1".to_string()]), ProcessState::Running);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn crash_count_resets_once_the_window_passed() {
+        let mut supervisor = supervisor(2, Duration::from_secs(60));
+
+        assert_eq!(supervisor.crashed(&outputs()), Some(SECOND));
+        assert_eq!(supervisor.crashed(&outputs()), Some(2 * SECOND));
+        tokio::time::advance(Duration::from_secs(61)).await;
+
+        assert_eq!(supervisor.crashed(&outputs()), Some(SECOND));
+        assert_eq!(supervisor.state(&outputs()), ProcessState::Restarting);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn restart_is_due_after_the_backoff() {
+        let mut supervisor = supervisor(5, Duration::from_secs(60));
+        let start = Instant::now();
+
+        supervisor.crashed(&outputs());
+        supervisor.crashed(&outputs());
+
+        assert_eq!(supervisor.due().await, outputs());
+        assert_eq!(start.elapsed(), 2 * SECOND);
+        assert_eq!(supervisor.state(&outputs()), ProcessState::Running);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn exited_polls_the_children_every_second() {
+        let child = tokio::process::Command::new("sleep").arg("60").kill_on_drop(true).spawn().unwrap();
+        let mut processes = vec![process(Some(child))];
+
+        let running = tokio::time::timeout(Duration::from_millis(2500), exited(&mut processes)).await;
+        assert!(running.is_err());
+
+        processes[0].child.as_mut().unwrap().start_kill().unwrap();
+        let start = Instant::now();
+        let (outputs, status) = exited(&mut processes).await;
+
+        assert_eq!(outputs, ["DP-1"]);
+        assert_eq!(status.signal(), Some(9));
+        assert!(processes[0].child.is_none());
+        assert_eq!(start.elapsed().subsec_nanos(), 0, "not woken by the poll: {:?}", start.elapsed());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn exited_waits_forever_without_children() {
+        let mut processes = vec![process(None)];
+
+        let waited = tokio::time::timeout(Duration::from_secs(3600), exited(&mut processes)).await;
+
+        assert!(waited.is_err());
+    }
+}
@@ -1,22 +1,30 @@
 use std::fmt::Display;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use tokio::sync::mpsc::UnboundedSender;
 use wayland_client::{
     backend::ObjectId, protocol::{wl_output::{self},
                                   wl_registry::{self},
                                   wl_seat::{self}}, Connection, Dispatch, Proxy, QueueHandle, WEnum
 };
+use wayland_protocols::xdg::xdg_output::zv1::client::{zxdg_output_manager_v1, zxdg_output_v1};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use wayland_client::protocol::wl_output::Transform;
+use crate::wl::OutputEvent;
+use crate::Result;
 
 #[allow(dead_code)]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OutputMode {
-    width: i32,
-    height: i32,
-    refresh: i32,
-    flags: Vec<String>,
+    pub(crate) width: i32,
+    pub(crate) height: i32,
+    pub(crate) refresh: i32,
+    pub(crate) flags: Vec<String>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OutputInfo {
+    #[serde(rename = "id")]
     pub(crate) protocol_id: u32,
     pub name: String,
     pub(crate) description: String,
@@ -28,12 +36,38 @@ pub struct OutputInfo {
     pub(crate) make: String,
     pub(crate) model: String,
     pub(crate) subpixel_orientation: String,
-    pub(crate) output_transform: String,
+    #[serde(with = "transform_name")]
+    pub(crate) output_transform: Transform,
     pub(crate) modes: Vec<OutputMode>,
+    /// Top left corner in the compositor space, from xdg-output.
+    #[serde(default, rename = "logical_position")]
+    pub(crate) xdg_position: Option<(i32, i32)>,
+    /// Size in the compositor space, from xdg-output. Accounts for fractional scale and transform.
+    #[serde(default, rename = "logical_size")]
+    pub(crate) xdg_size: Option<(i32, i32)>,
 }
 
-#[derive(Debug, Clone)]
+/// A transform as its name, see `wl::transform_name`.
+mod transform_name {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+    use wayland_client::protocol::wl_output::Transform;
+
+    pub fn serialize<S: Serializer>(transform: &Transform, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(crate::wl::transform_name(*transform))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Transform, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        crate::wl::TRANSFORMS.iter()
+            .find(|t| crate::wl::transform_name(**t) == name)
+            .copied()
+            .ok_or_else(|| D::Error::custom(format!("unknown transform {}", name)))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SeatInfo {
+    #[serde(rename = "id")]
     protocol_id: u32,
     name: String,
     capabilities: Vec<String>,
@@ -49,39 +83,141 @@ impl Display for SeatInfo {
     }
 }
 
-struct State {
-    seat: HashMap<ObjectId, SeatInfo>,
-    outputs: HashMap<ObjectId, OutputInfo>,
+impl SeatInfo {
+    pub fn id(&self) -> u32 {
+        self.protocol_id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn capabilities(&self) -> &[String] {
+        &self.capabilities
+    }
+}
+
+pub(super) struct State {
+    pub(super) seat: HashMap<ObjectId, SeatInfo>,
+    pub(super) outputs: HashMap<ObjectId, OutputInfo>,
+    /// registry name → bound `wl_output`, to resolve `global_remove`.
+    output_globals: HashMap<u32, wl_output::WlOutput>,
+    /// Bound when the compositor offers xdg-output, for the logical geometry of each output.
+    xdg_output_manager: Option<zxdg_output_manager_v1::ZxdgOutputManagerV1>,
+    /// Outputs already reported through `events`.
+    announced: HashSet<ObjectId>,
+    /// Outputs too old to send their name, called after their registry name until xdg-output names them.
+    unnamed: HashSet<ObjectId>,
+    /// Set by the watcher, which wants to hear about every `done`.
+    pub(super) events: Option<UnboundedSender<OutputEvent>>,
 }
 
 impl State {
-    fn new() -> Self {
+    pub(super) fn new() -> Self {
         Self {
             seat: HashMap::new(),
             outputs: HashMap::new(),
+            output_globals: HashMap::new(),
+            xdg_output_manager: None,
+            announced: HashSet::new(),
+            unnamed: HashSet::new(),
+            events: None,
+        }
+    }
+
+    /// Ask for the logical geometry of `output`, once xdg-output is bound.
+    fn watch_xdg_output(&self, output: &wl_output::WlOutput, qh: &QueueHandle<Self>) {
+        if let Some(manager) = &self.xdg_output_manager {
+            manager.get_xdg_output(output, qh, output.id());
+        }
+    }
+
+    /// Tell the watcher about the output with `id`, once its name and, with xdg-output,
+    /// its logical geometry are known.
+    fn announce(&mut self, id: &ObjectId) {
+        let Some(info) = self.outputs.get(id) else {
+            return;
+        };
+        let complete = !info.name.is_empty() && (self.xdg_output_manager.is_none() || info.xdg_size.is_some());
+        if self.events.is_none() || !complete {
+            return;
+        }
+        let info = info.clone();
+        if self.announced.insert(id.clone()) {
+            self.emit(OutputEvent::Added(info));
+        } else {
+            self.emit(OutputEvent::Changed(info));
+        }
+    }
+
+    fn emit(&mut self, event: OutputEvent) {
+        if let Some(events) = &self.events {
+            if events.send(event).is_err() {
+                // Nobody listens anymore.
+                self.events = None;
+            }
         }
     }
 }
 
+/// The output with `id`, created on its first event.
+fn output_entry<'a>(outputs: &'a mut HashMap<ObjectId, OutputInfo>, id: &ObjectId) -> &'a mut OutputInfo {
+    outputs.entry(id.clone()).or_insert_with(|| OutputInfo {
+        protocol_id: id.protocol_id(),
+        ..OutputInfo::new("")
+    })
+}
 
 impl Dispatch<wl_registry::WlRegistry, ()> for State {
     fn event(
-        _: &mut Self,
+        state: &mut Self,
         registry: &wl_registry::WlRegistry,
         event: wl_registry::Event,
         _: &(),
         _: &Connection,
         qh: &QueueHandle<Self>,
     ) {
+        if let wl_registry::Event::GlobalRemove { name } = event {
+            if let Some(output) = state.output_globals.remove(&name) {
+                let id = output.id();
+                state.announced.remove(&id);
+                state.unnamed.remove(&id);
+                if let Some(info) = state.outputs.remove(&id) {
+                    state.emit(OutputEvent::Removed(info));
+                }
+            }
+            return;
+        }
+
         if let wl_registry::Event::Global { name, interface, version } = event {
             match interface.as_str() {
                 "wl_output" => {
-                    registry.bind::<wl_output::WlOutput, _, _>(
+                    let output = registry.bind::<wl_output::WlOutput, _, _>(
                         name,
                         version.min(4),
                         qh,
                         (),
                     );
+                    // The `name` event came with version 4.
+                    if output.version() < 4 {
+                        output_entry(&mut state.outputs, &output.id()).name = format!("wl_output-{}", name);
+                        state.unnamed.insert(output.id());
+                    }
+                    state.watch_xdg_output(&output, qh);
+                    state.output_globals.insert(name, output);
+                }
+                "zxdg_output_manager_v1" => {
+                    let manager = registry.bind::<zxdg_output_manager_v1::ZxdgOutputManagerV1, _, _>(
+                        name,
+                        version.min(3),
+                        qh,
+                        (),
+                    );
+                    state.xdg_output_manager = Some(manager);
+                    // Outputs announced before the manager.
+                    for output in state.output_globals.values() {
+                        state.watch_xdg_output(output, qh);
+                    }
                 }
                 "wl_seat" => {
                     registry.bind::<wl_seat::WlSeat, _, _>(
@@ -107,22 +243,7 @@ impl Dispatch<wl_output::WlOutput, ()> for State {
         _: &QueueHandle<Self>,
     ) {
         let id = proxy.id();
-
-        let info = state.outputs.entry(id.clone()).or_insert_with(|| OutputInfo {
-            protocol_id: id.protocol_id(),
-            name: String::new(),
-            description: String::new(),
-            x: 0,
-            y: 0,
-            scale: 1,
-            physical_width: 0,
-            physical_height: 0,
-            make: String::new(),
-            model: String::new(),
-            subpixel_orientation: String::new(),
-            output_transform: String::new(),
-            modes: Vec::new(),
-        });
+        let info = output_entry(&mut state.outputs, &id);
 
         match event {
             wl_output::Event::Name { name } => {
@@ -148,7 +269,13 @@ impl Dispatch<wl_output::WlOutput, ()> for State {
                 info.make = make;
                 info.model = model;
                 info.subpixel_orientation = format!("{:?}", subpixel);
-                info.output_transform = format!("{:?}", transform);
+                info.output_transform = match transform {
+                    WEnum::Value(transform) => transform,
+                    WEnum::Unknown(value) => {
+                        debug!("Output {} has unknown transform {}, taking it as normal", info.name, value);
+                        wl_output::Transform::Normal
+                    }
+                };
             }
             wl_output::Event::Scale { factor } => {
                 info.scale = factor;
@@ -162,10 +289,10 @@ impl Dispatch<wl_output::WlOutput, ()> for State {
                 let mut fs = Vec::new();
                 match flags {
                     WEnum::Value(mode) => {
-                        if mode == wl_output::Mode::Current {
+                        if mode.contains(wl_output::Mode::Current) {
                             fs.push("current".into());
                         }
-                        if mode == wl_output::Mode::Preferred {
+                        if mode.contains(wl_output::Mode::Preferred) {
                             fs.push("preferred".into());
                         }
                     }
@@ -173,14 +300,73 @@ impl Dispatch<wl_output::WlOutput, ()> for State {
                 }
 
 
-                info.modes.push(OutputMode {
+                // Modes are sent again when the current one changes.
+                if fs.iter().any(|f| f == "current") {
+                    for m in info.modes.iter_mut() {
+                        m.flags.retain(|f| f != "current");
+                    }
+                }
+                let mode = OutputMode {
                     width,
                     height,
                     refresh,
                     flags: fs,
-                });
+                };
+                match info.modes.iter_mut().find(|m| {
+                    (m.width, m.height, m.refresh) == (width, height, refresh)
+                }) {
+                    Some(existing) => {
+                        for f in mode.flags {
+                            if !existing.flags.contains(&f) {
+                                existing.flags.push(f);
+                            }
+                        }
+                    }
+                    None => info.modes.push(mode),
+                }
             }
-            wl_output::Event::Done => {}
+            wl_output::Event::Done => state.announce(&id),
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<zxdg_output_manager_v1::ZxdgOutputManagerV1, ()> for State {
+    fn event(
+        _: &mut Self,
+        _: &zxdg_output_manager_v1::ZxdgOutputManagerV1,
+        _: zxdg_output_manager_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        // The manager has no events.
+    }
+}
+
+/// Keyed by the `wl_output` the xdg-output describes.
+impl Dispatch<zxdg_output_v1::ZxdgOutputV1, ObjectId> for State {
+    fn event(
+        state: &mut Self,
+        proxy: &zxdg_output_v1::ZxdgOutputV1,
+        event: zxdg_output_v1::Event,
+        id: &ObjectId,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        let info = output_entry(&mut state.outputs, id);
+        match event {
+            zxdg_output_v1::Event::LogicalPosition { x, y } => {
+                info.xdg_position = Some((x, y));
+            }
+            zxdg_output_v1::Event::LogicalSize { width, height } => {
+                info.xdg_size = Some((width, height));
+            }
+            zxdg_output_v1::Event::Name { name } if state.unnamed.remove(id) => {
+                info.name = name;
+            }
+            // Since version 3 `wl_output.done` covers xdg-output changes too.
+            zxdg_output_v1::Event::Done if proxy.version() < 3 => state.announce(id),
             _ => {}
         }
     }
@@ -232,10 +418,13 @@ impl Dispatch<wl_seat::WlSeat, ()> for State {
     }
 }
 
-pub fn get_info() -> (Vec<OutputInfo>, Vec<SeatInfo>) {
-    let conn = Connection::connect_to_env()
-        .expect("Failed to connect to Wayland");
+pub fn get_info() -> Result<(Vec<OutputInfo>, Vec<SeatInfo>)> {
+    let conn = Connection::connect_to_env()?;
+    get_info_from(&conn)
+}
 
+/// Like `get_info`, on an existing connection.
+pub fn get_info_from(conn: &Connection) -> Result<(Vec<OutputInfo>, Vec<SeatInfo>)> {
     let mut event_queue = conn.new_event_queue();
     let qh = event_queue.handle();
 
@@ -245,12 +434,186 @@ pub fn get_info() -> (Vec<OutputInfo>, Vec<SeatInfo>) {
     display.get_registry(&qh, ());
 
     // registry → bind
-    event_queue.roundtrip(&mut state).unwrap();
+    event_queue.roundtrip(&mut state)?;
     // output / seat → 吐事件
-    event_queue.roundtrip(&mut state).unwrap();
+    event_queue.roundtrip(&mut state)?;
+
+    // Sorted, so the same outputs always come in the same order.
+    let mut outputs: Vec<OutputInfo> = state.outputs.into_values().collect();
+    outputs.sort_by(|a, b| a.name.cmp(&b.name));
+    let mut seats: Vec<SeatInfo> = state.seat.into_values().collect();
+    seats.sort_by_key(|s| s.protocol_id);
+
+    Ok((outputs, seats))
+}
+
+#[cfg(test)]
+mod tests {
+    use bg_test_support::{FakeCompositor, FakeMode, FakeOutput, FakeSeat};
+    use super::{OutputInfo, Transform};
+
+    #[test]
+    fn outputs_and_seats_come_through() {
+        let compositor = FakeCompositor::start(
+            vec![
+                FakeOutput::new("HDMI-A-1")
+                    .with_make_model("Acme", "Panel")
+                    .with_position(2560, 0)
+                    .with_transform(bg_test_support::Transform::_90)
+                    .with_mode(1920, 1080, 60000),
+                FakeOutput::new("DP-1")
+                    .with_description("Main screen")
+                    .with_scale(2)
+                    .with_extra_mode(FakeMode { width: 1920, height: 1080, refresh: 60000, current: false, preferred: true })
+                    .with_mode(2560, 1440, 144000),
+            ],
+            vec![FakeSeat::new("seat0")],
+        ).unwrap();
+
+        let (outputs, seats) = super::get_info_from(&compositor.connect().unwrap()).unwrap();
+
+        let names: Vec<&str> = outputs.iter().map(|o| o.name.as_str()).collect();
+        assert_eq!(names, ["DP-1", "HDMI-A-1"]);
+
+        let dp = &outputs[0];
+        assert_eq!(dp.description(), "Main screen");
+        assert_eq!(dp.scale(), 2);
+        assert!(!dp.is_rotated());
+        let modes: Vec<(i32, i32, i32, bool)> = dp.modes().iter()
+            .map(|m| (m.width(), m.height(), m.refresh(), m.is_current()))
+            .collect();
+        assert_eq!(modes, [(1920, 1080, 60000, false), (2560, 1440, 144000, true)]);
+        assert!(dp.modes()[0].flags.contains(&"preferred".to_string()));
+        assert_eq!(dp.pixel_size(), Some((2560, 1440)));
+        assert_eq!(dp.logical_size(), Some((1280, 720)));
 
-    (
-        state.outputs.into_values().collect(),
-        state.seat.into_values().collect(),
-    )
+        let hdmi = &outputs[1];
+        assert_eq!((hdmi.make(), hdmi.model()), ("Acme", "Panel"));
+        assert_eq!(hdmi.position(), (2560, 0));
+        assert_eq!(hdmi.scale(), 1);
+        assert_eq!(hdmi.transform(), Transform::_90);
+        assert!(hdmi.is_rotated());
+        assert_eq!(hdmi.pixel_size(), Some((1080, 1920)));
+
+        assert_eq!(seats.len(), 1);
+        assert_eq!(seats[0].name(), "seat0");
+        assert_eq!(seats[0].capabilities(), ["keyboard", "pointer"]);
+    }
+
+    #[test]
+    fn works_without_xdg_output() {
+        let compositor = FakeCompositor::start_without_xdg_output(
+            vec![FakeOutput::new("DP-1").with_scale(2).with_mode(3840, 2160, 60000)],
+            Vec::new(),
+        ).unwrap();
+
+        let (outputs, _) = super::get_info_from(&compositor.connect().unwrap()).unwrap();
+
+        assert_eq!(outputs.len(), 1);
+        assert_eq!(outputs[0].xdg_size, None);
+        assert_eq!(outputs[0].logical_size(), Some((1920, 1080)));
+        assert_eq!(outputs[0].fractional_scale(), 2.0);
+    }
+
+    #[test]
+    fn xdg_output_geometry_comes_through() {
+        let compositor = FakeCompositor::start(
+            vec![
+                FakeOutput::new("DP-1")
+                    .with_position(0, 0)
+                    .with_transform(bg_test_support::Transform::_90)
+                    .with_scale(2)
+                    .with_mode(2560, 1440, 60000)
+                    .with_logical_geometry(0, 0, 960, 1707),
+                FakeOutput::new("eDP-1")
+                    .with_position(960, 0)
+                    .with_scale(2)
+                    .with_mode(2880, 1800, 60000)
+                    .with_logical_geometry(960, 400, 1920, 1200),
+            ],
+            Vec::new(),
+        ).unwrap();
+
+        let (outputs, _) = super::get_info_from(&compositor.connect().unwrap()).unwrap();
+
+        let rotated = &outputs[0];
+        assert_eq!(rotated.transform(), Transform::_90);
+        assert_eq!(rotated.scale(), 2);
+        assert_eq!(rotated.pixel_size(), Some((1440, 2560)));
+        assert_eq!(rotated.logical_size(), Some((960, 1707)));
+        assert_eq!(rotated.fractional_scale(), 1.5);
+
+        let laptop = &outputs[1];
+        assert_eq!(laptop.position(), (960, 400));
+        assert_eq!((laptop.x, laptop.y), (960, 0));
+        assert_eq!(laptop.logical_size(), Some((1920, 1200)));
+        assert_eq!(laptop.fractional_scale(), 1.5);
+    }
+
+    #[test]
+    fn transform_is_stored_by_name() {
+        let output = OutputInfo::new("DP-1").with_transform(Transform::Flipped270);
+
+        let json = serde_json::to_value(&output).unwrap();
+        assert_eq!(json["output_transform"], "flipped-270");
+        let back: OutputInfo = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(back.transform(), Transform::Flipped270);
+    }
+
+    #[test]
+    fn output_serializes_to_a_stable_shape() {
+        let output = OutputInfo::new("DP-1")
+            .with_make_model("Acme", "Panel")
+            .with_position(1920, 0)
+            .with_scale(2)
+            .with_mode(3840, 2160, 60000);
+
+        let json = serde_json::to_value(&output).unwrap();
+
+        let mut keys: Vec<&str> = json.as_object().unwrap().keys().map(String::as_str).collect();
+        keys.sort();
+        assert_eq!(keys, [
+            "description", "id", "logical_position", "logical_size", "make", "model", "modes", "name",
+            "output_transform", "physical_height", "physical_width", "scale", "subpixel_orientation", "x", "y",
+        ]);
+        assert_eq!(json["name"], "DP-1");
+        assert_eq!((json["x"].as_i64(), json["y"].as_i64(), json["scale"].as_i64()), (Some(1920), Some(0), Some(2)));
+        assert_eq!(json["output_transform"], "normal");
+        assert_eq!(json["modes"], serde_json::json!([{ "width": 3840, "height": 2160, "refresh": 60000, "flags": ["current"] }]));
+        assert!(json["logical_size"].is_null());
+    }
+
+    #[test]
+    fn old_outputs_are_named_by_xdg_output() {
+        let compositor = FakeCompositor::start_without_output_names(
+            vec![FakeOutput::new("DP-1").with_mode(2560, 1440, 60000)],
+            Vec::new(),
+            true,
+        ).unwrap();
+
+        let (outputs, _) = super::get_info_from(&compositor.connect().unwrap()).unwrap();
+
+        assert_eq!(outputs.len(), 1);
+        assert_eq!(outputs[0].name, "DP-1");
+        assert_eq!(outputs[0].logical_size(), Some((2560, 1440)));
+    }
+
+    #[test]
+    fn old_outputs_without_xdg_output_get_a_generated_name() {
+        let compositor = FakeCompositor::start_without_output_names(
+            vec![
+                FakeOutput::new("DP-1").with_mode(2560, 1440, 60000),
+                FakeOutput::new("HDMI-A-1").with_position(2560, 0).with_mode(1920, 1080, 60000),
+            ],
+            Vec::new(),
+            false,
+        ).unwrap();
+
+        let (outputs, _) = super::get_info_from(&compositor.connect().unwrap()).unwrap();
+
+        let names: Vec<&str> = outputs.iter().map(|o| o.name.as_str()).collect();
+        assert_eq!(names.len(), 2);
+        assert!(names.iter().all(|name| name.starts_with("wl_output-")), "{:?}", names);
+        assert_ne!(names[0], names[1]);
+    }
 }
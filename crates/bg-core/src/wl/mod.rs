@@ -1,5 +1,9 @@
 mod lib;
 mod output;
+mod watch;
 
-pub use output::get_output_by_name;
-pub use lib::{OutputInfo, OutputMode, SeatInfo, get_info};
+pub use output::{get_output_by_name, transform_name};
+pub(crate) use output::TRANSFORMS;
+pub use wayland_client::protocol::wl_output::Transform;
+pub use lib::{OutputInfo, OutputMode, SeatInfo, get_info, get_info_from};
+pub use watch::{OutputEvent, watch_outputs, watch_outputs_on};
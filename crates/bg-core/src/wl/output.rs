@@ -1,6 +1,7 @@
 use std::fmt::Display;
 use log::info;
-use crate::wl::OutputInfo;
+use wayland_client::protocol::wl_output::Transform;
+use crate::wl::{OutputInfo, OutputMode};
 
 #[allow(dead_code)]
 fn select_outputs(
@@ -20,17 +21,48 @@ fn select_outputs(
     }
 }
 
+/// Every transform `transform_name` knows.
+pub(crate) const TRANSFORMS: [Transform; 8] = [
+    Transform::Normal,
+    Transform::_90,
+    Transform::_180,
+    Transform::_270,
+    Transform::Flipped,
+    Transform::Flipped90,
+    Transform::Flipped180,
+    Transform::Flipped270,
+];
+
+/// How a transform is shown and stored, e.g. `90` or `flipped-270`.
+pub fn transform_name(transform: Transform) -> &'static str {
+    match transform {
+        Transform::Normal => "normal",
+        Transform::_90 => "90",
+        Transform::_180 => "180",
+        Transform::_270 => "270",
+        Transform::Flipped => "flipped",
+        Transform::Flipped90 => "flipped-90",
+        Transform::Flipped180 => "flipped-180",
+        Transform::Flipped270 => "flipped-270",
+        // The enum is non-exhaustive, newer protocol versions may add transforms.
+        _ => "unknown",
+    }
+}
+
 impl Display for OutputInfo {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         writeln!(f, "id: {}", self.protocol_id)?;
         writeln!(f, "name: {}", self.name)?;
         writeln!(f, "description: {}", self.description)?;
         writeln!(f, "x: {}, y: {}, scale: {}", self.x, self.y, self.scale)?;
+        if let (Some((x, y)), Some((width, height))) = (self.xdg_position, self.xdg_size) {
+            writeln!(f, "logical: {}x{} at {},{}, scale: {}", width, height, x, y, self.fractional_scale())?;
+        }
         writeln!(f, "physical_width: {}, physical_height: {}", self.physical_width, self.physical_height)?;
         writeln!(f, "make: {}", self.make)?;
         writeln!(f, "model: {}", self.model)?;
         writeln!(f, "subpixel_orientation: {}", self.subpixel_orientation)?;
-        writeln!(f, "output_transform: {}", self.output_transform)?;
+        writeln!(f, "output_transform: {}", transform_name(self.output_transform))?;
         writeln!(f, "modes: {:?}", self.modes)?;
 
         Ok(())
@@ -39,6 +71,178 @@ impl Display for OutputInfo {
 
 
 
-pub fn get_output_by_name(name: &str) -> Option<OutputInfo> {
-    crate::wl::get_info().0.into_iter().find(|o| {o.name == name})
+impl OutputInfo {
+    /// An output that was not announced by a compositor, e.g. to stand in for
+    /// `get_info()` when driving the orchestrator without one. Build it up with the `with_` methods.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            protocol_id: 0,
+            name: name.into(),
+            description: String::new(),
+            x: 0,
+            y: 0,
+            scale: 1,
+            physical_width: 0,
+            physical_height: 0,
+            make: String::new(),
+            model: String::new(),
+            subpixel_orientation: String::new(),
+            output_transform: Transform::Normal,
+            modes: Vec::new(),
+            xdg_position: None,
+            xdg_size: None,
+        }
+    }
+
+    pub fn with_description(mut self, description: impl Into<String>) -> Self {
+        self.description = description.into();
+        self
+    }
+
+    pub fn with_make_model(mut self, make: impl Into<String>, model: impl Into<String>) -> Self {
+        self.make = make.into();
+        self.model = model.into();
+        self
+    }
+
+    pub fn with_position(mut self, x: i32, y: i32) -> Self {
+        self.x = x;
+        self.y = y;
+        self
+    }
+
+    pub fn with_scale(mut self, scale: i32) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    pub fn with_transform(mut self, transform: Transform) -> Self {
+        self.output_transform = transform;
+        self
+    }
+
+    /// Position and size in the compositor space, as xdg-output reports them.
+    pub fn with_logical_geometry(mut self, x: i32, y: i32, width: i32, height: i32) -> Self {
+        self.xdg_position = Some((x, y));
+        self.xdg_size = Some((width, height));
+        self
+    }
+
+    /// Add a mode and make it the current one. `refresh` is in mHz.
+    pub fn with_mode(mut self, width: i32, height: i32, refresh: i32) -> Self {
+        for m in self.modes.iter_mut() {
+            m.flags.retain(|f| f != "current");
+        }
+        self.modes.push(OutputMode {
+            width,
+            height,
+            refresh,
+            flags: vec!["current".into()],
+        });
+        self
+    }
+
+    /// Whether `key` names this output, by connector name, `make model` or description.
+    pub fn matches(&self, key: &str) -> bool {
+        key == self.name
+            || key == format!("{} {}", self.make, self.model)
+            || key == self.description
+    }
+
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    pub fn make(&self) -> &str {
+        &self.make
+    }
+
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    /// Top left corner in the compositor space, from xdg-output when the compositor offers it.
+    pub fn position(&self) -> (i32, i32) {
+        self.xdg_position.unwrap_or((self.x, self.y))
+    }
+
+    /// The integer scale of `wl_output`, see `fractional_scale` for the one in effect.
+    pub fn scale(&self) -> i32 {
+        self.scale
+    }
+
+    /// Pixels per logical pixel, e.g. 1.5. Derived from the xdg-output size,
+    /// falling back to the integer scale.
+    pub fn fractional_scale(&self) -> f64 {
+        match (self.pixel_size(), self.xdg_size) {
+            (Some((width, _)), Some((logical_width, _))) if logical_width > 0 => width as f64 / logical_width as f64,
+            _ => self.scale.max(1) as f64,
+        }
+    }
+
+    pub fn transform(&self) -> Transform {
+        self.output_transform
+    }
+
+    pub fn modes(&self) -> &[OutputMode] {
+        &self.modes
+    }
+
+    /// Whether the transform turns the panel by 90 or 270 degrees.
+    pub fn is_rotated(&self) -> bool {
+        matches!(
+            self.output_transform,
+            Transform::_90 | Transform::_270 | Transform::Flipped90 | Transform::Flipped270
+        )
+    }
+
+    /// Size in the compositor space. Reported by xdg-output, otherwise
+    /// the current mode turned and divided by the integer scale.
+    pub fn logical_size(&self) -> Option<(i32, i32)> {
+        if let Some(size) = self.xdg_size {
+            return Some(size);
+        }
+        let (width, height) = self.pixel_size()?;
+        let scale = self.scale.max(1);
+        Some((width / scale, height / scale))
+    }
+
+    /// Size in pixels as shown on the desktop: the current mode, turned by the transform.
+    pub fn pixel_size(&self) -> Option<(i32, i32)> {
+        let mode = self.current_mode()?;
+        match self.is_rotated() {
+            true => Some((mode.height, mode.width)),
+            false => Some((mode.width, mode.height)),
+        }
+    }
+
+    /// The mode flagged `current`, falling back to the first advertised one.
+    pub fn current_mode(&self) -> Option<&OutputMode> {
+        self.modes.iter()
+            .find(|m| m.flags.iter().any(|f| f == "current"))
+            .or(self.modes.first())
+    }
+}
+
+impl OutputMode {
+    pub fn width(&self) -> i32 {
+        self.width
+    }
+
+    pub fn height(&self) -> i32 {
+        self.height
+    }
+
+    /// In mHz.
+    pub fn refresh(&self) -> i32 {
+        self.refresh
+    }
+
+    pub fn is_current(&self) -> bool {
+        self.flags.iter().any(|f| f == "current")
+    }
+}
+
+pub fn get_output_by_name(name: &str) -> crate::Result<Option<OutputInfo>> {
+    Ok(crate::wl::get_info()?.0.into_iter().find(|o| {o.name == name}))
 }
\ No newline at end of file
@@ -0,0 +1,186 @@
+use std::io::ErrorKind;
+use std::os::fd::{AsFd, AsRawFd};
+use std::thread::JoinHandle;
+use log::{debug, error, info};
+use tokio::io::unix::AsyncFd;
+use tokio::io::Interest;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use wayland_client::backend::WaylandError;
+use wayland_client::{Connection, DispatchError};
+use crate::wl::OutputInfo;
+use super::lib::State;
+use crate::{Error, Result};
+
+#[derive(Debug, Clone)]
+pub enum OutputEvent {
+    /// Sent for every output present when watching starts, and for each plugged in later.
+    Added(OutputInfo),
+    Removed(OutputInfo),
+    /// Mode, scale, position or another property of a known output changed.
+    Changed(OutputInfo),
+}
+
+/// Keep a Wayland connection open on a background thread and report output hotplug.
+/// The thread ends with the connection, or as soon as the receiver is dropped.
+pub fn watch_outputs() -> Result<UnboundedReceiver<OutputEvent>> {
+    let conn = Connection::connect_to_env()?;
+    watch_outputs_on(conn)
+}
+
+/// Like `watch_outputs`, on an existing connection.
+pub fn watch_outputs_on(conn: Connection) -> Result<UnboundedReceiver<OutputEvent>> {
+    spawn_watcher(conn).map(|(rx, _)| rx)
+}
+
+fn spawn_watcher(conn: Connection) -> Result<(UnboundedReceiver<OutputEvent>, JoinHandle<()>)> {
+    let (tx, rx) = unbounded_channel();
+
+    let thread = std::thread::Builder::new()
+        .name("wl-output-watch".into())
+        .spawn(move || {
+            let runtime = match tokio::runtime::Builder::new_current_thread().enable_io().build() {
+                Ok(runtime) => runtime,
+                Err(e) => {
+                    error!("Cannot watch outputs: {}", e);
+                    return;
+                }
+            };
+            match runtime.block_on(watch(conn, tx)) {
+                Ok(()) => debug!("Output watcher stopped."),
+                Err(e) => error!("Lost Wayland connection: {}", e),
+            }
+        })
+        .map_err(Error::Watcher)?;
+
+    Ok((rx, thread))
+}
+
+/// Dispatch output events into `tx` until nobody listens anymore.
+async fn watch(conn: Connection, tx: UnboundedSender<OutputEvent>) -> std::result::Result<(), DispatchError> {
+    let mut event_queue = conn.new_event_queue();
+    let qh = event_queue.handle();
+    let socket = AsyncFd::with_interest(conn.as_fd().as_raw_fd(), Interest::READABLE)
+        .map_err(WaylandError::Io)?;
+
+    let mut state = State::new();
+    state.events = Some(tx.clone());
+    conn.display().get_registry(&qh, ());
+
+    info!("Watching outputs.");
+    while state.events.is_some() {
+        event_queue.dispatch_pending(&mut state)?;
+        event_queue.flush()?;
+        let Some(guard) = event_queue.prepare_read() else {
+            continue;
+        };
+
+        tokio::select! {
+            _ = tx.closed() => break,
+            ready = socket.readable() => {
+                let mut ready = ready.map_err(WaylandError::Io)?;
+                match guard.read() {
+                    Ok(_) => {}
+                    Err(WaylandError::Io(e)) if e.kind() == ErrorKind::WouldBlock => ready.clear_ready(),
+                    Err(e) => return Err(e.into()),
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bg_test_support::{FakeCompositor, FakeOutput};
+    use tokio::sync::mpsc::UnboundedReceiver;
+
+    use super::{spawn_watcher, watch_outputs_on, OutputEvent};
+
+    async fn next(events: &mut UnboundedReceiver<OutputEvent>) -> OutputEvent {
+        tokio::time::timeout(Duration::from_secs(5), events.recv()).await
+            .expect("no output event within 5s")
+            .expect("watcher stopped")
+    }
+
+    #[tokio::test]
+    async fn hotplug_produces_events() {
+        let compositor = FakeCompositor::start(
+            vec![FakeOutput::new("DP-1").with_mode(2560, 1440, 60000)],
+            Vec::new(),
+        ).unwrap();
+        let mut events = watch_outputs_on(compositor.connect().unwrap()).unwrap();
+
+        match next(&mut events).await {
+            OutputEvent::Added(info) => {
+                assert_eq!(info.name, "DP-1");
+                assert_eq!(info.logical_size(), Some((2560, 1440)));
+            }
+            other => panic!("expected DP-1 to be added, got {:?}", other),
+        }
+
+        assert!(compositor.add_output(FakeOutput::new("HDMI-A-1").with_position(2560, 0).with_mode(1920, 1080, 60000)));
+        match next(&mut events).await {
+            OutputEvent::Added(info) => {
+                assert_eq!(info.name, "HDMI-A-1");
+                assert_eq!(info.position(), (2560, 0));
+            }
+            other => panic!("expected HDMI-A-1 to be added, got {:?}", other),
+        }
+
+        assert!(compositor.update_output(FakeOutput::new("DP-1").with_scale(2).with_mode(3840, 2160, 60000)));
+        match next(&mut events).await {
+            OutputEvent::Changed(info) => {
+                assert_eq!(info.name, "DP-1");
+                assert_eq!(info.scale(), 2);
+                assert_eq!(info.pixel_size(), Some((3840, 2160)));
+                assert_eq!(info.logical_size(), Some((1920, 1080)));
+            }
+            other => panic!("expected DP-1 to change, got {:?}", other),
+        }
+
+        assert!(compositor.remove_output("HDMI-A-1"));
+        match next(&mut events).await {
+            OutputEvent::Removed(info) => assert_eq!(info.name, "HDMI-A-1"),
+            other => panic!("expected HDMI-A-1 to be removed, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn unnamed_outputs_are_announced() {
+        let compositor = FakeCompositor::start_without_output_names(
+            vec![FakeOutput::new("DP-1").with_mode(2560, 1440, 60000)],
+            Vec::new(),
+            false,
+        ).unwrap();
+        let mut events = watch_outputs_on(compositor.connect().unwrap()).unwrap();
+
+        match next(&mut events).await {
+            OutputEvent::Added(info) => {
+                assert!(info.name.starts_with("wl_output-"), "{}", info.name);
+                assert_eq!(info.pixel_size(), Some((2560, 1440)));
+            }
+            other => panic!("expected an output to be added, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn watcher_stops_with_its_receiver() {
+        let compositor = FakeCompositor::start(
+            vec![FakeOutput::new("DP-1").with_mode(2560, 1440, 60000)],
+            Vec::new(),
+        ).unwrap();
+        let (mut events, thread) = spawn_watcher(compositor.connect().unwrap()).unwrap();
+        next(&mut events).await;
+
+        drop(events);
+
+        // The thread is gone without another output event to wake it.
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while !thread.is_finished() {
+            assert!(std::time::Instant::now() < deadline, "watcher thread still running");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+}
@@ -0,0 +1,394 @@
+use std::io::{Error, Result};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use log::{debug, error};
+use tempfile::TempDir;
+use wayland_server::backend::{ClientData, GlobalId};
+use wayland_server::protocol::wl_output::{self, WlOutput};
+use wayland_server::protocol::wl_seat::{self, WlSeat};
+use wayland_protocols::xdg::xdg_output::zv1::server::zxdg_output_manager_v1::{self, ZxdgOutputManagerV1};
+use wayland_protocols::xdg::xdg_output::zv1::server::zxdg_output_v1::{self, ZxdgOutputV1};
+use wayland_server::{Client, DataInit, Dispatch, Display, DisplayHandle, GlobalDispatch, ListeningSocket, New, Resource};
+
+use crate::{FakeOutput, FakeSeat};
+
+/// How often the server thread looks for new clients, their requests and output changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// A Wayland server on a background thread that announces the given outputs and seats,
+/// and their logical geometry through xdg-output, listening on a socket in a temporary directory.
+/// Dropping it stops the server.
+///
+/// Connect with `connect()` and hand the connection to `wl::get_info_from` or
+/// `wl::watch_outputs_on`, or point `WAYLAND_DISPLAY` at `socket_path()`.
+pub struct FakeCompositor {
+    socket: PathBuf,
+    changes: Option<Sender<OutputChange>>,
+    thread: Option<JoinHandle<()>>,
+    // Removed last, once the server is gone.
+    _dir: TempDir,
+}
+
+enum OutputChange {
+    Add(FakeOutput, Sender<bool>),
+    Update(FakeOutput, Sender<bool>),
+    Remove(String, Sender<bool>),
+}
+
+impl FakeCompositor {
+    pub fn start(outputs: Vec<FakeOutput>, seats: Vec<FakeSeat>) -> Result<Self> {
+        Self::launch(outputs, seats, true, 4)
+    }
+
+    /// Like `start`, for a compositor that does not offer xdg-output.
+    pub fn start_without_xdg_output(outputs: Vec<FakeOutput>, seats: Vec<FakeSeat>) -> Result<Self> {
+        Self::launch(outputs, seats, false, 4)
+    }
+
+    /// Like `start`, with `wl_output` version 3, which has no `name` event.
+    /// The names are only sent through xdg-output, if `xdg_output` is offered.
+    pub fn start_without_output_names(outputs: Vec<FakeOutput>, seats: Vec<FakeSeat>, xdg_output: bool) -> Result<Self> {
+        Self::launch(outputs, seats, xdg_output, 3)
+    }
+
+    fn launch(outputs: Vec<FakeOutput>, seats: Vec<FakeSeat>, xdg_output: bool, output_version: u32) -> Result<Self> {
+        let dir = tempfile::Builder::new().prefix("bg-fake-wayland").tempdir()?;
+        let socket = dir.path().join("wayland-0");
+        let listener = ListeningSocket::bind_absolute(socket.clone()).map_err(Error::other)?;
+        let display: Display<State> = Display::new().map_err(Error::other)?;
+
+        let mut state = State { output_version, ..State::default() };
+        let handle = display.handle();
+        for output in outputs {
+            state.add_output(&handle, output);
+        }
+        for seat in seats {
+            handle.create_global::<State, WlSeat, FakeSeat>(7, seat);
+        }
+        if xdg_output {
+            handle.create_global::<State, ZxdgOutputManagerV1, ()>(3, ());
+        }
+
+        let (changes, receiver) = channel();
+        let thread = std::thread::Builder::new()
+            .name("fake-compositor".into())
+            .spawn(move || serve(display, listener, state, receiver))?;
+
+        debug!("Fake compositor listening on {}", socket.display());
+        Ok(Self {
+            socket,
+            changes: Some(changes),
+            thread: Some(thread),
+            _dir: dir,
+        })
+    }
+
+    /// Absolute path of the socket, valid as `WAYLAND_DISPLAY`.
+    pub fn socket_path(&self) -> &Path {
+        &self.socket
+    }
+
+    /// A new client connection to this compositor.
+    pub fn connect(&self) -> Result<wayland_client::Connection> {
+        let stream = UnixStream::connect(&self.socket)?;
+        wayland_client::Connection::from_socket(stream).map_err(Error::other)
+    }
+
+    /// Plug in an output. Returns false if one with that name is already there.
+    pub fn add_output(&self, output: FakeOutput) -> bool {
+        self.change(|reply| OutputChange::Add(output, reply))
+    }
+
+    /// Send the properties of the output with the same name again, followed by `done`.
+    /// Returns false if there is no such output.
+    pub fn update_output(&self, output: FakeOutput) -> bool {
+        self.change(|reply| OutputChange::Update(output, reply))
+    }
+
+    /// Unplug the output called `name`. Returns false if there is no such output.
+    pub fn remove_output(&self, name: &str) -> bool {
+        self.change(|reply| OutputChange::Remove(name.to_string(), reply))
+    }
+
+    /// Wait until the server applied the change and flushed the resulting events.
+    fn change(&self, change: impl FnOnce(Sender<bool>) -> OutputChange) -> bool {
+        let (reply, answer) = channel();
+        let sent = self.changes.as_ref().is_some_and(|changes| changes.send(change(reply)).is_ok());
+        sent && answer.recv().unwrap_or(false)
+    }
+}
+
+impl Drop for FakeCompositor {
+    fn drop(&mut self) {
+        // The server stops once its change channel is closed.
+        self.changes.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn serve(mut display: Display<State>, listener: ListeningSocket, mut state: State, changes: Receiver<OutputChange>) {
+    loop {
+        let handle = display.handle();
+        match changes.try_recv() {
+            Ok(change) => {
+                let (done, reply) = state.apply(&handle, change);
+                let _ = display.flush_clients();
+                let _ = reply.send(done);
+            }
+            Err(TryRecvError::Empty) => {}
+            Err(TryRecvError::Disconnected) => return,
+        }
+
+        loop {
+            match listener.accept() {
+                Ok(Some(stream)) => {
+                    if let Err(e) = handle.clone().insert_client(stream, Arc::new(ClientState)) {
+                        error!("Fake compositor could not add a client: {}", e);
+                    }
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    error!("Fake compositor could not accept a client: {}", e);
+                    break;
+                }
+            }
+        }
+
+        if let Err(e) = display.dispatch_clients(&mut state) {
+            error!("Fake compositor failed to dispatch: {}", e);
+        }
+        let _ = display.flush_clients();
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+struct ClientState;
+
+impl ClientData for ClientState {}
+
+#[derive(Default)]
+struct State {
+    /// Version of the `wl_output` globals.
+    output_version: u32,
+    /// Every plugged in output with its global, in the order they were added.
+    outputs: Vec<(FakeOutput, GlobalId)>,
+    /// Bound `wl_output` resources by output name, to send updates to.
+    bound: Vec<(String, WlOutput)>,
+    /// xdg-output resources by output name.
+    xdg_bound: Vec<(String, ZxdgOutputV1)>,
+}
+
+impl State {
+    fn add_output(&mut self, handle: &DisplayHandle, output: FakeOutput) -> bool {
+        if self.outputs.iter().any(|(o, _)| o.name == output.name) {
+            return false;
+        }
+        let global = handle.create_global::<State, WlOutput, String>(self.output_version, output.name.clone());
+        self.outputs.push((output, global));
+        true
+    }
+
+    fn apply(&mut self, handle: &DisplayHandle, change: OutputChange) -> (bool, Sender<bool>) {
+        match change {
+            OutputChange::Add(output, reply) => (self.add_output(handle, output), reply),
+            OutputChange::Update(output, reply) => {
+                let Some((known, _)) = self.outputs.iter_mut().find(|(o, _)| o.name == output.name) else {
+                    return (false, reply);
+                };
+                *known = output;
+                self.bound.retain(|(_, resource)| resource.is_alive());
+                self.xdg_bound.retain(|(_, resource)| resource.is_alive());
+                // Version 3 clients take xdg-output changes with the next `wl_output.done`.
+                for (_, resource) in self.xdg_bound.iter().filter(|(name, _)| *name == known.name) {
+                    announce_logical(resource, known);
+                }
+                for (_, resource) in self.bound.iter().filter(|(name, _)| *name == known.name) {
+                    announce(resource, known);
+                }
+                (true, reply)
+            }
+            OutputChange::Remove(name, reply) => {
+                let Some(index) = self.outputs.iter().position(|(o, _)| o.name == name) else {
+                    return (false, reply);
+                };
+                let (_, global) = self.outputs.remove(index);
+                handle.remove_global::<State>(global);
+                self.bound.retain(|(bound, _)| *bound != name);
+                self.xdg_bound.retain(|(bound, _)| *bound != name);
+                (true, reply)
+            }
+        }
+    }
+}
+
+/// Send everything a client learns about `output` on bind.
+fn announce(resource: &WlOutput, output: &FakeOutput) {
+    resource.geometry(
+        output.x,
+        output.y,
+        output.physical_width,
+        output.physical_height,
+        output.subpixel,
+        output.make.clone(),
+        output.model.clone(),
+        output.transform,
+    );
+    for mode in &output.modes {
+        let mut flags = wl_output::Mode::empty();
+        if mode.current {
+            flags |= wl_output::Mode::Current;
+        }
+        if mode.preferred {
+            flags |= wl_output::Mode::Preferred;
+        }
+        resource.mode(flags, mode.width, mode.height, mode.refresh);
+    }
+    if resource.version() >= 2 {
+        resource.scale(output.scale);
+    }
+    if resource.version() >= 4 {
+        resource.name(output.name.clone());
+        resource.description(output.description.clone());
+    }
+    if resource.version() >= 2 {
+        resource.done();
+    }
+}
+
+/// Send the logical geometry of `output`.
+fn announce_logical(resource: &ZxdgOutputV1, output: &FakeOutput) {
+    let ((x, y), (width, height)) = output.logical_geometry();
+    resource.logical_position(x, y);
+    resource.logical_size(width, height);
+    if resource.version() >= 2 {
+        resource.name(output.name.clone());
+        resource.description(output.description.clone());
+    }
+    if resource.version() < 3 {
+        resource.done();
+    }
+}
+
+impl GlobalDispatch<WlOutput, String> for State {
+    fn bind(
+        state: &mut Self,
+        _: &DisplayHandle,
+        _: &Client,
+        resource: New<WlOutput>,
+        name: &String,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        let resource = data_init.init(resource, ());
+        if let Some((output, _)) = state.outputs.iter().find(|(o, _)| o.name == *name) {
+            announce(&resource, output);
+        }
+        state.bound.push((name.clone(), resource));
+    }
+}
+
+impl Dispatch<WlOutput, ()> for State {
+    fn request(
+        _: &mut Self,
+        _: &Client,
+        _: &WlOutput,
+        _: wl_output::Request,
+        _: &(),
+        _: &DisplayHandle,
+        _: &mut DataInit<'_, Self>,
+    ) {
+        // `release` is the only request, a destructor.
+    }
+}
+
+impl GlobalDispatch<ZxdgOutputManagerV1, ()> for State {
+    fn bind(
+        _: &mut Self,
+        _: &DisplayHandle,
+        _: &Client,
+        resource: New<ZxdgOutputManagerV1>,
+        _: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl Dispatch<ZxdgOutputManagerV1, ()> for State {
+    fn request(
+        state: &mut Self,
+        _: &Client,
+        _: &ZxdgOutputManagerV1,
+        request: zxdg_output_manager_v1::Request,
+        _: &(),
+        _: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        let zxdg_output_manager_v1::Request::GetXdgOutput { id, output } = request else {
+            return;
+        };
+        let name = state.bound.iter()
+            .find(|(_, resource)| *resource == output)
+            .map(|(name, _)| name.clone())
+            .unwrap_or_default();
+        let resource = data_init.init(id, ());
+        if let Some((known, _)) = state.outputs.iter().find(|(o, _)| o.name == name) {
+            announce_logical(&resource, known);
+            if resource.version() >= 3 && output.version() >= 2 {
+                output.done();
+            }
+        }
+        state.xdg_bound.push((name, resource));
+    }
+}
+
+impl Dispatch<ZxdgOutputV1, ()> for State {
+    fn request(
+        _: &mut Self,
+        _: &Client,
+        _: &ZxdgOutputV1,
+        _: zxdg_output_v1::Request,
+        _: &(),
+        _: &DisplayHandle,
+        _: &mut DataInit<'_, Self>,
+    ) {
+        // `destroy` is the only request, a destructor.
+    }
+}
+
+impl GlobalDispatch<WlSeat, FakeSeat> for State {
+    fn bind(
+        _: &mut Self,
+        _: &DisplayHandle,
+        _: &Client,
+        resource: New<WlSeat>,
+        seat: &FakeSeat,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        let resource = data_init.init(resource, ());
+        resource.capabilities(seat.capabilities);
+        if resource.version() >= 2 {
+            resource.name(seat.name.clone());
+        }
+    }
+}
+
+impl Dispatch<WlSeat, ()> for State {
+    fn request(
+        _: &mut Self,
+        _: &Client,
+        _: &WlSeat,
+        _: wl_seat::Request,
+        _: &(),
+        _: &DisplayHandle,
+        _: &mut DataInit<'_, Self>,
+    ) {
+        // Input devices are not faked, bg-core only reads the seat announcements.
+    }
+}
@@ -0,0 +1,11 @@
+mod compositor;
+mod media;
+mod output;
+mod script;
+
+pub use compositor::*;
+pub use media::*;
+pub use output::*;
+pub use script::*;
+pub use wayland_server::protocol::wl_output::{Subpixel, Transform};
+pub use wayland_server::protocol::wl_seat::Capability;
@@ -0,0 +1,9 @@
+use std::path::{Path, PathBuf};
+use image::RgbImage;
+
+/// Write a black `width`x`height` PNG named `name` into `dir`, e.g. to scan as a media library.
+pub fn write_image(dir: &Path, name: &str, width: u32, height: u32) -> PathBuf {
+    let path = dir.join(name);
+    RgbImage::new(width, height).save(&path).expect("Failed writing test image");
+    path
+}
@@ -0,0 +1,154 @@
+use wayland_server::protocol::wl_output::{Subpixel, Transform};
+use wayland_server::protocol::wl_seat::Capability;
+
+/// A mode advertised through `wl_output.mode`. `refresh` is in mHz.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FakeMode {
+    pub width: i32,
+    pub height: i32,
+    pub refresh: i32,
+    pub current: bool,
+    pub preferred: bool,
+}
+
+/// What a `wl_output` global of the fake compositor announces on bind.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FakeOutput {
+    pub name: String,
+    pub description: String,
+    pub x: i32,
+    pub y: i32,
+    /// In millimetres.
+    pub physical_width: i32,
+    pub physical_height: i32,
+    pub make: String,
+    pub model: String,
+    pub subpixel: Subpixel,
+    pub transform: Transform,
+    pub scale: i32,
+    pub modes: Vec<FakeMode>,
+    /// Sent through xdg-output, derived from position, mode, transform and scale when unset.
+    pub logical_position: Option<(i32, i32)>,
+    pub logical_size: Option<(i32, i32)>,
+}
+
+impl FakeOutput {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            description: String::new(),
+            x: 0,
+            y: 0,
+            physical_width: 0,
+            physical_height: 0,
+            make: String::new(),
+            model: String::new(),
+            subpixel: Subpixel::Unknown,
+            transform: Transform::Normal,
+            scale: 1,
+            modes: Vec::new(),
+            logical_position: None,
+            logical_size: None,
+        }
+    }
+
+    pub fn with_description(mut self, description: impl Into<String>) -> Self {
+        self.description = description.into();
+        self
+    }
+
+    pub fn with_make_model(mut self, make: impl Into<String>, model: impl Into<String>) -> Self {
+        self.make = make.into();
+        self.model = model.into();
+        self
+    }
+
+    pub fn with_position(mut self, x: i32, y: i32) -> Self {
+        self.x = x;
+        self.y = y;
+        self
+    }
+
+    pub fn with_physical_size(mut self, width: i32, height: i32) -> Self {
+        self.physical_width = width;
+        self.physical_height = height;
+        self
+    }
+
+    pub fn with_subpixel(mut self, subpixel: Subpixel) -> Self {
+        self.subpixel = subpixel;
+        self
+    }
+
+    pub fn with_transform(mut self, transform: Transform) -> Self {
+        self.transform = transform;
+        self
+    }
+
+    pub fn with_scale(mut self, scale: i32) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    /// Position and size in the compositor space, e.g. 1280x720 for 1920x1080 at scale 1.5.
+    pub fn with_logical_geometry(mut self, x: i32, y: i32, width: i32, height: i32) -> Self {
+        self.logical_position = Some((x, y));
+        self.logical_size = Some((width, height));
+        self
+    }
+
+    /// What xdg-output reports: the given logical geometry, or the one an
+    /// integer-scaled compositor would compute.
+    pub fn logical_geometry(&self) -> ((i32, i32), (i32, i32)) {
+        let position = self.logical_position.unwrap_or((self.x, self.y));
+        let size = self.logical_size.unwrap_or_else(|| {
+            let (width, height) = self.modes.iter()
+                .find(|mode| mode.current)
+                .map(|mode| (mode.width, mode.height))
+                .unwrap_or_default();
+            let (width, height) = match self.transform {
+                Transform::_90 | Transform::_270 | Transform::Flipped90 | Transform::Flipped270 => (height, width),
+                _ => (width, height),
+            };
+            let scale = self.scale.max(1);
+            (width / scale, height / scale)
+        });
+        (position, size)
+    }
+
+    /// Add a mode and make it the current one.
+    pub fn with_mode(mut self, width: i32, height: i32, refresh: i32) -> Self {
+        for mode in self.modes.iter_mut() {
+            mode.current = false;
+        }
+        self.modes.push(FakeMode { width, height, refresh, current: true, preferred: false });
+        self
+    }
+
+    /// Add a mode that is neither current nor preferred.
+    pub fn with_extra_mode(mut self, mode: FakeMode) -> Self {
+        self.modes.push(mode);
+        self
+    }
+}
+
+/// What a `wl_seat` global of the fake compositor announces on bind.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FakeSeat {
+    pub name: String,
+    pub capabilities: Capability,
+}
+
+impl FakeSeat {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            capabilities: Capability::Pointer | Capability::Keyboard,
+        }
+    }
+
+    pub fn with_capabilities(mut self, capabilities: Capability) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+}
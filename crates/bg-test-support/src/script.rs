@@ -0,0 +1,18 @@
+use std::fs;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+/// Write an executable `sh` script named `name` into `dir`, to stand in for a
+/// wallpaper program. `body` runs after the shebang, e.g. `echo $$ > pid; exec sleep 60`.
+pub fn write_script(dir: &Path, name: &str, body: &str) -> io::Result<PathBuf> {
+    let path = dir.join(name);
+    fs::write(&path, format!("#!/bin/sh\n{}\n", body))?;
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755))?;
+    Ok(path)
+}
+
+/// Whether `pid` still exists, a zombie that was never reaped counts.
+pub fn process_exists(pid: u32) -> bool {
+    Path::new("/proc").join(pid.to_string()).exists()
+}